@@ -0,0 +1,94 @@
+/// Decoding and scaling libraries whose versions are recorded alongside results, as upgrading them can change hashes,
+/// with the feature that builds them in, if they aren't always.
+const LIBRARIES: &[(&str, Option<&str>)] = &[
+	("image", None),
+	("zune-jpeg", Some("jpeg")),
+	("png", Some("png")),
+	("image-webp", Some("webp")),
+	("gif", Some("gif")),
+	("tiff", Some("tiff")),
+	("libheif-rs", Some("heic")),
+	("jxl-oxide", Some("jxl")),
+	("resvg", Some("svg")),
+	("dav1d", Some("avif")),
+];
+
+
+fn main() {
+	// The compiler and library versions this build records alongside results
+	println!("cargo:rerun-if-changed=Cargo.lock");
+	let rustc = std::process::Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+		.arg("--version")
+		.output()
+		.ok()
+		.and_then(|output| String::from_utf8(output.stdout).ok())
+		.unwrap_or_default();
+	println!("cargo:rustc-env=HASHER_RUSTC_VERSION={}", rustc.trim());
+	println!("cargo:rustc-env=HASHER_LIBRARY_VERSIONS={}", library_versions());
+
+	// Regenerate the C header of the bindings whenever they change
+	#[cfg(feature = "ffi")]
+	{
+		println!("cargo:rerun-if-changed=src/lib.rs");
+		println!("cargo:rerun-if-changed=src/ffi.rs");
+
+		let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+		let config = cbindgen::Config {
+			language: cbindgen::Language::C,
+			include_guard: Some("PHASH_H".to_string()),
+			usize_is_size_t: true,
+			// cbindgen also reads the CLI's modules that the bindings include, whose public constants aren't part of them
+			export: cbindgen::ExportConfig {
+				exclude: vec!["THUMBNAIL_HEAD".to_string()],
+				..Default::default()
+			},
+			autogen_warning: Some("/* Generated by cbindgen from src/lib.rs; don't edit. */".to_string()),
+			..Default::default()
+		};
+
+		cbindgen::Builder::new()
+			.with_config(config)
+			.with_src(format!("{}/src/lib.rs", dir))
+			.generate()
+			.expect("Error generating C header")
+			.write_to_file(format!("{}/include/phash.h", dir));
+	}
+
+	// libpHash's hash function can only be called from C++
+	#[cfg(feature = "conformance")]
+	{
+		println!("cargo:rerun-if-changed=src/conformance.cpp");
+
+		// Cargo passes link-lib directives only to the library target, so the binary links these through `#[link]`
+		cc::Build::new().cpp(true).cargo_metadata(false).file("src/conformance.cpp").compile("conformance");
+		println!("cargo:rustc-link-search=native={}", std::env::var("OUT_DIR").unwrap());
+	}
+}
+
+
+/// Whether the crate is being built with a feature.
+fn enabled(feature: &str) -> bool {
+	std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"))).is_some()
+}
+
+
+/// The versions in the lock file of the `LIBRARIES` this build's features use, e.g. `image 0.25.1, png 0.17.13`, or an
+/// empty string without a lock file.
+fn library_versions() -> String {
+	let lock = std::fs::read_to_string(format!("{}/Cargo.lock", std::env::var("CARGO_MANIFEST_DIR").unwrap())).unwrap_or_default();
+	let mut versions = Vec::new();
+	let mut lines = lock.lines();
+
+	while let Some(line) = lines.next() {
+		let name = line.strip_prefix("name = \"").and_then(|name| name.strip_suffix('"'));
+		let version = name.and_then(|_| lines.next()).and_then(|line| line.strip_prefix("version = \"")).and_then(|version| version.strip_suffix('"'));
+		if let (Some(name), Some(version)) = (name, version) {
+			let used = LIBRARIES.iter().find(|(library, _)| *library == name).map(|(_, feature)| feature.is_none_or(enabled));
+			if used == Some(true) {
+				versions.push(format!("{} {}", name, version));
+			}
+		}
+	}
+
+	versions.join(", ")
+}
@@ -0,0 +1,51 @@
+#ifndef PHASH_H
+#define PHASH_H
+
+/* Generated by cbindgen from src/lib.rs; don't edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * `phash_compute` succeeded.
+ */
+#define PHASH_OK 0
+
+/**
+ * `phash_compute` was given a null pointer.
+ */
+#define PHASH_ERROR_ARGUMENT 1
+
+/**
+ * The image couldn't be decoded, or is in a format this build doesn't support.
+ */
+#define PHASH_ERROR_DECODE 2
+
+/**
+ * Hashing failed unexpectedly.
+ */
+#define PHASH_ERROR_INTERNAL 3
+
+/**
+ * Compute the perceptual hash of an encoded image of `len` bytes at `buf`, storing it in `out_hash`.
+ *
+ * Returns `PHASH_OK`, or one of the `PHASH_ERROR_*` codes, leaving `out_hash` untouched.  Safe to call from several
+ * threads at once.
+ *
+ * # Safety
+ *
+ * `buf` must point to `len` readable bytes and `out_hash` to a writable `uint64_t`.
+ */
+int32_t phash_compute(const uint8_t *buf,
+                      size_t len,
+                      uint64_t *out_hash);
+
+/**
+ * The Hamming distance between two hashes: the number of bits in which they differ, from 0 to 64.
+ */
+uint32_t phash_distance(uint64_t a, uint64_t b);
+
+#endif  /* PHASH_H */
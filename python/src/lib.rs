@@ -0,0 +1,42 @@
+//! Python bindings to the hashing core, so that scripts can hash images without running the CLI and reading back its
+//! output file.  Images are hashed with the CLI's default parameters.
+
+
+/// Perceptual image hashes, as computed by the hasher CLI with its default parameters.
+#[pyo3::pymodule]
+mod phash_hasher {
+	use pyo3::{exceptions::PyValueError, prelude::*};
+	use rayon::prelude::*;
+	use std::path::PathBuf;
+
+	fn error(err: anyhow::Error) -> PyErr {
+		PyValueError::new_err(format!("{:#}", err))
+	}
+
+	/// The perceptual hash of an image file, as an unsigned 64-bit integer.  Raises ValueError if it can't be read or
+	/// decoded.
+	#[pyfunction]
+	fn hash_file(py: Python<'_>, path: PathBuf) -> PyResult<u64> {
+		py.detach(|| phash::hash_file(&path)).map_err(error)
+	}
+
+	/// The perceptual hash of an encoded image, as an unsigned 64-bit integer.  Raises ValueError if it can't be
+	/// decoded.
+	#[pyfunction]
+	fn hash_bytes(py: Python<'_>, data: Vec<u8>) -> PyResult<u64> {
+		py.detach(|| phash::hash_bytes(&data)).map_err(error)
+	}
+
+	/// The perceptual hashes of several image files, hashed in parallel without holding the GIL.  The hash of each
+	/// file that can't be read or decoded is None.
+	#[pyfunction]
+	fn hash_files(py: Python<'_>, paths: Vec<PathBuf>) -> Vec<Option<u64>> {
+		py.detach(|| paths.par_iter().map(|path| phash::hash_file(path).ok()).collect())
+	}
+
+	/// The Hamming distance between two hashes: the number of bits in which they differ, from 0 to 64.
+	#[pyfunction]
+	fn distance(a: u64, b: u64) -> u32 {
+		phash::distance(a, b)
+	}
+}
@@ -0,0 +1,487 @@
+//! Acts on clusters of duplicates found by `dedupe`: replacing all but one member of each with links to it, moving
+//! them aside, or deleting them.
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use std::{
+	collections::HashMap,
+	path::{Component, Path, PathBuf},
+	time::SystemTime,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::Entry,
+	compare::hashes,
+	dedupe::within_threshold,
+	hasher::Hasher,
+	settings::{Frames, InvariantStore, Settings},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ActionArgs {
+	/// What to do with the duplicates in each cluster, every member but the one kept.  Prints each step as
+	/// `action\tpath\ttarget` (without a target for `delete`) instead of listing pairs.  Only members whose hash is
+	/// within the threshold of the kept member's are acted on, and only in output files with one entry per input, not
+	/// those hashed with `--tiles`, `--video`, `--frames` other than `first` or `--invariant-store all`.  Files are
+	/// re-hashed first, and left alone if they no longer match the output file.
+	#[arg(long, value_enum, conflicts_with_all = ["clusters", "corroborate_exif", "group_by"])]
+	action: Option<Action>,
+
+	/// Directory duplicates are moved to by `--action move-to`, under their original paths, e.g. `/a/b.jpg` to
+	/// `DIR/a/b.jpg`.
+	#[arg(long, value_name = "DIR", required_if_eq("action", "move-to"))]
+	destination: Option<PathBuf>,
+
+	/// Which member of each cluster to keep.
+	#[arg(long, value_enum, default_value_t = Keep::Largest, requires = "action")]
+	keep: Keep,
+
+	/// Print the steps `--action` would take without taking them.
+	#[arg(long, requires = "action")]
+	dry_run: bool,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Action {
+	/// Replace duplicates with hard links to the member kept.  Members must be on the same filesystem.
+	Hardlink,
+	/// Replace duplicates with symbolic links to the member kept.
+	Symlink,
+	/// Move duplicates into `--destination`.
+	MoveTo,
+	/// Delete duplicates.
+	Delete,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Keep {
+	/// The largest file, which is usually the best quality copy.
+	Largest,
+	/// The file modified longest ago, usually the original.
+	Earliest,
+	/// The first by path.
+	First,
+}
+
+
+impl ActionArgs {
+	/// Whether to act on duplicates.
+	pub fn enabled(&self) -> bool {
+		self.action.is_some()
+	}
+}
+
+
+/// Counts of the steps taken, or that would be taken in a dry run.
+#[derive(Debug, Default)]
+pub struct Outcome {
+	pub done: usize,
+	pub failed: usize,
+	/// Members left alone because they aren't within the threshold of the member kept, or either changed since they
+	/// were hashed.
+	pub skipped: usize,
+}
+
+
+/// Check that the duplicates of an output file hashed with these settings can be acted on.  Inputs with several entries,
+/// one per tile, frame or transform, are paired by any one of them, which doesn't make them duplicates of each other:
+/// a tile of a collage, a frame of a video or a mirror image of an image.
+pub fn check(settings: &Settings) -> anyhow::Result<()> {
+	if settings.tiles.is_some() {
+		bail!("output files hashed with --tiles have an entry per tile");
+	}
+	if settings.video.is_some() || settings.frames != Frames::First {
+		bail!("output files hashed with --video or --frames have an entry per frame");
+	}
+	if settings.invariant.as_ref().is_some_and(|invariant| invariant.store == InvariantStore::All) {
+		bail!("output files hashed with --invariant-store all have an entry per transform");
+	}
+
+	Ok(())
+}
+
+
+/// Act on each cluster as asked, if at all, printing every step.  Clusters are linked through chains of pairs, so only
+/// the members whose single entry is within `threshold` of the kept member's are acted on.  Clusters whose member to
+/// keep can no longer be read are left alone, as are duplicates that turn out to be the kept file itself under another
+/// path, or already hard links to it.  Files are only acted on, or kept in place of others, if `unchanged` finds that
+/// they still hash to their cached entries, as the output file may be older than the files.
+pub fn run(
+	args: &ActionArgs,
+	clusters: &[Vec<PathBuf>],
+	hashes: &HashMap<PathBuf, Vec<Entry>>,
+	threshold: u32,
+	unchanged: impl Fn(&Path, &[Entry]) -> anyhow::Result<bool>,
+) -> Option<Outcome> {
+	let action = args.action?;
+	let mut outcome = Outcome::default();
+	let changed = |path: &Path| match unchanged(path, &hashes[path]) {
+		Ok(unchanged) => !unchanged,
+		Err(err) => {
+			warn!("Warning: couldn't re-hash {}: {:#}", path.display(), err);
+			true
+		},
+	};
+
+	for members in clusters {
+		let keep = keep(members, args.keep);
+		let Ok(kept) = keep.canonicalize() else {
+			error!("Error: {} can no longer be read, leaving its duplicates alone", keep.display());
+			outcome.failed += members.len() - 1;
+			continue;
+		};
+		if changed(keep) {
+			warn!("Warning: {} changed since it was hashed, leaving its duplicates alone", keep.display());
+			outcome.skipped += members.len() - 1;
+			continue;
+		}
+
+		for member in members.iter().filter(|member| *member != keep) {
+			if !within_threshold(hashes, keep, member, threshold) {
+				warn!("Warning: {} isn't within the threshold of {}, which is kept, leaving it alone", member.display(), keep.display());
+				outcome.skipped += 1;
+				continue;
+			}
+			if member.canonicalize().is_ok_and(|member| member == kept) {
+				warn!("Warning: {} is {} under another path, leaving it alone", member.display(), keep.display());
+				continue;
+			}
+			if action == Action::Hardlink && same_file(member, &kept) {
+				continue;
+			}
+			if changed(member) {
+				warn!("Warning: {} changed since it was hashed, leaving it alone", member.display());
+				outcome.skipped += 1;
+				continue;
+			}
+
+			let target = match action {
+				Action::Hardlink | Action::Symlink => kept.clone(),
+				Action::MoveTo => moved_path(args.destination.as_deref().expect("clap requires --destination"), member),
+				Action::Delete => PathBuf::new(),
+			};
+			let name = action.to_possible_value().unwrap();
+			match action {
+				Action::Delete => println!("{}\t{}", name.get_name(), member.display()),
+				_ => println!("{}\t{}\t{}", name.get_name(), member.display(), target.display()),
+			}
+
+			if args.dry_run {
+				outcome.done += 1;
+				continue;
+			}
+			match apply(action, member, &target) {
+				Ok(()) => outcome.done += 1,
+				Err(err) => {
+					error!("Error acting on {}: {:#}", member.display(), err);
+					outcome.failed += 1;
+				},
+			}
+		}
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"dedupe-action",
+		serde_json::json!({
+			"action": action.to_possible_value().unwrap().get_name(),
+			"keep": args.keep.to_possible_value().unwrap().get_name(),
+			"dry_run": args.dry_run,
+			"done": outcome.done,
+			"failed": outcome.failed,
+			"skipped": outcome.skipped,
+		}),
+	);
+
+	if args.dry_run {
+		info!("Dry run: would act on {} duplicates in {} clusters, leaving {} alone", outcome.done, clusters.len(), outcome.skipped);
+	} else {
+		info!("Acted on {} duplicates in {} clusters, {} failed, {} left alone", outcome.done, clusters.len(), outcome.failed, outcome.skipped);
+	}
+
+	Some(outcome)
+}
+
+
+/// Whether a file still hashes to its cached entries, with every algorithm it was hashed with.
+pub fn unchanged(hasher: &Hasher, path: &Path, cached: &[Entry]) -> anyhow::Result<bool> {
+	let algorithms = &hasher.settings.algorithms;
+	let columns = |entries: &[Entry]| entries.iter().map(|entry| hashes(entry, algorithms, algorithms)).collect::<Vec<_>>();
+
+	Ok(columns(&hasher.hash(path)?) == columns(cached))
+}
+
+
+/// The member of a cluster to keep.  Files that can't be read count as empty and as modified now; ties go to the
+/// first by path.
+fn keep(members: &[PathBuf], keep: Keep) -> &PathBuf {
+	match keep {
+		Keep::Largest => crate::dedupe::largest_file(members),
+		Keep::Earliest => members
+			.iter()
+			.min_by_key(|path| (std::fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or_else(|_| SystemTime::now()), *path))
+			.unwrap(),
+		Keep::First => members.iter().min().unwrap(),
+	}
+}
+
+
+/// Where `--action move-to` moves a path: under the destination, at the path with any root or prefix removed.
+fn moved_path(destination: &Path, path: &Path) -> PathBuf {
+	let relative = path.components().filter(|component| matches!(component, Component::Normal(_))).collect::<PathBuf>();
+	destination.join(relative)
+}
+
+
+/// Take a step.  Links replace duplicates atomically, by creating them next to the duplicate and renaming them over it.
+fn apply(action: Action, path: &Path, target: &Path) -> anyhow::Result<()> {
+	let temp = || {
+		let mut name = path.file_name().unwrap_or_default().to_os_string();
+		name.push(".hasher-link");
+		path.with_file_name(name)
+	};
+
+	match action {
+		Action::Hardlink => {
+			let temp = temp();
+			std::fs::hard_link(target, &temp).context("Error creating hard link")?;
+			std::fs::rename(&temp, path).inspect_err(|_| drop(std::fs::remove_file(&temp))).context("Error replacing file with hard link")?;
+		},
+		Action::Symlink => {
+			let temp = temp();
+			symlink(target, &temp).context("Error creating symbolic link")?;
+			std::fs::rename(&temp, path).inspect_err(|_| drop(std::fs::remove_file(&temp))).context("Error replacing file with symbolic link")?;
+		},
+		Action::MoveTo => {
+			if target.symlink_metadata().is_ok() {
+				bail!("{} already exists", target.display());
+			}
+			if let Some(parent) = target.parent() {
+				std::fs::create_dir_all(parent).context("Error creating destination directory")?;
+			}
+
+			// Across filesystems, copy and then remove the original
+			if std::fs::rename(path, target).is_err() {
+				std::fs::copy(path, target).context("Error copying file")?;
+				std::fs::remove_file(path).context("Error removing file after copying it")?;
+			}
+		},
+		Action::Delete => std::fs::remove_file(path).context("Error deleting file")?,
+	}
+
+	Ok(())
+}
+
+
+/// Whether two paths are hard links to the same file.
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	match (std::fs::metadata(a), std::fs::metadata(b)) {
+		(Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+		_ => false,
+	}
+}
+
+
+/// Whether two paths are hard links to the same file.  Always false where that can't be told cheaply; hard linking
+/// them again leaves them as they are.
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> bool {
+	false
+}
+
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+	std::os::unix::fs::symlink(target, link)
+}
+
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+	std::os::windows::fs::symlink_file(target, link)
+}
+
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+	Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "symbolic links aren't supported on this platform"))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cache::Metadata;
+
+	/// A directory of files named after their contents, removed at the end of the test.
+	struct Files(PathBuf);
+
+	impl Files {
+		fn new(test: &str, names: &[&str]) -> Self {
+			let dir = std::env::temp_dir().join(format!("hasher-action-{}-{}", test, std::process::id()));
+			std::fs::create_dir_all(&dir).unwrap();
+			for name in names {
+				std::fs::write(dir.join(name), name).unwrap();
+			}
+
+			Files(dir)
+		}
+
+		fn path(&self, name: &str) -> PathBuf {
+			self.0.join(name)
+		}
+	}
+
+	impl Drop for Files {
+		fn drop(&mut self) {
+			drop(std::fs::remove_dir_all(&self.0));
+		}
+	}
+
+
+	fn args(action: Action, dry_run: bool) -> ActionArgs {
+		ActionArgs { action: Some(action), destination: None, keep: Keep::First, dry_run }
+	}
+
+
+	fn entry(phash: u64) -> Entry {
+		Entry { phash: Some(phash), extra: Vec::new() }
+	}
+
+
+	/// `a`, kept, and `c` are only linked through `b`, 3 bits from each.
+	fn chain(files: &Files) -> (Vec<PathBuf>, HashMap<PathBuf, Vec<Entry>>) {
+		let members = ["a", "b", "c"].map(|name| files.path(name)).to_vec();
+		let hashes = members.iter().cloned().zip([vec![entry(0)], vec![entry(0b111)], vec![entry(0b111_111)]]).collect();
+
+		(members, hashes)
+	}
+
+
+	#[test]
+	fn deletes_only_members_within_threshold_of_kept() {
+		let files = Files::new("delete", &["a", "b", "c"]);
+		let (members, hashes) = chain(&files);
+
+		let outcome = run(&args(Action::Delete, false), &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.failed, outcome.skipped), (1, 0, 1));
+		assert!(files.path("a").exists());
+		assert!(!files.path("b").exists());
+		assert!(files.path("c").exists());
+	}
+
+
+	#[test]
+	fn dry_run_leaves_files_alone() {
+		let files = Files::new("dry-run", &["a", "b", "c"]);
+		let (members, hashes) = chain(&files);
+
+		let outcome = run(&args(Action::Delete, true), &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.skipped), (1, 1));
+		assert!(["a", "b", "c"].iter().all(|name| files.path(name).exists()));
+	}
+
+
+	#[test]
+	fn leaves_members_with_several_or_trivial_entries_alone() {
+		let files = Files::new("entries", &["a", "b", "c", "d"]);
+		let members = ["a", "b", "c", "d"].map(|name| files.path(name)).to_vec();
+		let hashes = members
+			.iter()
+			.cloned()
+			.zip([vec![entry(0)], vec![entry(0), entry(u64::MAX)], vec![Entry { phash: None, extra: Vec::new() }], vec![]])
+			.collect();
+
+		let outcome = run(&args(Action::Delete, false), &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.skipped), (0, 3));
+		assert!(["a", "b", "c", "d"].iter().all(|name| files.path(name).exists()));
+	}
+
+
+	#[test]
+	fn leaves_clusters_alone_if_kept_member_has_several_entries() {
+		let files = Files::new("kept-entries", &["a", "b"]);
+		let members = vec![files.path("a"), files.path("b")];
+		let hashes = [(files.path("a"), vec![entry(0), entry(1)]), (files.path("b"), vec![entry(0)])].into_iter().collect();
+
+		let outcome = run(&args(Action::Delete, false), &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.skipped), (0, 1));
+		assert!(files.path("b").exists());
+	}
+
+
+	#[test]
+	fn moves_members_within_threshold() {
+		let files = Files::new("move-to", &["a", "b", "c"]);
+		let (members, hashes) = chain(&files);
+		let destination = files.path("moved");
+		let args = ActionArgs { destination: Some(destination.clone()), ..args(Action::MoveTo, false) };
+
+		let outcome = run(&args, &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.skipped), (1, 1));
+		assert!(!files.path("b").exists());
+		assert_eq!(std::fs::read(moved_path(&destination, &files.path("b"))).unwrap(), b"b");
+		assert!(files.path("c").exists());
+	}
+
+
+	#[cfg(unix)]
+	#[test]
+	fn hard_links_members_within_threshold() {
+		let files = Files::new("hardlink", &["a", "b", "c"]);
+		let (members, hashes) = chain(&files);
+
+		let outcome = run(&args(Action::Hardlink, false), &[members], &hashes, 4, |_, _| Ok(true)).unwrap();
+
+		assert_eq!((outcome.done, outcome.skipped), (1, 1));
+		assert!(same_file(&files.path("a"), &files.path("b")));
+		assert!(!same_file(&files.path("a"), &files.path("c")));
+		assert_eq!(std::fs::read(files.path("c")).unwrap(), b"c");
+	}
+
+
+	#[test]
+	fn leaves_files_changed_since_hashed_alone() {
+		let files = Files::new("changed", &["a", "b", "d"]);
+		let members = ["a", "b", "d"].map(|name| files.path(name)).to_vec();
+		let hashes = members.iter().cloned().map(|path| (path, vec![entry(0)])).collect();
+
+		let outcome = run(&args(Action::Delete, false), std::slice::from_ref(&members), &hashes, 4, |path, _| Ok(!path.ends_with("b"))).unwrap();
+		assert_eq!((outcome.done, outcome.skipped), (1, 1));
+		assert!(files.path("b").exists());
+		assert!(!files.path("d").exists());
+
+		// Nor are the duplicates of a kept file that changed
+		let outcome = run(&args(Action::Delete, false), &[members[..2].to_vec()], &hashes, 4, |path, _| Ok(!path.ends_with("a"))).unwrap();
+		assert_eq!((outcome.done, outcome.skipped), (0, 1));
+		assert!(files.path("b").exists());
+	}
+
+
+	#[test]
+	fn refuses_output_files_with_several_entries_per_input() {
+		let check_with = |key: &str, value: &str| {
+			let mut metadata = Metadata::default();
+			metadata.set(key, value);
+			check(&Settings::from_metadata(&metadata).unwrap())
+		};
+
+		assert!(check(&Settings::default()).is_ok());
+		assert!(check_with("tiles", "2").is_err());
+		assert!(check_with("frames", "all").is_err());
+		assert!(check_with("frames", "representative").is_err());
+		assert!(check_with("video", "interval:10").is_err());
+		assert!(check_with("invariant", "rot90,flip:all").is_err());
+		assert!(check_with("invariant", "rot90,flip:min").is_ok());
+	}
+}
@@ -0,0 +1,33 @@
+//! Decodes the frames of animated GIF, WebP and APNG images.
+#[cfg(any(feature = "gif", feature = "png", feature = "webp"))]
+use {
+	image::{AnimationDecoder, ImageFormat},
+	std::io::Cursor,
+};
+
+
+/// Decoder for the fully composited frames of an animated image.
+/// Returns None for formats that can't be animated and for PNG and WebP images that aren't.
+pub fn decode_frames(data: &[u8]) -> anyhow::Result<Option<image::Frames<'_>>> {
+	match image::guess_format(data) {
+		#[cfg(feature = "gif")]
+		Ok(ImageFormat::Gif) => Ok(Some(image::codecs::gif::GifDecoder::new(Cursor::new(data))?.into_frames())),
+		#[cfg(feature = "png")]
+		Ok(ImageFormat::Png) => {
+			let decoder = image::codecs::png::PngDecoder::new(Cursor::new(data))?;
+			if !decoder.is_apng()? {
+				return Ok(None);
+			}
+			Ok(Some(decoder.apng()?.into_frames()))
+		},
+		#[cfg(feature = "webp")]
+		Ok(ImageFormat::WebP) => {
+			let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(data))?;
+			if !decoder.has_animation() {
+				return Ok(None);
+			}
+			Ok(Some(decoder.into_frames()))
+		},
+		_ => Ok(None),
+	}
+}
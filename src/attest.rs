@@ -0,0 +1,151 @@
+//! Signed attestations of output files, so that a hash inventory can later be shown to be unmodified.
+//! Signatures are minisign (ed25519) signatures, and keys can be generated with `minisign -G`.  The signature of a
+//! sharded output file covers its manifest and every shard.
+use minisign::{PublicKey, SecretKey, SecretKeyBox, SignatureBox};
+use std::{
+	io::{self, Cursor},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+use crate::{cache::read_result_file, shards::Manifest};
+
+
+#[derive(clap::Args, Debug)]
+pub struct AttestArgs {
+	/// Output file to sign.  Any later run that appends to it or its shards invalidates the signature, so attest once
+	/// hashing is done.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Minisign secret key.  Prompts for its password if it is encrypted.
+	#[arg(short = 'k', long)]
+	secret_key: PathBuf,
+
+	/// Where to write the signature.  Defaults to the output file's path with `.minisig` appended.
+	#[arg(long)]
+	signature: Option<PathBuf>,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyAttestationArgs {
+	/// Output file to verify.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Minisign public key of the key that signed the output file.
+	#[arg(short, long)]
+	public_key: PathBuf,
+
+	/// Signature to verify.  Defaults to the output file's path with `.minisig` appended.
+	#[arg(long)]
+	signature: Option<PathBuf>,
+}
+
+
+/// Sign an output file.  The signature's trusted comment records when it was signed and how many entries it covered,
+/// and how many shards for sharded output files.
+pub fn run_attest(args: AttestArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| fatal("reading", &args.output, err));
+	let entries = results.hashes.values().map(Vec::len).sum::<usize>();
+	let secret_key = read_secret_key(&args.secret_key).unwrap_or_else(|err| fatal("reading", &args.secret_key, err));
+
+	let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+	let file_name = args.output.file_name().unwrap_or_default().to_string_lossy();
+	let (signed, shards) = signed(&args.output).unwrap_or_else(|err| fatal("reading", &args.output, err));
+	let trusted_comment = match shards {
+		0 => format!("timestamp:{}\tfile:{}\tentries:{}\t{}", timestamp, file_name, entries, results.metadata),
+		shards => format!("timestamp:{}\tfile:{}\tshards:{}\tentries:{}\t{}", timestamp, file_name, shards, entries, results.metadata),
+	};
+	let untrusted_comment = format!("signature of hasher output {}", file_name);
+
+	let signature = minisign::sign(None, &secret_key, signed, Some(&trusted_comment), Some(&untrusted_comment)).unwrap_or_else(|err| fatal("signing", &args.output, err));
+
+	let signature_path = signature_path(&args.output, args.signature);
+	std::fs::write(&signature_path, signature.to_string()).unwrap_or_else(|err| fatal("writing", &signature_path, err));
+
+	#[cfg(feature = "audit")]
+	crate::audit::record("attest", serde_json::json!({ "output": args.output, "signature": signature_path, "shards": shards, "entries": entries }));
+
+	info!("Signed {} entries of {}{} in {}", entries, args.output.display(), describe_shards(shards), signature_path.display());
+}
+
+
+/// Verify an output file against its signature, printing the signature's trusted comment if it matches.
+pub fn run_verify_attestation(args: VerifyAttestationArgs) {
+	let public_key = PublicKey::from_file(&args.public_key).unwrap_or_else(|err| fatal("reading", &args.public_key, err));
+	let signature_path = signature_path(&args.output, args.signature);
+	let signature = SignatureBox::from_file(&signature_path).unwrap_or_else(|err| fatal("reading", &signature_path, err));
+
+	let (signed, shards) = signed(&args.output).unwrap_or_else(|err| fatal("reading", &args.output, err));
+	let verified = minisign::verify(&public_key, &signature, signed, true, false, false);
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"verify-attestation",
+		serde_json::json!({ "output": args.output, "signature": signature_path, "shards": shards, "ok": verified.is_ok() }),
+	);
+
+	if let Err(err) = verified {
+		error!("Attestation of {}{} FAILED: {}", args.output.display(), describe_shards(shards), err);
+		std::process::exit(1);
+	}
+
+	println!("{}", signature.trusted_comment().unwrap_or_default());
+	info!("Attestation of {}{} OK", args.output.display(), describe_shards(shards));
+}
+
+
+/// The data an attestation signs, and the number of shards it covers.  That is the output file as is, followed, if it
+/// is a shard manifest, by the length and contents of each of its shards in turn, so that editing, truncating or
+/// swapping shards also invalidates the signature.  Shards that don't exist yet count as empty.
+fn signed(output: &Path) -> anyhow::Result<(Cursor<Vec<u8>>, usize)> {
+	let mut signed = std::fs::read(output)?;
+	let shards = Manifest::parse(output, &signed)?.map(|manifest| manifest.paths).unwrap_or_default();
+
+	for path in &shards {
+		let shard = match std::fs::read(path) {
+			Ok(shard) => shard,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+			Err(err) => anyhow::bail!("Error reading shard {}: {}", path.display(), err),
+		};
+
+		signed.extend_from_slice(format!("{}\n", shard.len()).as_bytes());
+		signed.extend_from_slice(&shard);
+	}
+
+	Ok((Cursor::new(signed), shards.len()))
+}
+
+
+fn describe_shards(shards: usize) -> String {
+	match shards {
+		0 => String::new(),
+		shards => format!(" and its {} shards", shards),
+	}
+}
+
+
+/// Read a secret key, which is either unencrypted (`minisign -G -W`) or password-protected.
+fn read_secret_key(path: &Path) -> anyhow::Result<SecretKey> {
+	let key = SecretKeyBox::from_string(&std::fs::read_to_string(path)?)?;
+
+	Ok(SecretKey::from_unencrypted_box(key.clone()).or_else(|_| SecretKey::from_box(key, None))?)
+}
+
+
+fn signature_path(output: &Path, signature: Option<PathBuf>) -> PathBuf {
+	signature.unwrap_or_else(|| {
+		let mut path = output.as_os_str().to_owned();
+		path.push(".minisig");
+		PathBuf::from(path)
+	})
+}
+
+
+fn fatal(action: &str, path: &Path, err: impl std::fmt::Display) -> ! {
+	error!("Error {} {}: {}", action, path.display(), err);
+	std::process::exit(1);
+}
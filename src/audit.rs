@@ -0,0 +1,127 @@
+//! An append-only audit log recording every invocation and what it did, for workflows that need to document their process.
+//! Each line is a JSON record that includes the SHA-256 of the line before it, so that editing, removing or reordering
+//! earlier records breaks the chain.  Anchoring the latest hash elsewhere also protects the end of the log.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+	fs::File,
+	io::{BufRead, BufReader, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	sync::{Mutex, OnceLock},
+	time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+
+/// `prev` of the first record.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+	seq: u64,
+	time: u64,
+	operator: String,
+	pid: u32,
+	event: String,
+	details: serde_json::Value,
+	prev: String,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyAuditLogArgs {
+	/// Audit log to check.
+	log: PathBuf,
+}
+
+
+/// Start recording to the audit log at `path`, creating it if needed.
+pub fn open(path: &Path) -> anyhow::Result<()> {
+	let file = File::options().read(true).append(true).create(true).open(path)?;
+	LOG.set(Mutex::new(file)).map_err(|_| anyhow::anyhow!("Audit log already open"))
+}
+
+
+/// Append a record to the audit log, if one was opened.
+/// Not being able to record is fatal, since the log would otherwise silently miss actions.
+pub fn record(event: &str, details: serde_json::Value) {
+	let Some(log) = LOG.get() else {
+		return;
+	};
+
+	let mut file = log.lock().unwrap();
+	if let Err(err) = append(&mut file, event, details) {
+		error!("Error writing audit log: {:#}", err);
+		std::process::exit(1);
+	}
+}
+
+
+fn append(file: &mut File, event: &str, details: serde_json::Value) -> anyhow::Result<()> {
+	// Other processes may be appending to the same log
+	file.lock()?;
+
+	file.seek(SeekFrom::Start(0))?;
+	let (seq, prev) = BufReader::new(&*file)
+		.lines()
+		.try_fold((0, GENESIS.to_string()), |(seq, _), line| line.map(|line| (seq + 1, sha256(&line))))?;
+
+	let record = Record {
+		seq,
+		time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+		operator: std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string()),
+		pid: std::process::id(),
+		event: event.to_string(),
+		details,
+		prev,
+	};
+
+	let mut line = serde_json::to_string(&record)?;
+	line.push('\n');
+	file.write_all(line.as_bytes())?;
+	file.sync_data()?;
+	file.unlock()?;
+
+	Ok(())
+}
+
+
+/// Check that every record of an audit log chains to the one before it.
+pub fn run_verify(args: VerifyAuditLogArgs) {
+	let file = File::open(&args.log).unwrap_or_else(|err| {
+		error!("Error opening {}: {}", args.log.display(), err);
+		std::process::exit(1);
+	});
+
+	let mut prev = GENESIS.to_string();
+	let mut count = 0;
+
+	for (index, line) in BufReader::new(file).lines().enumerate() {
+		let line = line.unwrap();
+		let problem = match serde_json::from_str::<Record>(&line) {
+			Err(err) => Some(format!("invalid record: {}", err)),
+			Ok(record) if record.seq != index as u64 => Some(format!("sequence number {} where {} was expected", record.seq, index)),
+			Ok(record) if record.prev != prev => Some("does not chain to the previous record".to_string()),
+			Ok(_) => None,
+		};
+
+		if let Some(problem) = problem {
+			error!("Audit log {} is BROKEN at line {}: {}", args.log.display(), index + 1, problem);
+			std::process::exit(1);
+		}
+
+		prev = sha256(&line);
+		count += 1;
+	}
+
+	println!("{}", prev);
+	info!("Audit log {} OK: {} records, latest hash printed above", args.log.display(), count);
+}
+
+
+fn sha256(line: &str) -> String {
+	Sha256::digest(line.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
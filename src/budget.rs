@@ -0,0 +1,75 @@
+//! Limits how many pixels are decoded at once, so that many large images decoded in parallel don't exhaust memory.
+use image::io::Reader as ImageReader;
+use std::{
+	io::Cursor,
+	sync::{Condvar, Mutex},
+};
+
+
+/// A budget of decoded pixels shared by the threads hashing inputs.
+pub struct PixelBudget {
+	max: u64,
+	in_flight: Mutex<u64>,
+	released: Condvar,
+}
+
+/// Pixels taken from a `PixelBudget`, returned when dropped.
+pub struct Permit<'a> {
+	budget: &'a PixelBudget,
+	pixels: u64,
+}
+
+impl PixelBudget {
+	pub fn new(max: u64) -> Self {
+		PixelBudget {
+			max,
+			in_flight: Mutex::new(0),
+			released: Condvar::new(),
+		}
+	}
+
+	/// Wait until `pixels` more fit within the budget.  An image larger than the whole budget waits until nothing else
+	/// is in flight, and is then decoded on its own.
+	pub fn acquire(&self, pixels: u64) -> Permit<'_> {
+		let pixels = pixels.min(self.max);
+		let mut in_flight = self.released.wait_while(self.in_flight.lock().unwrap(), |in_flight| *in_flight + pixels > self.max).unwrap();
+		*in_flight += pixels;
+
+		Permit { budget: self, pixels }
+	}
+}
+
+impl Drop for Permit<'_> {
+	fn drop(&mut self) {
+		*self.budget.in_flight.lock().unwrap() -= self.pixels;
+		self.budget.released.notify_all();
+	}
+}
+
+
+/// The number of pixels an encoded image decodes to, from its header, or 0 if it can't be told.
+pub fn estimate_pixels(data: &[u8]) -> u64 {
+	ImageReader::new(Cursor::new(data))
+		.with_guessed_format()
+		.ok()
+		.and_then(|reader| reader.into_dimensions().ok())
+		.map(|(width, height)| width as u64 * height as u64)
+		.unwrap_or(0)
+}
+
+
+/// Parse a number of pixels, optionally with a `K`, `M` or `G` suffix (powers of 1000).
+pub fn parse_pixels(s: &str) -> Result<u64, String> {
+	let (number, scale) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+		Some('K') => (&s[..s.len() - 1], 1_000),
+		Some('M') => (&s[..s.len() - 1], 1_000_000),
+		Some('G') => (&s[..s.len() - 1], 1_000_000_000),
+		_ => (s, 1),
+	};
+
+	match number.parse::<u64>() {
+		Ok(0) => Err("must be at least 1".to_string()),
+		Ok(number) => number.checked_mul(scale).ok_or_else(|| "too large".to_string()),
+		Err(err) => Err(err.to_string()),
+	}
+}
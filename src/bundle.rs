@@ -0,0 +1,159 @@
+//! Packages an output file with everything needed to interpret it into a single tar archive, for handing off or
+//! attaching to a report.
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fs::File,
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+use crate::{
+	cache::read_result_file,
+	settings::Settings,
+	shards::Manifest,
+	source::{Source, SourceArgs},
+	thumbnail::render,
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct BundleArgs {
+	/// Output file to package.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Archive to write.  Overwritten if it exists.
+	#[arg(short, long)]
+	bundle: PathBuf,
+
+	/// Audit log to include.
+	#[arg(long)]
+	include_log: Option<PathBuf>,
+
+	/// Maximum number of thumbnails to include.  Clusters are listed in full either way.
+	#[arg(long, default_value_t = 500)]
+	max_thumbnails: usize,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+/// Longest side of the thumbnails in a bundle.
+const THUMBNAIL_SIZE: u32 = 256;
+
+
+/// Write a bundle containing:
+///
+/// - `hashes.tsv`, the output file as is, and for sharded output files its shards, named as they are beside it
+/// - `settings.txt`, the parameters the hashes were computed with
+/// - `version.txt`, the version, target and features of this build
+/// - `clusters.tsv`, every group of paths sharing a hash, with the thumbnail of each member
+/// - `thumbnails/`, JPEG thumbnails of cluster members
+/// - `audit.log`, if one is given
+pub fn run(args: BundleArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	let settings = Settings::from_metadata(&results.metadata).unwrap_or_else(|err| {
+		error!("Error: can't bundle {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	let contents = std::fs::read(&args.output).map_err(anyhow::Error::from);
+	let shards = contents.and_then(|contents| Manifest::parse(&args.output, &contents)).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+	let shards = shards.map(|manifest| manifest.paths).unwrap_or_default();
+
+	// Paths sharing a hash are flagged as clusters
+	let mut by_phash: BTreeMap<u64, BTreeSet<&PathBuf>> = BTreeMap::new();
+	for (path, entries) in &results.hashes {
+		for phash in entries.iter().filter_map(|entry| entry.phash) {
+			by_phash.entry(phash).or_default().insert(path);
+		}
+	}
+	let clusters = by_phash.into_iter().filter(|(_, paths)| paths.len() > 1).collect::<Vec<_>>();
+
+	let file = File::create(&args.bundle).unwrap_or_else(|err| {
+		error!("Error creating {}: {}", args.bundle.display(), err);
+		std::process::exit(1);
+	});
+	let mut archive = tar::Builder::new(BufWriter::new(file));
+
+	append_file(&mut archive, "hashes.tsv", &args.output);
+	for path in &shards {
+		if path.exists() {
+			append_file(&mut archive, &path.file_name().unwrap_or_default().to_string_lossy(), path);
+		}
+	}
+	append(&mut archive, "settings.txt", results.metadata.to_string().replace(", ", "\n") + "\n");
+	append(&mut archive, "version.txt", crate::version::describe());
+	if let Some(log) = &args.include_log {
+		append_file(&mut archive, "audit.log", log);
+	}
+
+	let source = Source::new(&args.source);
+	let mut listing = String::from("cluster\tphash\tpath\tthumbnail\n");
+	let mut thumbnails = 0;
+
+	for (index, (phash, paths)) in clusters.iter().enumerate() {
+		for (member, path) in paths.iter().enumerate() {
+			let mut name = String::new();
+
+			if thumbnails < args.max_thumbnails {
+				match render(&source, &settings, path, THUMBNAIL_SIZE) {
+					Ok((data, _)) => {
+						name = format!("thumbnails/{}-{}.jpg", index, member);
+						append(&mut archive, &name, data);
+						thumbnails += 1;
+					},
+					Err(err) => error!("Error creating thumbnail for {}: {}", path.display(), err),
+				}
+			}
+
+			listing.push_str(&format!("{}\t{}\t{}\t{}\n", index, phash, path.display(), name));
+		}
+	}
+
+	append(&mut archive, "clusters.tsv", listing);
+
+	archive.into_inner().and_then(|mut writer| writer.flush()).unwrap_or_else(|err| {
+		error!("Error writing {}: {}", args.bundle.display(), err);
+		std::process::exit(1);
+	});
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"bundle",
+		serde_json::json!({ "output": args.output, "bundle": args.bundle, "shards": shards.len(), "clusters": clusters.len(), "thumbnails": thumbnails }),
+	);
+
+	info!("Bundled {} with {} clusters and {} thumbnails into {}", args.output.display(), clusters.len(), thumbnails, args.bundle.display());
+}
+
+
+fn append<W: Write>(archive: &mut tar::Builder<W>, name: &str, data: impl AsRef<[u8]>) {
+	let data = data.as_ref();
+	let mut header = tar::Header::new_gnu();
+	header.set_size(data.len() as u64);
+	header.set_mode(0o644);
+	header.set_mtime(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+
+	archive.append_data(&mut header, name, data).unwrap();
+}
+
+
+fn append_file<W: Write>(archive: &mut tar::Builder<W>, name: &str, path: &Path) {
+	let data = std::fs::read(path).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", path.display(), err);
+		std::process::exit(1);
+	});
+
+	append(archive, name, data);
+}
@@ -0,0 +1,268 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt,
+	io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+};
+use tracing::warn;
+
+
+/// Parameters that were used to compute the hashes in an output file.
+/// Stored as `#key\tvalue` header lines at the start of the file.
+/// Keys missing from a file (e.g. files written before the key existed) take their default value.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Metadata(BTreeMap<String, String>);
+
+/// Default value of every metadata key.
+/// These describe how files written before the key existed were hashed, which isn't always the current default.
+const METADATA_DEFAULTS: &[(&str, &str)] = &[("algorithm", "phash"), ("background", "none"), ("compat", "none"), ("ensemble", "none"), ("filter", "lanczos3"), ("frames", "first"), ("grayscale", "rec709"), ("invariant", "none"), ("jpeg", "full"), ("orientation", "ignore"), ("pdf", "none"), ("preprocess", "none"), ("thumbnails", "none"), ("tiles", "none"), ("trivial", "hash"), ("video", "none")];
+
+/// Metadata keys whose defaults changed after files were written with the old ones, with the old value and the flag
+/// that keeps hashing with it.
+const LEGACY_FLAGS: &[(&str, &str, &str)] = &[("orientation", "ignore", "--no-exif-orientation"), ("jpeg", "full", "--full-decode")];
+
+impl Default for Metadata {
+	fn default() -> Self {
+		Metadata(METADATA_DEFAULTS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+	}
+}
+
+impl Metadata {
+	pub fn set(&mut self, key: &str, value: impl Into<String>) {
+		self.0.insert(key.to_string(), value.into());
+	}
+
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).map(String::as_str)
+	}
+
+	/// The flags a run hashing with `current` needs to resume a file written with these parameters, if they only differ
+	/// by defaults that have changed since.
+	pub fn legacy_flags(&self, current: &Metadata) -> Option<Vec<&'static str>> {
+		let mut flags = Vec::new();
+		for (key, value) in &self.0 {
+			if current.get(key) == Some(value) {
+				continue;
+			}
+			match LEGACY_FLAGS.iter().find(|(legacy_key, legacy, _)| legacy_key == key && legacy == value) {
+				Some((_, _, flag)) => flags.push(*flag),
+				None => return None,
+			}
+		}
+
+		Some(flags).filter(|flags| !flags.is_empty() && current.0.keys().all(|key| self.0.contains_key(key)))
+	}
+
+	fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		for (key, value) in &self.0 {
+			writeln!(writer, "#{}\t{}", key, value)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl fmt::Display for Metadata {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let parts = self.0.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+		write!(f, "{}", parts.join(", "))
+	}
+}
+
+
+/// Prefix of the keys of header lines describing the build and machine that created an output file.  Unlike the
+/// metadata, these may differ between the runs that write to it, and each run resuming it appends lines of its own,
+/// numbered from 2, e.g. `#env.2.version`, before the entries it writes.
+const ENVIRONMENT_PREFIX: &str = "env.";
+
+
+/// Written in place of the hash of trivial images.
+const TRIVIAL: &str = "trivial";
+
+
+/// A hash as written in output files.
+pub fn format_phash(phash: Option<u64>) -> String {
+	phash.map(|phash| phash.to_string()).unwrap_or_else(|| TRIVIAL.to_string())
+}
+
+
+/// Parse a hash as written in output files, or return None if it isn't one.
+pub fn parse_phash(phash: &str) -> Option<Option<u64>> {
+	match phash {
+		TRIVIAL => Some(None),
+		phash => phash.parse().ok().map(Some),
+	}
+}
+
+
+/// A single hashed image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	/// None for trivial (essentially uniform) images skipped by `--skip-trivial`, written as `trivial`.
+	pub phash: Option<u64>,
+	/// Additional `key=value` columns describing the image, e.g. the Photos asset UUID it belongs to.
+	pub extra: Vec<String>,
+}
+
+/// The contents of an output file.
+/// Most paths have a single entry; inputs such as videos have several, kept in file order.
+pub struct Results {
+	pub metadata: Metadata,
+	/// The build and machine that created the file, from its `#env.` header lines.
+	pub environment: BTreeMap<String, String>,
+	/// Those of each run that resumed writing it since, in order.
+	pub resumed: Vec<BTreeMap<String, String>>,
+	pub hashes: HashMap<PathBuf, Vec<Entry>>,
+}
+
+
+/// Read the output file and return its metadata and a map of paths to entries.
+/// Handles the case where the file is truncated.
+/// Leaves the file pointer at the end of the file, ready for appending.
+pub fn read_result<R: Read + Seek>(reader: &mut R) -> Results {
+	let mut metadata = Metadata::default();
+	let mut environment = BTreeMap::new();
+	let mut resumed = Vec::new();
+	let mut cache = HashMap::new();
+
+	let mut valid_len = 0;
+
+	reader.seek(SeekFrom::Start(0)).unwrap();
+
+	let mut reader = BufReader::new(reader);
+
+	loop {
+		let mut line = String::new();
+		match reader.read_line(&mut line) {
+			Ok(0) | Err(_) => break,
+			Ok(_) => (),
+		}
+
+		// If it doesn't end in a newline, it's truncated
+		if !line.ends_with('\n') {
+			break;
+		}
+
+		// Header lines only appear before the first entry, except for the environments of resumed runs
+		if let Some(header) = line.strip_prefix('#').filter(|header| cache.is_empty() || header.starts_with(ENVIRONMENT_PREFIX)) {
+			let Some((key, value)) = header.split_once('\t') else {
+				break;
+			};
+
+			match key.trim().strip_prefix(ENVIRONMENT_PREFIX) {
+				Some(key) => match key.split_once('.').and_then(|(run, key)| Some((run.parse::<usize>().ok()?, key))) {
+					Some((run, key)) => {
+						let index = run.saturating_sub(2);
+						if resumed.len() <= index {
+							resumed.resize_with(index + 1, BTreeMap::new);
+						}
+						resumed[index].insert(key.to_string(), value.trim().to_string());
+					},
+					None => {
+						environment.insert(key.to_string(), value.trim().to_string());
+					},
+				},
+				None => metadata.set(key.trim(), value.trim()),
+			}
+			valid_len = reader.stream_position().unwrap();
+			continue;
+		}
+
+		// Split by tab
+		let parts = line.split('\t').map(|part| part.trim()).collect::<Vec<_>>();
+
+		if parts.len() < 2 {
+			break;
+		}
+
+		let Some(phash) = parse_phash(parts[1]) else {
+			break;
+		};
+
+		let path = PathBuf::from(parts[0]);
+		let extra = parts[2..].iter().map(|part| part.to_string()).collect();
+
+		cache.entry(path).or_insert_with(Vec::new).push(Entry { phash, extra });
+		valid_len = reader.stream_position().unwrap();
+	}
+
+	let reader = reader.into_inner();
+
+	reader.seek(SeekFrom::Start(valid_len)).unwrap();
+
+	Results {
+		metadata,
+		environment,
+		resumed,
+		hashes: cache,
+	}
+}
+
+
+/// Read an output file from disk, or from object storage with the `object-store` feature.  The shards of sharded output
+/// files are read in parallel and returned as one.
+pub fn read_result_file(path: &Path) -> anyhow::Result<Results> {
+	let data = crate::storage::open(path, Default::default())?.read()?.ok_or_else(|| anyhow::anyhow!("output file does not exist"))?;
+	match crate::shards::Manifest::parse(path, &data)? {
+		Some(manifest) => manifest.read(),
+		None => Ok(read_result(&mut io::Cursor::new(data))),
+	}
+}
+
+
+/// Write the metadata header if the writer is at the start of an empty output file.
+pub fn write_header<W: Write + Seek>(writer: &mut W, metadata: &Metadata) -> io::Result<()> {
+	if writer.stream_position()? == 0 {
+		metadata.write(writer)?;
+	}
+
+	Ok(())
+}
+
+
+/// Write `#env.` header lines describing the build and machine creating an output file, after its metadata.
+pub fn write_environment<W: Write, K: fmt::Display, V: fmt::Display>(writer: &mut W, environment: impl IntoIterator<Item = (K, V)>) -> io::Result<()> {
+	for (key, value) in environment {
+		writeln!(writer, "#{}{}\t{}", ENVIRONMENT_PREFIX, key, value)?;
+	}
+
+	Ok(())
+}
+
+
+/// Write the `#env.N.` lines describing the build and machine of the `run`th run writing to an output file, which
+/// resumes writing it.
+pub fn write_run_environment<W: Write, K: fmt::Display, V: fmt::Display>(writer: &mut W, run: usize, environment: impl IntoIterator<Item = (K, V)>) -> io::Result<()> {
+	for (key, value) in environment {
+		writeln!(writer, "#{}{}.{}\t{}", ENVIRONMENT_PREFIX, run, key, value)?;
+	}
+
+	Ok(())
+}
+
+
+/// Write a single entry to an output file.
+/// Paths that aren't UTF-8 and paths and extra columns containing tabs or newlines can't be represented and are skipped
+/// with a warning.
+pub fn write_entry<W: Write>(writer: &mut W, path: &Path, entry: &Entry) -> io::Result<()> {
+	let Some(path) = path.to_str() else {
+		warn!("Warning: path isn't valid UTF-8, it will be skipped: {}", path.display());
+		return Ok(());
+	};
+
+	if path.contains('\t') || path.contains('\n') {
+		warn!("Warning: path contains tab or newline, it will be skipped: {}", path);
+		return Ok(());
+	}
+
+	if entry.extra.iter().any(|extra| extra.contains('\t') || extra.contains('\n')) {
+		warn!("Warning: metadata for path contains tab or newline, it will be skipped: {}", path);
+		return Ok(());
+	}
+
+	write!(writer, "{}\t{}", path, format_phash(entry.phash))?;
+	for extra in &entry.extra {
+		write!(writer, "\t{}", extra)?;
+	}
+	writeln!(writer)
+}
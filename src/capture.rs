@@ -0,0 +1,137 @@
+//! Reads what EXIF says about how a photo was taken, to corroborate perceptual matches.
+use std::{fmt, io::Cursor, str::FromStr};
+
+
+/// Capture details of a photo.  Fields are None when the image doesn't record them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capture {
+	/// `DateTimeOriginal`, as recorded.
+	pub time: Option<String>,
+	pub make: Option<String>,
+	pub model: Option<String>,
+	/// GPS latitude and longitude, in degrees.
+	pub location: Option<(f64, f64)>,
+}
+
+impl Capture {
+	/// The fraction of the fields recorded by both photos that agree, or None if they have none in common.
+	pub fn agreement(&self, other: &Capture) -> Option<f64> {
+		let compared = [(&self.time, &other.time), (&self.make, &other.make), (&self.model, &other.model)]
+			.into_iter()
+			.filter_map(|(a, b)| Some((a.as_ref()?, b.as_ref()?)))
+			.collect::<Vec<_>>();
+
+		if compared.is_empty() {
+			return None;
+		}
+
+		Some(compared.iter().filter(|(a, b)| a == b).count() as f64 / compared.len() as f64)
+	}
+}
+
+
+/// Read the capture details of an encoded image.
+pub fn read_capture(data: &[u8]) -> Capture {
+	let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) else {
+		return Capture::default();
+	};
+
+	let field = |tag| {
+		exif.get_field(tag, exif::In::PRIMARY)
+			.map(|field| field.display_value().to_string().trim_matches('"').trim().to_string())
+			.filter(|value| !value.is_empty())
+	};
+
+	// Coordinates are degrees, minutes and seconds, with the hemisphere in a separate reference tag
+	let coordinate = |tag, reference, negative: &str| {
+		let exif::Value::Rational(dms) = &exif.get_field(tag, exif::In::PRIMARY)?.value else {
+			return None;
+		};
+		let [degrees, minutes, seconds] = dms.get(..3)? else {
+			return None;
+		};
+		let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+
+		Some(if field(reference).as_deref() == Some(negative) { -value } else { value })
+	};
+
+	Capture {
+		time: field(exif::Tag::DateTimeOriginal),
+		make: field(exif::Tag::Make),
+		model: field(exif::Tag::Model),
+		location: coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S")
+			.zip(coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W"))
+			.filter(|(latitude, longitude)| latitude.is_finite() && longitude.is_finite()),
+	}
+}
+
+
+/// Mean radius of the Earth, in meters.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+
+/// Great-circle distance between two locations, in meters.
+pub fn distance((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+	let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+	let a = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1).to_radians() / 2.0).sin().powi(2);
+
+	2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+
+/// How matches are grouped in reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+	/// By where photos were taken: within this many meters of the first photo of a group.
+	Geotag(f64),
+}
+
+impl fmt::Display for GroupBy {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			GroupBy::Geotag(radius) => write!(f, "geotag:{}m", radius),
+		}
+	}
+}
+
+impl FromStr for GroupBy {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let radius = match s.split_once(':') {
+			Some(("geotag", radius)) => radius,
+			None if s == "geotag" => "100m",
+			_ => anyhow::bail!("Unknown grouping: {} (expected geotag:<radius>, e.g. geotag:100m)", s),
+		};
+
+		let (number, scale) = if let Some(km) = radius.strip_suffix("km") {
+			(km, 1000.0)
+		} else {
+			(radius.strip_suffix('m').unwrap_or(radius), 1.0)
+		};
+		let radius = number.parse::<f64>().map_err(|_| anyhow::anyhow!("Invalid radius: {}", radius))? * scale;
+		if radius.is_nan() || radius <= 0.0 {
+			anyhow::bail!("Radius must be positive");
+		}
+
+		Ok(GroupBy::Geotag(radius))
+	}
+}
+
+
+/// Assign locations to groups, each holding the locations within `radius` meters of its first.
+/// Returns the index of each location's group.
+pub fn group_locations(locations: &[(f64, f64)], radius: f64) -> Vec<usize> {
+	let mut centers: Vec<(f64, f64)> = Vec::new();
+
+	locations
+		.iter()
+		.map(|&location| match centers.iter().position(|&center| distance(center, location) <= radius) {
+			Some(group) => group,
+			None => {
+				centers.push(location);
+				centers.len() - 1
+			},
+		})
+		.collect()
+}
@@ -0,0 +1,214 @@
+//! Finds the JPEG and PNG images stored in a raw disk image or other container (`--carve`), so that everything
+//! recoverable from a drive can be hashed in one step.  Carved images are hashed in place as `path@offset:len` inputs.
+use anyhow::Context;
+use std::{
+	fs::File,
+	io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+	path::{Path, PathBuf},
+};
+use tracing::info;
+
+
+/// How much of the container is searched for signatures at once.
+const BLOCK: usize = 4 * 1024 * 1024;
+
+/// Largest image carved.  Candidates that don't end by then are taken to be false positives.
+const MAX_LEN: u64 = 256 * 1024 * 1024;
+
+const JPEG_SIGNATURE: &[u8] = &[0xFF, 0xD8, 0xFF];
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+
+/// Return a `path@offset:len` input for every image found in the container, with a `carved=` column naming its
+/// format.  Images are found by their signatures and measured by walking their structure, so they must be stored
+/// contiguously; images found inside others, such as EXIF thumbnails, aren't returned separately.
+pub fn scan(path: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+	let context = || format!("Error carving {}", path.display());
+	let mut blocks = File::open(path).with_context(context)?;
+	let mut images = BufReader::new(File::open(path).with_context(context)?);
+	let size = blocks.metadata().with_context(context)?.len();
+
+	let mut carved = Vec::new();
+	let mut block = vec![0; BLOCK];
+	let mut start = 0;
+
+	'blocks: loop {
+		blocks.seek(SeekFrom::Start(start)).with_context(context)?;
+		let len = read_block(&mut blocks, &mut block).with_context(context)?;
+		let eof = len < BLOCK;
+		// Signatures that straddle the end of the block are found at the start of the next one
+		let limit = if eof { len } else { len - (PNG_SIGNATURE.len() - 1) };
+
+		let mut i = 0;
+		while i < limit {
+			let candidate = &block[i..len];
+			let measured = if candidate.starts_with(JPEG_SIGNATURE) {
+				measure(&mut images, start + i as u64, size, jpeg_len).with_context(context)?.map(|len| (len, "jpeg"))
+			} else if candidate.starts_with(PNG_SIGNATURE) {
+				measure(&mut images, start + i as u64, size, png_len).with_context(context)?.map(|len| (len, "png"))
+			} else {
+				None
+			};
+
+			let Some((image_len, format)) = measured else {
+				i += 1;
+				continue;
+			};
+
+			let offset = start + i as u64;
+			carved.push((PathBuf::from(format!("{}@{}:{}", path.display(), offset, image_len)), vec![format!("carved={}", format)]));
+
+			if i as u64 + image_len >= limit as u64 {
+				start = offset + image_len;
+				continue 'blocks;
+			}
+			i += image_len as usize;
+		}
+
+		if eof {
+			break;
+		}
+		start += limit as u64;
+	}
+
+	info!("Carved {} images from {}", carved.len(), path.display());
+
+	Ok(carved)
+}
+
+
+/// Fill as much of the block as the rest of the file allows, returning how many bytes were read.
+fn read_block(file: &mut File, block: &mut [u8]) -> std::io::Result<usize> {
+	let mut len = 0;
+	while len < block.len() {
+		match file.read(&mut block[len..]) {
+			Ok(0) => break,
+			Ok(n) => len += n,
+			Err(err) if err.kind() == ErrorKind::Interrupted => {},
+			Err(err) => return Err(err),
+		}
+	}
+
+	Ok(len)
+}
+
+
+/// Measure the candidate image at an offset, returning None if it isn't a complete image.  Reaching the end of the
+/// container isn't an error, but means the candidate was truncated.
+fn measure(images: &mut BufReader<File>, offset: u64, size: u64, len: fn(&mut Reader) -> std::io::Result<Option<u64>>) -> std::io::Result<Option<u64>> {
+	images.seek(SeekFrom::Start(offset))?;
+	match len(&mut Reader { inner: images, pos: 0 }) {
+		// Skipping the last segment may have gone past the end
+		Ok(len) => Ok(len.filter(|len| offset + len <= size)),
+		Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+
+/// Reads a candidate image, tracking how far into it it has read.
+struct Reader<'a> {
+	inner: &'a mut BufReader<File>,
+	pos: u64,
+}
+
+impl Reader<'_> {
+	fn byte(&mut self) -> std::io::Result<u8> {
+		let mut byte = [0];
+		self.inner.read_exact(&mut byte)?;
+		self.pos += 1;
+		Ok(byte[0])
+	}
+
+	fn u16(&mut self) -> std::io::Result<u16> {
+		Ok(u16::from_be_bytes([self.byte()?, self.byte()?]))
+	}
+
+	fn u32(&mut self) -> std::io::Result<u32> {
+		Ok(u32::from_be_bytes([self.byte()?, self.byte()?, self.byte()?, self.byte()?]))
+	}
+
+	fn skip(&mut self, len: u64) -> std::io::Result<()> {
+		self.inner.seek_relative(len as i64)?;
+		self.pos += len;
+		Ok(())
+	}
+}
+
+
+/// The length of a JPEG from its SOI marker to its EOI marker.  Segments are skipped by their lengths, so that the EOI
+/// of an embedded thumbnail doesn't end the image, and entropy-coded data is scanned for the next marker.
+fn jpeg_len(reader: &mut Reader) -> std::io::Result<Option<u64>> {
+	reader.skip(2)?;
+	let mut marker = None;
+	let mut scanned = false;
+
+	while reader.pos <= MAX_LEN {
+		let mut code = match marker.take() {
+			Some(code) => code,
+			None if reader.byte()? == 0xFF => reader.byte()?,
+			None => return Ok(None),
+		};
+		while code == 0xFF {
+			code = reader.byte()?;
+		}
+
+		match code {
+			0xD9 => return Ok(scanned.then_some(reader.pos)),
+			0xD0..=0xD7 | 0x01 => continue,
+			0x00 | 0xD8 => return Ok(None),
+			_ => {},
+		}
+
+		let len = reader.u16()?;
+		if len < 2 {
+			return Ok(None);
+		}
+		reader.skip(len as u64 - 2)?;
+
+		// Start of scan: the entropy-coded data runs until a marker other than a restart marker or stuffed 0xFF
+		if code == 0xDA {
+			scanned = true;
+			marker = loop {
+				if reader.pos > MAX_LEN {
+					return Ok(None);
+				}
+				if reader.byte()? != 0xFF {
+					continue;
+				}
+
+				let mut code = reader.byte()?;
+				while code == 0xFF {
+					code = reader.byte()?;
+				}
+				if !matches!(code, 0x00 | 0xD0..=0xD7) {
+					break Some(code);
+				}
+			};
+		}
+	}
+
+	Ok(None)
+}
+
+
+/// The length of a PNG from its signature to the end of its IEND chunk.
+fn png_len(reader: &mut Reader) -> std::io::Result<Option<u64>> {
+	reader.skip(PNG_SIGNATURE.len() as u64)?;
+
+	while reader.pos <= MAX_LEN {
+		let len = reader.u32()?;
+		let kind = reader.u32()?.to_be_bytes();
+		if len > i32::MAX as u32 || !kind.iter().all(u8::is_ascii_alphabetic) {
+			return Ok(None);
+		}
+
+		// Data and CRC
+		reader.skip(len as u64 + 4)?;
+		if &kind == b"IEND" {
+			return Ok(Some(reader.pos));
+		}
+	}
+
+	Ok(None)
+}
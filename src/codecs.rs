@@ -0,0 +1,193 @@
+//! Image decoding, including the optional decoders for formats the image crate doesn't handle.
+use anyhow::Context;
+use image::{io::Reader as ImageReader, DynamicImage, ImageError};
+use std::{fmt, io::Cursor};
+
+
+/// The image's format isn't supported by this build, as opposed to the image being corrupt.
+#[derive(Debug)]
+pub struct UnsupportedFormat(pub String);
+
+impl fmt::Display for UnsupportedFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Unsupported format: {}", self.0)
+	}
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+
+/// A decoded image.
+pub struct Decoded {
+	pub image: DynamicImage,
+	/// Whether the decoder already rotated the image into its display orientation.
+	/// HEIC and JPEG XL carry orientation in the container, which their decoders apply.
+	pub oriented: bool,
+}
+
+
+/// Decode an encoded image of any supported format.
+pub fn decode(data: &[u8]) -> anyhow::Result<Decoded> {
+	match sniff_format(data) {
+		#[cfg(feature = "heic")]
+		Some("HEIC") => return decode_heic(data).map(|image| Decoded { image, oriented: true }),
+		#[cfg(feature = "jxl")]
+		Some("JPEG XL") => return decode_jxl(data).map(|image| Decoded { image, oriented: true }),
+		#[cfg(feature = "svg")]
+		Some("SVG") => return decode_svg(data).map(|image| Decoded { image, oriented: true }),
+		Some(format) => return Err(UnsupportedFormat(format!("{} (not enabled in this build)", format)).into()),
+		None => (),
+	}
+
+	let image = ImageReader::new(Cursor::new(data))
+		.with_guessed_format()
+		.context("Error guessing image format")?
+		.decode()
+		.map_err(|err| match err {
+			ImageError::Unsupported(err) => anyhow::Error::new(UnsupportedFormat(err.to_string())),
+			err => anyhow::Error::new(err).context("Error decoding image"),
+		})?;
+
+	Ok(Decoded { image, oriented: false })
+}
+
+
+/// Scaled JPEGs are decoded to at least this many pixels along one side.
+#[cfg(feature = "jpeg")]
+const SCALED_JPEG_SIZE: u16 = 256;
+
+
+/// Decode an encoded image like `decode`, except that large JPEGs are scaled down by 1/2, 1/4 or 1/8 as they are decoded,
+/// to no less than `SCALED_JPEG_SIZE` pixels along one side.  Far faster for photos, whose detail is lost in hashing.
+pub fn decode_scaled(data: &[u8]) -> anyhow::Result<Decoded> {
+	#[cfg(feature = "jpeg")]
+	if data.starts_with(&[0xff, 0xd8, 0xff]) {
+		if let Some(image) = decode_jpeg_scaled(data) {
+			return Ok(Decoded { image, oriented: false });
+		}
+	}
+
+	decode(data)
+}
+
+
+/// Decode a JPEG scaled down, or return None if it is too small to scale or isn't 8 bit grayscale or RGB, leaving it to
+/// the image crate's decoder.
+#[cfg(feature = "jpeg")]
+fn decode_jpeg_scaled(data: &[u8]) -> Option<DynamicImage> {
+	use jpeg_decoder::{Decoder, PixelFormat};
+
+	let mut decoder = Decoder::new(Cursor::new(data));
+	decoder.read_info().ok()?;
+	let info = decoder.info()?;
+	let (width, height) = decoder.scale(SCALED_JPEG_SIZE, SCALED_JPEG_SIZE).ok()?;
+	if (width, height) == (info.width, info.height) {
+		return None;
+	}
+
+	let (width, height) = (width as u32, height as u32);
+	match info.pixel_format {
+		PixelFormat::L8 => image::GrayImage::from_raw(width, height, decoder.decode().ok()?).map(DynamicImage::ImageLuma8),
+		PixelFormat::RGB24 => image::RgbImage::from_raw(width, height, decoder.decode().ok()?).map(DynamicImage::ImageRgb8),
+		PixelFormat::L16 | PixelFormat::CMYK32 => None,
+	}
+}
+
+
+/// Recognize the formats that need one of the optional decoders.
+fn sniff_format(data: &[u8]) -> Option<&'static str> {
+	const HEIC_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1"];
+
+	if data.starts_with(&[0xff, 0x0a]) || data.starts_with(b"\x00\x00\x00\x0cJXL \x0d\x0a\x87\x0a") {
+		return Some("JPEG XL");
+	}
+
+	if data.len() >= 12 && &data[4..8] == b"ftyp" && HEIC_BRANDS.contains(&&data[8..12]) {
+		return Some("HEIC");
+	}
+
+	// SVGZ is only recognized when it can be decoded, as gzip on its own says nothing about the contents
+	if cfg!(feature = "svg") && data.starts_with(&[0x1f, 0x8b]) {
+		return Some("SVG");
+	}
+
+	let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+	let head = head.trim_start_matches('\u{feff}').trim_start();
+	if head.starts_with('<') && head.contains("<svg") {
+		return Some("SVG");
+	}
+
+	None
+}
+
+
+#[cfg(feature = "heic")]
+fn decode_heic(data: &[u8]) -> anyhow::Result<DynamicImage> {
+	use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+	let context = HeifContext::read_from_bytes(data).context("Error decoding image")?;
+	let handle = context.primary_image_handle().context("Error decoding image")?;
+	let image = LibHeif::new()
+		.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+		.context("Error decoding image")?;
+
+	let plane = image.planes().interleaved.context("Error decoding image: no interleaved plane")?;
+	let row_len = plane.width as usize * 3;
+	let pixels = plane.data.chunks(plane.stride).flat_map(|row| &row[..row_len]).copied().collect();
+
+	image::RgbImage::from_raw(plane.width, plane.height, pixels)
+		.map(DynamicImage::ImageRgb8)
+		.context("Error decoding image: truncated plane")
+}
+
+
+#[cfg(feature = "jxl")]
+fn decode_jxl(data: &[u8]) -> anyhow::Result<DynamicImage> {
+	use jxl_oxide::{JxlImage, PixelFormat};
+
+	let image = JxlImage::builder()
+		.read(Cursor::new(data))
+		.map_err(|err| anyhow::anyhow!(err))
+		.context("Error decoding image")?;
+	let render = image.render_frame(0).map_err(|err| anyhow::anyhow!(err)).context("Error decoding image")?;
+
+	let mut stream = render.stream();
+	let (width, height) = (stream.width(), stream.height());
+	let mut pixels = vec![0u8; width as usize * height as usize * stream.channels() as usize];
+	stream.write_to_buffer(&mut pixels);
+
+	let image = match image.pixel_format() {
+		PixelFormat::Gray => image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+		PixelFormat::Graya => image::GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+		PixelFormat::Rgb => image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+		PixelFormat::Rgba => image::RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+		PixelFormat::Cmyk | PixelFormat::Cmyka => return Err(UnsupportedFormat("CMYK JPEG XL".to_string()).into()),
+	};
+
+	image.context("Error decoding image: unexpected buffer size")
+}
+
+
+/// Number of pixels along the longer side of a rasterized SVG.
+#[cfg(feature = "svg")]
+const SVG_SIZE: u32 = 512;
+
+
+/// Rasterize an SVG so that its longer side is `SVG_SIZE` pixels, regardless of its nominal size.
+#[cfg(feature = "svg")]
+fn decode_svg(data: &[u8]) -> anyhow::Result<DynamicImage> {
+	use resvg::{tiny_skia, usvg};
+
+	let tree = usvg::Tree::from_data(data, &usvg::Options::default()).context("Error decoding image")?;
+	let size = tree.size();
+	let scale = SVG_SIZE as f32 / size.width().max(size.height());
+	let width = ((size.width() * scale).round() as u32).max(1);
+	let height = ((size.height() * scale).round() as u32).max(1);
+
+	let mut pixmap = tiny_skia::Pixmap::new(width, height).context("Error decoding image: invalid size")?;
+	resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+	image::RgbaImage::from_raw(width, height, pixmap.take_demultiplied())
+		.map(DynamicImage::ImageRgba8)
+		.context("Error decoding image: unexpected buffer size")
+}
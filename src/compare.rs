@@ -0,0 +1,217 @@
+//! Compares the inputs of an output file by a weighted combination of the distances between several of their hashes.
+use clap::ValueEnum;
+use std::{collections::BTreeSet, fmt, path::PathBuf, str::FromStr};
+
+use crate::{
+	cache::{read_result_file, Entry},
+	index::BkTree,
+	settings::{Algorithm, Settings},
+};
+use tracing::{error, info};
+
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+	/// Output file hashed with every algorithm in the combination, e.g. with `--algorithm phash,dhash`.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Weighted sum of the distances between each algorithm's hashes to compare by, e.g. `0.6*phash+0.4*dhash`.
+	#[arg(long, value_name = "EXPR")]
+	combine: Combination,
+
+	/// Maximum combined distance between the inputs of listed pairs.
+	#[arg(short, long, default_value_t = 4.0)]
+	threshold: f64,
+
+	/// Two inputs of the output file to compare.  Without them, every pair within the threshold is listed.
+	#[arg(num_args = 2, value_names = ["A", "B"])]
+	paths: Vec<PathBuf>,
+}
+
+
+/// A weighted sum of distances between the hashes of several algorithms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combination(Vec<(f64, Algorithm)>);
+
+impl FromStr for Combination {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let terms = s
+			.split('+')
+			.map(|term| {
+				let (weight, algorithm) = match term.split_once('*') {
+					Some((weight, algorithm)) => (weight.trim().parse::<f64>().map_err(|_| format!("invalid weight: {}", weight.trim()))?, algorithm),
+					None => (1.0, term),
+				};
+				let algorithm = Algorithm::from_str(algorithm.trim(), false).map_err(|_| format!("unknown algorithm: {}", algorithm.trim()))?;
+
+				if !(weight.is_finite() && weight > 0.0) {
+					return Err(format!("weights must be positive: {}", term.trim()));
+				}
+
+				Ok((weight, algorithm))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Combination(terms))
+	}
+}
+
+impl fmt::Display for Combination {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let terms = self.0.iter().map(|(weight, algorithm)| format!("{}*{}", weight, algorithm)).collect::<Vec<_>>();
+		write!(f, "{}", terms.join("+"))
+	}
+}
+
+impl Combination {
+	/// The combined distance between two entries' hashes, in the order of the combination's terms.
+	fn distance(&self, a: &[u64], b: &[u64]) -> f64 {
+		self.0.iter().zip(a.iter().zip(b)).map(|((weight, _), (a, b))| weight * (a ^ b).count_ones() as f64).sum()
+	}
+}
+
+
+/// The hashes of an entry for each algorithm, in the order given, or None for trivial entries.
+pub fn hashes(entry: &Entry, stored: &[Algorithm], algorithms: &[Algorithm]) -> Option<Vec<u64>> {
+	algorithms
+		.iter()
+		.map(|algorithm| {
+			// The first algorithm's hash is the entry's own, the others are in `name=` columns
+			if stored.first() == Some(algorithm) {
+				entry.phash
+			} else {
+				entry.extra.iter().find_map(|extra| extra.strip_prefix(&format!("{}=", algorithm))?.parse().ok())
+			}
+		})
+		.collect()
+}
+
+
+/// The best match between two inputs: their combined distance and the distance of each algorithm.
+struct Match {
+	distance: f64,
+	distances: Vec<u32>,
+}
+
+
+/// Print each pair as `path\tpath\tdistance` with the combined distance, followed by the distance of each algorithm's
+/// hashes (e.g. `phash=3\tdhash=5`) for the entries that are closest.  Of inputs hashed with `--tiles`, only the
+/// entries of whole images are compared.
+pub fn run(args: CompareArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	let stored = Settings::from_metadata(&results.metadata)
+		.unwrap_or_else(|err| {
+			error!("Error: can't compare {}: {}", args.output.display(), err);
+			std::process::exit(1);
+		})
+		.algorithms;
+	let algorithms = args.combine.0.iter().map(|(_, algorithm)| *algorithm).collect::<Vec<_>>();
+	if let Some(missing) = algorithms.iter().find(|algorithm| !stored.contains(algorithm)) {
+		error!("Error: {} has no {} hashes", args.output.display(), missing);
+		std::process::exit(1);
+	}
+
+	// Every input's comparable entries, by path
+	let mut paths = results.hashes.keys().collect::<Vec<_>>();
+	paths.sort_unstable();
+	let entries = paths
+		.iter()
+		.map(|path| {
+			results.hashes[*path]
+				.iter()
+				.filter(|entry| entry.extra.iter().all(|extra| extra.strip_prefix("tile=").is_none_or(|tile| tile == "full")))
+				.filter_map(|entry| hashes(entry, &stored, &algorithms))
+				.collect::<Vec<_>>()
+		})
+		.collect::<Vec<_>>();
+
+	let best = |a: usize, b: usize| {
+		entries[a]
+			.iter()
+			.flat_map(|x| entries[b].iter().map(move |y| (x, y)))
+			.map(|(x, y)| Match {
+				distance: args.combine.distance(x, y),
+				distances: x.iter().zip(y).map(|(x, y)| (x ^ y).count_ones()).collect(),
+			})
+			.min_by(|x, y| x.distance.total_cmp(&y.distance))
+	};
+
+	let print = |a: &PathBuf, b: &PathBuf, found: &Match| {
+		let mut line = format!("{}\t{}\t{:.2}", a.display(), b.display(), found.distance);
+		for (algorithm, distance) in algorithms.iter().zip(&found.distances) {
+			line.push_str(&format!("\t{}={}", algorithm, distance));
+		}
+		println!("{}", line);
+	};
+
+	if let [a, b] = &args.paths[..] {
+		let index = |path: &PathBuf| {
+			paths.binary_search(&path).unwrap_or_else(|_| {
+				error!("Error: {} isn't in {}", path.display(), args.output.display());
+				std::process::exit(1);
+			})
+		};
+
+		match best(index(a), index(b)) {
+			Some(found) => print(a, b, &found),
+			None => {
+				error!("Error: {} and {} have no hashes to compare", a.display(), b.display());
+				std::process::exit(1);
+			},
+		}
+		return;
+	}
+
+	// The combined distance is at least the total weight times the smallest of the distances, so every pair within the
+	// threshold has an algorithm whose hashes are within the threshold divided by the total weight
+	let total_weight = args.combine.0.iter().map(|(weight, _)| weight).sum::<f64>();
+	let candidate_threshold = (args.threshold / total_weight).floor().max(0.0) as u32;
+
+	let mut candidates = BTreeSet::new();
+	for i in 0..algorithms.len() {
+		let mut tree = BkTree::new();
+		for (index, hashes) in entries.iter().enumerate() {
+			for hashes in hashes {
+				tree.insert(hashes[i], index);
+			}
+		}
+
+		for (index, hashes) in entries.iter().enumerate() {
+			for hashes in hashes {
+				for (&other, _) in tree.find(hashes[i], candidate_threshold) {
+					if other > index {
+						candidates.insert((index, other));
+					}
+				}
+			}
+		}
+	}
+
+	let mut pairs = 0;
+	for (a, b) in candidates {
+		if let Some(found) = best(a, b).filter(|found| found.distance <= args.threshold) {
+			print(paths[a], paths[b], &found);
+			pairs += 1;
+		}
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"compare",
+		serde_json::json!({
+			"output": args.output,
+			"combine": args.combine.to_string(),
+			"threshold": args.threshold,
+			"pairs": pairs,
+		}),
+	);
+
+	info!("Found {} pairs within {} of each other among {} paths", pairs, args.threshold, paths.len());
+}
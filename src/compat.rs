@@ -0,0 +1,248 @@
+//! Reimplementations of other perceptual hashing libraries, for comparing against hashes they computed.
+use clap::ValueEnum;
+use image::DynamicImage;
+
+
+/// A library whose hashes are reproduced instead of computing this tool's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compat {
+	/// `ph_dct_imagehash` of the pHash C library.
+	#[value(name = "phash-org")]
+	PhashOrg,
+	/// `imagehash.phash()` of the Python imagehash package, with its default hash size.
+	Imagehash,
+}
+
+impl Compat {
+	pub fn hash(self, img: &DynamicImage) -> u64 {
+		match self {
+			Compat::PhashOrg => phash_org(img),
+			Compat::Imagehash => imagehash(img),
+		}
+	}
+
+	/// Extra columns written for each hash: imagehash's own serialization.
+	pub fn columns(self, hash: u64) -> Vec<String> {
+		match self {
+			Compat::PhashOrg => Vec::new(),
+			Compat::Imagehash => vec![format!("hex={:016x}", hash)],
+		}
+	}
+}
+
+
+/// Size of the box filter pHash smooths images with.
+const PHASH_ORG_FILTER: usize = 7;
+
+
+/// `ph_dct_imagehash`, step for step: CImg's YCbCr luma, a 7x7 box filter with clamped edges, nearest neighbor
+/// resizing to 32x32, a single precision DCT accumulated in double precision, and bits set where the 8x8 block of
+/// coefficients below and to the right of the DC term exceeds its median, in row order.
+///
+/// pHash's handling of images with an alpha channel reads past the image it is given; here the alpha channel is
+/// ignored instead.  Decoders can differ by a level here and there in lossy formats, so JPEG hashes may differ from
+/// those of a pHash build using another decoder by a bit or two.
+fn phash_org(img: &DynamicImage) -> u64 {
+	let (width, height) = (img.width() as usize, img.height() as usize);
+
+	// CImg::RGBtoYCbCr, channel 0, truncated back to 8 bits; grayscale images are used as is
+	let luma = if img.color().has_color() {
+		img.to_rgb8().pixels().map(|p| ((66 * p[0] as u32 + 129 * p[1] as u32 + 25 * p[2] as u32 + 128) / 256 + 16) as f32).collect::<Vec<_>>()
+	} else {
+		img.to_luma8().into_raw().into_iter().map(f32::from).collect()
+	};
+
+	// An unnormalized box filter; all sums are integers that f32 holds exactly, so the order doesn't matter
+	let radius = (PHASH_ORG_FILTER / 2) as isize;
+	let at = |x: isize, y: isize| luma[y.clamp(0, height as isize - 1) as usize * width + x.clamp(0, width as isize - 1) as usize];
+	let smoothed = |x: usize, y: usize| {
+		let mut sum = 0.0;
+		for dy in -radius..=radius {
+			for dx in -radius..=radius {
+				sum += at(x as isize + dx, y as isize + dy);
+			}
+		}
+		sum
+	};
+
+	// Nearest neighbor, sampling pixel floor(x * width / 32)
+	let mut small = [[0f32; 32]; 32];
+	for (y, row) in small.iter_mut().enumerate() {
+		for (x, value) in row.iter_mut().enumerate() {
+			*value = smoothed(x * width / 32, y * height / 32);
+		}
+	}
+
+	let dct = phash_org_dct_matrix();
+	let mut dct_t = [[0f32; 32]; 32];
+	for (y, row) in dct.iter().enumerate() {
+		for (x, value) in row.iter().enumerate() {
+			dct_t[x][y] = *value;
+		}
+	}
+	let coefficients = multiply(&multiply(&dct, &small), &dct_t);
+
+	let block = coefficients[1..=8].iter().flat_map(|row| row[1..=8].iter().copied()).collect::<Vec<_>>();
+	let mut sorted = block.clone();
+	sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	let median = (sorted[32] + sorted[31]) / 2.0;
+
+	block.iter().enumerate().filter(|(_, v)| **v > median).fold(0, |hash, (i, _)| hash | 1 << i)
+}
+
+
+/// `ph_dct_matrix(32)`, computed in double precision and stored in single.
+fn phash_org_dct_matrix() -> [[f32; 32]; 32] {
+	let n = 32.0f64;
+	let c1 = (2.0 / n).sqrt() as f32;
+
+	let mut matrix = [[(1.0 / n.sqrt()) as f32; 32]; 32];
+	for (y, row) in matrix.iter_mut().enumerate().skip(1) {
+		for (x, value) in row.iter_mut().enumerate() {
+			*value = (c1 as f64 * (std::f64::consts::PI / (2.0 * n) * y as f64 * (2 * x + 1) as f64).cos()) as f32;
+		}
+	}
+
+	matrix
+}
+
+
+/// CImg's matrix product: single precision products summed in double precision.
+fn multiply(a: &[[f32; 32]; 32], b: &[[f32; 32]; 32]) -> [[f32; 32]; 32] {
+	let mut product = [[0f32; 32]; 32];
+	for (row, a_row) in product.iter_mut().zip(a) {
+		for (col, value) in row.iter_mut().enumerate() {
+			*value = (0..32).map(|k| (a_row[k] * b[k][col]) as f64).sum::<f64>() as f32;
+		}
+	}
+
+	product
+}
+
+
+/// Fixed point precision of Pillow's resampling coefficients.
+const PILLOW_PRECISION_BITS: u32 = 32 - 8 - 2;
+
+
+/// `imagehash.phash()`: Pillow's `convert('L')` and Lanczos `resize` to 32x32, a double precision DCT-II of the rows
+/// and then the columns, and bits set where the 8x8 block of lowest frequencies (including the DC term) exceeds its
+/// median.  Bits are in row order from the most significant, so the hash written as 16 hex digits is imagehash's
+/// `str()` of it.
+///
+/// scipy computes the DCT with FFTs, so a coefficient within rounding error of the median could in principle land on
+/// the other side of it.  As with `phash-org`, lossy formats decoded differently than by Pillow's decoders can differ.
+fn imagehash(img: &DynamicImage) -> u64 {
+	// Pillow's L24 luma, rounded in 16 bit fixed point
+	let (width, height) = (img.width() as usize, img.height() as usize);
+	let luma = if img.color().has_color() {
+		img.to_rgb8().pixels().map(|p| ((19595 * p[0] as u32 + 38470 * p[1] as u32 + 7471 * p[2] as u32 + 0x8000) >> 16) as u8).collect::<Vec<_>>()
+	} else {
+		img.to_luma8().into_raw()
+	};
+
+	// Horizontal pass first, then vertical, each rounding back to 8 bits; dimensions that are already right are skipped
+	let temp = if width == 32 {
+		luma
+	} else {
+		let horizontal = pillow_coefficients(width, 32);
+		(0..height)
+			.flat_map(|y| {
+				let luma = &luma;
+				horizontal
+					.iter()
+					.map(move |(start, weights)| pillow_convolve(weights.iter().enumerate().map(|(i, weight)| (luma[y * width + start + i], *weight))))
+			})
+			.collect()
+	};
+
+	let mut pixels = [[0f64; 32]; 32];
+	if height == 32 {
+		for (y, row) in pixels.iter_mut().enumerate() {
+			for (x, pixel) in row.iter_mut().enumerate() {
+				*pixel = temp[y * 32 + x] as f64;
+			}
+		}
+	} else {
+		for (row, (start, weights)) in pixels.iter_mut().zip(pillow_coefficients(height, 32)) {
+			for (x, pixel) in row.iter_mut().enumerate() {
+				*pixel = pillow_convolve(weights.iter().enumerate().map(|(i, weight)| (temp[(start + i) * 32 + x], *weight))) as f64;
+			}
+		}
+	}
+
+	// scipy.fftpack.dct along axis 0, then axis 1: y[k] = 2 * sum(x[n] * cos(pi * k * (2n + 1) / 2N))
+	let dct = |values: [f64; 32]| -> [f64; 32] {
+		std::array::from_fn(|k| {
+			2.0 * values.iter().enumerate().map(|(n, v)| v * (std::f64::consts::PI * k as f64 * (2 * n + 1) as f64 / 64.0).cos()).sum::<f64>()
+		})
+	};
+	let mut columns = [[0f64; 32]; 32];
+	for x in 0..32 {
+		let column = dct(std::array::from_fn(|y| pixels[y][x]));
+		for y in 0..32 {
+			columns[y][x] = column[y];
+		}
+	}
+	let coefficients = columns.map(dct);
+
+	let block = coefficients[..8].iter().flat_map(|row| row[..8].iter().copied()).collect::<Vec<_>>();
+	let mut sorted = block.clone();
+	sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	let median = (sorted[31] + sorted[32]) / 2.0;
+
+	block.iter().enumerate().filter(|(_, v)| **v > median).fold(0, |hash, (i, _)| hash | 1 << (63 - i))
+}
+
+
+/// Pillow's `precompute_coeffs` for Lanczos and `normalize_coeffs_8bpc`: the first source pixel and fixed point weights
+/// of each output pixel.
+fn pillow_coefficients(in_size: usize, out_size: usize) -> Vec<(usize, Vec<i32>)> {
+	fn sinc(x: f64) -> f64 {
+		if x == 0.0 {
+			return 1.0;
+		}
+		let x = x * std::f64::consts::PI;
+		x.sin() / x
+	}
+	let lanczos = |x: f64| if (-3.0..3.0).contains(&x) { sinc(x) * sinc(x / 3.0) } else { 0.0 };
+
+	let scale = in_size as f64 / out_size as f64;
+	let filter_scale = scale.max(1.0);
+	let support = 3.0 * filter_scale;
+	let inverse_scale = 1.0 / filter_scale;
+
+	(0..out_size)
+		.map(|xx| {
+			let center = (xx as f64 + 0.5) * scale;
+			let min = ((center - support + 0.5) as i64).max(0) as usize;
+			let max = ((center + support + 0.5) as i64).min(in_size as i64) as usize;
+
+			let weights = (min..max).map(|x| lanczos((x as f64 - center + 0.5) * inverse_scale)).collect::<Vec<_>>();
+			let total = weights.iter().sum::<f64>();
+			let weights = weights
+				.into_iter()
+				.map(|w| if total != 0.0 { w / total } else { w })
+				.map(|w| {
+					let w = w * (1 << PILLOW_PRECISION_BITS) as f64;
+					(if w < 0.0 { -0.5 + w } else { 0.5 + w }) as i32
+				})
+				.collect();
+
+			(min, weights)
+		})
+		.collect()
+}
+
+
+/// One output pixel of Pillow's 8 bit resampling, which accumulates in 32 bits.
+fn pillow_convolve(taps: impl Iterator<Item = (u8, i32)>) -> u8 {
+	let sum = taps.fold(1i32 << (PILLOW_PRECISION_BITS - 1), |sum, (pixel, weight)| sum.wrapping_add((pixel as i32).wrapping_mul(weight)));
+
+	if sum >= 1 << PILLOW_PRECISION_BITS << 8 {
+		255
+	} else if sum <= 0 {
+		0
+	} else {
+		(sum >> PILLOW_PRECISION_BITS) as u8
+	}
+}
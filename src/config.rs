@@ -0,0 +1,164 @@
+//! Defaults for command line options read from a TOML file (`--config`), so that long invocations can be shared between
+//! machines.  Keys are the long names of options, e.g. `algorithm = "dhash"` or `threads = 8`, and apply to every
+//! command that has the option; tables named after a subcommand, e.g. `[dedupe]`, apply only to it.  Options given on
+//! the command line override the file.
+use clap::{error::ErrorKind, Arg, Command};
+use std::{
+	ffi::OsString,
+	path::{Path, PathBuf},
+};
+
+
+/// Name of the config file looked for in the current directory.
+const LOCAL_NAME: &str = "phash.toml";
+
+
+/// Add `--config` to the command, and the defaults from the config file given with it or else found in the default
+/// locations.  Exits with a usage error if the file can't be read or sets an unknown option.
+pub fn apply(command: Command) -> Command {
+	let mut command = command.arg(
+		Arg::new("config")
+			.long("config")
+			.global(true)
+			.value_name("FILE")
+			.value_parser(clap::value_parser!(PathBuf))
+			.help(format!(
+				"Read defaults for options from a TOML file.  Without it, {} in the current directory or phash-hasher/config.toml in \
+				 the user's config directory is read if it exists.  Options given on the command line override the file",
+				LOCAL_NAME
+			)),
+	);
+
+	let path = match explicit_path(std::env::args_os()) {
+		Some(path) => path,
+		None => match default_paths().into_iter().find(|path| path.is_file()) {
+			Some(path) => path,
+			None => return command,
+		},
+	};
+
+	match read(&path).and_then(|table| set_defaults(command.clone(), &table)) {
+		Ok(configured) => configured,
+		Err(err) => command.error(ErrorKind::InvalidValue, format!("Error reading config file {}: {}", path.display(), err)).exit(),
+	}
+}
+
+
+/// The value of `--config` on the command line, found before it is parsed.
+fn explicit_path(args: impl Iterator<Item = OsString>) -> Option<PathBuf> {
+	let mut args = args.skip(1);
+
+	while let Some(arg) = args.next() {
+		if arg == "--config" {
+			return args.next().map(PathBuf::from);
+		}
+		if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+			return Some(PathBuf::from(path));
+		}
+		if arg == "--" {
+			break;
+		}
+	}
+
+	None
+}
+
+
+fn default_paths() -> Vec<PathBuf> {
+	let mut paths = vec![PathBuf::from(LOCAL_NAME)];
+
+	let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+		.or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+	if let Some(dir) = config_dir {
+		paths.push(dir.join("phash-hasher").join("config.toml"));
+	}
+
+	paths
+}
+
+
+fn read(path: &Path) -> Result<toml::Table, String> {
+	let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+	contents.parse::<toml::Table>().map_err(|err| err.to_string())
+}
+
+
+/// Set the keys of a table as defaults of the command and its subcommands, then those of its subcommand tables.
+fn set_defaults(mut command: Command, table: &toml::Table) -> Result<Command, String> {
+	let subcommands = command.get_subcommands().map(|subcommand| subcommand.get_name().to_string()).collect::<Vec<_>>();
+
+	for (key, value) in table {
+		if value.is_table() {
+			if !subcommands.contains(key) {
+				return Err(format!("unknown subcommand [{}]", key));
+			}
+			continue;
+		}
+
+		let values = values(key, value)?;
+		let mut found = false;
+		command = set_default(command, &key.replace('_', "-"), &values, &mut found);
+		if !found {
+			return Err(format!("unknown option {}", key));
+		}
+	}
+
+	for (key, value) in table {
+		if let toml::Value::Table(table) = value {
+			let mut result = Ok(());
+			command = command.mut_subcommand(key, |subcommand| match set_defaults(subcommand.clone(), table) {
+				Ok(subcommand) => subcommand,
+				Err(err) => {
+					result = Err(format!("[{}]: {}", key, err));
+					subcommand
+				},
+			});
+			result?;
+		}
+	}
+
+	Ok(command)
+}
+
+
+/// Set the default of the option with a long name on a command and every subcommand that has it.
+fn set_default(mut command: Command, long: &str, values: &[String], found: &mut bool) -> Command {
+	let id = command.get_arguments().find(|arg| arg.get_long() == Some(long)).map(|arg| arg.get_id().clone());
+	if let Some(id) = id {
+		// Clap checks required options before filling in defaults, so those the file gives are no longer required
+		command = command.mut_arg(id, |arg| {
+			arg.default_values(values.to_vec()).required(false).required_unless_present(clap::builder::Resettable::Reset)
+		});
+		*found = true;
+	}
+
+	let subcommands = command.get_subcommands().map(|subcommand| subcommand.get_name().to_string()).collect::<Vec<_>>();
+	for name in subcommands {
+		command = command.mut_subcommand(name, |subcommand| set_default(subcommand, long, values, found));
+	}
+
+	command
+}
+
+
+/// The command line values of a key's value: a string, number or boolean, or an array of them for options that take
+/// several.
+fn values(key: &str, value: &toml::Value) -> Result<Vec<String>, String> {
+	match value {
+		toml::Value::Array(values) => values.iter().map(|value| scalar(key, value)).collect(),
+		value => scalar(key, value).map(|value| vec![value]),
+	}
+}
+
+
+fn scalar(key: &str, value: &toml::Value) -> Result<String, String> {
+	match value {
+		toml::Value::String(value) => Ok(value.clone()),
+		toml::Value::Integer(value) => Ok(value.to_string()),
+		toml::Value::Float(value) => Ok(value.to_string()),
+		toml::Value::Boolean(value) => Ok(value.to_string()),
+		_ => Err(format!("unsupported value for {}", key)),
+	}
+}
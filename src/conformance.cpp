@@ -0,0 +1,15 @@
+// Calls libpHash's ph_dct_imagehash, which is declared with a C++ reference, through a C function for conformance.rs.
+#include <stdint.h>
+
+#include <pHash.h>
+
+
+extern "C" int hasher_ph_dct_imagehash(const char *file, uint64_t *hash) {
+	ulong64 result = 0;
+	if (ph_dct_imagehash(file, result) < 0) {
+		return -1;
+	}
+
+	*hash = result;
+	return 0;
+}
@@ -0,0 +1,158 @@
+//! Compares the hashes `--compat` computes with those of the libraries it reproduces, run on the same images, to back
+//! claims that hashes from either can be mixed.
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::{
+	ffi::CString,
+	io::{BufRead, BufReader, Write},
+	os::raw::{c_char, c_int},
+	path::PathBuf,
+	process::{Command, Stdio},
+};
+use tracing::{error, info};
+
+use crate::{
+	compat::Compat,
+	hasher::Hasher,
+	progress,
+	read_input_list,
+	settings::Settings,
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ConformanceArgs {
+	/// File listing the images to compare on, one path per line.  If "-", the list is read from stdin.
+	#[arg(short, long)]
+	input: String,
+
+	/// Libraries to compare with.
+	#[arg(long, value_enum, value_delimiter = ',', default_value = "phash-org,imagehash")]
+	against: Vec<Compat>,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+#[link(name = "conformance", kind = "static")]
+#[link(name = "pHash")]
+#[link(name = "stdc++")]
+extern "C" {
+	/// `ph_dct_imagehash` of the linked libpHash, through the wrapper in `conformance.cpp`.
+	fn hasher_ph_dct_imagehash(file: *const c_char, hash: *mut u64) -> c_int;
+}
+
+
+/// Reads image paths on stdin and prints `imagehash.phash()` of each as hex, or an empty line if it fails.
+const IMAGEHASH_SCRIPT: &str = "import sys, imagehash, PIL.Image
+for line in sys.stdin:
+    try:
+        print(imagehash.phash(PIL.Image.open(line.rstrip('\\n'))), flush=True)
+    except Exception:
+        print(flush=True)
+";
+
+
+/// The reference hashes of some images, None for those it couldn't hash.
+fn reference_hashes(compat: Compat, paths: &[PathBuf]) -> anyhow::Result<Vec<Option<u64>>> {
+	match compat {
+		// libpHash isn't known to be thread safe
+		Compat::PhashOrg => Ok(paths
+			.iter()
+			.map(|path| {
+				let file = CString::new(path.to_str()?).ok()?;
+				let mut hash = 0;
+				(unsafe { hasher_ph_dct_imagehash(file.as_ptr(), &mut hash) } == 0).then_some(hash)
+			})
+			.collect()),
+		Compat::Imagehash => {
+			let mut child = Command::new("python3").args(["-c", IMAGEHASH_SCRIPT]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+
+			// Write paths on another thread, so that neither side blocks on a full pipe
+			let mut stdin = child.stdin.take().unwrap();
+			let list = paths.iter().map(|path| format!("{}\n", path.display())).collect::<String>();
+			let writer = std::thread::spawn(move || stdin.write_all(list.as_bytes()));
+
+			let hashes = BufReader::new(child.stdout.take().unwrap())
+				.lines()
+				.map(|line| Ok(u64::from_str_radix(&line?, 16).ok()))
+				.collect::<std::io::Result<Vec<_>>>()?;
+			writer.join().unwrap()?;
+
+			let status = child.wait()?;
+			if !status.success() || hashes.len() != paths.len() {
+				anyhow::bail!("python3 failed ({}); is the imagehash package installed?", status);
+			}
+
+			Ok(hashes)
+		},
+	}
+}
+
+
+/// For each library, print a `MISMATCH\tlibrary\tpath\tours\ttheirs\tdistance` line for each image whose hashes differ,
+/// then `library\tstatistic\tvalue` lines: `compared` images (those both hashed), `identical` hashes, and the `mean`
+/// and `max` distance between them.
+pub fn run(args: ConformanceArgs) {
+	let paths = read_input_list(&args.input).collect::<Vec<_>>();
+	let mut summaries = Vec::new();
+
+	for compat in &args.against {
+		let name = clap::ValueEnum::to_possible_value(compat).unwrap().get_name().to_string();
+		let settings = Settings {
+			compat: Some(*compat),
+			exif_orientation: false,
+			scaled_jpeg: false,
+			..Settings::default()
+		};
+		let hasher = Hasher::new(settings, Source::new(&args.source));
+
+		let ours = paths
+			.par_iter()
+			.progress_with(progress::bar(paths.len() as u64))
+			.map(|path| match hasher.hash(path) {
+				Ok(entries) => entries.first().and_then(|entry| entry.phash),
+				Err(err) => {
+					error!("Error computing phash for {}: {}", path.display(), err);
+					None
+				},
+			})
+			.collect::<Vec<_>>();
+
+		let theirs = reference_hashes(*compat, &paths).unwrap_or_else(|err| {
+			error!("Error running {}: {:#}", name, err);
+			std::process::exit(1);
+		});
+
+		let mut distances = Vec::new();
+		for ((path, ours), theirs) in paths.iter().zip(&ours).zip(&theirs) {
+			let (Some(ours), Some(theirs)) = (ours, theirs) else {
+				continue;
+			};
+
+			let distance = (ours ^ theirs).count_ones();
+			if distance > 0 {
+				println!("MISMATCH\t{}\t{}\t{}\t{}\t{}", name, path.display(), ours, theirs, distance);
+			}
+			distances.push(distance);
+		}
+
+		let identical = distances.iter().filter(|distance| **distance == 0).count();
+		let mean = distances.iter().sum::<u32>() as f64 / distances.len().max(1) as f64;
+		let max = distances.iter().copied().max().unwrap_or(0);
+		println!("{}\tcompared\t{}", name, distances.len());
+		println!("{}\tidentical\t{}", name, identical);
+		println!("{}\tmean\t{:.3}", name, mean);
+		println!("{}\tmax\t{}", name, max);
+
+		info!("{}: {} of {} images hash identically, mean distance {:.3}, max {}", name, identical, distances.len(), mean, max);
+		summaries.push(serde_json::json!({ "library": name, "compared": distances.len(), "identical": identical, "max": max }));
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record("conformance", serde_json::json!({ "input": args.input, "images": paths.len(), "libraries": summaries }));
+	#[cfg(not(feature = "audit"))]
+	let _ = summaries;
+}
@@ -0,0 +1,324 @@
+//! Finds perceptual duplicates among the entries of an output file.
+use rayon::prelude::*;
+use std::{
+	collections::{BTreeMap, HashMap},
+	path::{Path, PathBuf},
+};
+use tracing::{error, info};
+
+use crate::{
+	action::{self, ActionArgs},
+	cache::{read_result_file, Entry},
+	capture::{group_locations, read_capture, Capture, GroupBy},
+	hasher::Hasher,
+	index::BkTree,
+	report,
+	settings::Settings,
+	soft_match::{self, SoftMatchArgs},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct DedupeArgs {
+	/// Output file to search for duplicates.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Maximum Hamming distance between the hashes of duplicates.
+	#[arg(short, long, default_value_t = 4)]
+	threshold: u32,
+
+	/// Compare the EXIF capture time, camera make and model of each pair, reporting the fraction that agree in an
+	/// `exif=` column (`exif=none` when the two don't record any of them in common).
+	#[arg(long)]
+	corroborate_exif: bool,
+
+	/// Also group pairs by where the photos were taken, according to their EXIF GPS coordinates: `geotag:<radius>`,
+	/// e.g. `geotag:100m` or `geotag:1km`.  Pairs are listed by location group with a `geotag=` column holding the
+	/// group, `apart` if the two photos were taken further apart, or `none` if either isn't geotagged.
+	#[arg(long, value_name = "GROUPING")]
+	group_by: Option<GroupBy>,
+
+	/// Print clusters of duplicates instead of pairs, as a JSON object per line with the cluster's `id`, its
+	/// `representative` (the largest file) and its `members` in path order.  Clusters are linked through chains of
+	/// pairs, so not all members need be within the threshold of each other.
+	#[arg(long, conflicts_with_all = ["corroborate_exif", "group_by"])]
+	clusters: bool,
+
+	/// Also write an HTML page showing each cluster of duplicates side by side, with thumbnails, file sizes and
+	/// dimensions, to review before deleting any.  Thumbnails are embedded, so the page stands alone.
+	#[arg(long, value_name = "FILE")]
+	report: Option<PathBuf>,
+
+	#[command(flatten)]
+	soft_match: SoftMatchArgs,
+
+	#[command(flatten)]
+	action: ActionArgs,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+/// A pair of duplicate paths, with the smallest distance between any of their hashes.
+pub struct Pair {
+	pub a: PathBuf,
+	pub b: PathBuf,
+	pub distance: u32,
+	/// For output files hashed with `--tiles`, the region of each image that matched.
+	pub tiles: Option<(String, String)>,
+}
+
+
+/// Find every pair of paths with hashes within `threshold` of each other, ordered by path.
+/// Trivial entries never match anything.  With `--tiles`, a tile only matches the full frame of another image, and
+/// matches between full frames are preferred over those of equal distance involving a tile.
+pub fn find_pairs(hashes: &HashMap<PathBuf, Vec<Entry>>, threshold: u32) -> Vec<Pair> {
+	let mut paths = hashes.keys().collect::<Vec<_>>();
+	paths.sort_unstable();
+
+	let mut tree = BkTree::new();
+	for (index, path) in paths.iter().enumerate() {
+		for entry in &hashes[*path] {
+			if let Some(phash) = entry.phash {
+				tree.insert(phash, (index, tile(entry)));
+			}
+		}
+	}
+
+	// The best match of each pair: distance, number of tiles involved, and their regions
+	let mut pairs = BTreeMap::<_, (u32, usize, _, _)>::new();
+	for (index, path) in paths.iter().enumerate() {
+		for entry in &hashes[*path] {
+			let Some(phash) = entry.phash else {
+				continue;
+			};
+			let tile_a = tile(entry);
+
+			for (&(other, tile_b), distance) in tree.find(phash, threshold) {
+				let tiled = [tile_a, tile_b].iter().filter(|tile| tile.is_some_and(|tile| tile != FULL)).count();
+				if other <= index || tiled > 1 {
+					continue;
+				}
+
+				let candidate = (distance, tiled, tile_a, tile_b);
+				pairs.entry((index, other)).and_modify(|best| *best = (*best).min(candidate)).or_insert(candidate);
+			}
+		}
+	}
+
+	pairs
+		.into_iter()
+		.map(|((a, b), (distance, _, tile_a, tile_b))| Pair {
+			a: paths[a].clone(),
+			b: paths[b].clone(),
+			distance,
+			tiles: tile_a.zip(tile_b).map(|(a, b)| (a.to_string(), b.to_string())),
+		})
+		.collect()
+}
+
+
+/// Group duplicates into clusters: the connected components of the pairs, so members are linked through a chain of
+/// pairs but not necessarily all within the threshold of each other.  Members are ordered by path, clusters by their
+/// first member.
+pub fn clusters(pairs: &[Pair]) -> Vec<Vec<PathBuf>> {
+	let mut paths = pairs.iter().flat_map(|pair| [&pair.a, &pair.b]).collect::<Vec<_>>();
+	paths.sort_unstable();
+	paths.dedup();
+	let index = |path: &PathBuf| paths.binary_search(&path).unwrap();
+
+	// Union-find, each cluster's root being its first member
+	let mut parents = (0..paths.len()).collect::<Vec<_>>();
+	for pair in pairs {
+		let (a, b) = (root(&mut parents, index(&pair.a)), root(&mut parents, index(&pair.b)));
+		parents[a.max(b)] = a.min(b);
+	}
+
+	let mut clusters: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+	for (i, path) in paths.iter().enumerate() {
+		clusters.entry(root(&mut parents, i)).or_default().push((*path).clone());
+	}
+
+	clusters.into_values().collect()
+}
+
+
+/// Whether two members of a cluster are duplicates of each other themselves, rather than only through a chain of pairs:
+/// each has a single entry, which isn't trivial, and their hashes are within `threshold`.
+pub fn within_threshold(hashes: &HashMap<PathBuf, Vec<Entry>>, a: &Path, b: &Path, threshold: u32) -> bool {
+	let hash = |path| match hashes.get(path).map(Vec::as_slice) {
+		Some([entry]) => entry.phash,
+		_ => None,
+	};
+
+	hash(a).zip(hash(b)).is_some_and(|(a, b)| (a ^ b).count_ones() <= threshold)
+}
+
+
+/// The root of `i`'s set in a union-find forest, halving the path to it along the way.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+	while parents[i] != i {
+		parents[i] = parents[parents[i]];
+		i = parents[i];
+	}
+
+	i
+}
+
+
+/// The member of a cluster to keep: the largest file, which is usually the best quality copy, or the first by path of
+/// those of equal size.  Files that can't be read count as empty.
+pub fn largest_file(members: &[PathBuf]) -> &PathBuf {
+	members
+		.iter()
+		.max_by_key(|path| (std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0), std::cmp::Reverse(*path)))
+		.unwrap()
+}
+
+
+/// The `tile=` value of the whole image.
+pub const FULL: &str = "full";
+
+
+/// The region of an image an entry was computed from, if the image was hashed with `--tiles`.
+pub fn tile(entry: &Entry) -> Option<&str> {
+	entry.extra.iter().find_map(|extra| extra.strip_prefix("tile="))
+}
+
+
+/// Print each pair of duplicates as `path\tpath\tdistance`, followed by a `tile=` column holding the matching region
+/// of each (e.g. `tile=full:r0c1`) for output files hashed with `--tiles`, and any requested columns, or each cluster
+/// of duplicates as JSON.
+pub fn run(args: DedupeArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	// Files are re-hashed before they are acted on, with the parameters of the output file
+	let hasher = args.action.enabled().then(|| {
+		let checked = Settings::from_metadata(&results.metadata).and_then(|settings| action::check(&settings).map(|()| settings));
+		match checked {
+			Ok(settings) => Hasher::new(settings, Source::new(&args.source)),
+			Err(err) => {
+				error!("Error: can't act on the duplicates of {}: {}", args.output.display(), err);
+				std::process::exit(1);
+			},
+		}
+	});
+
+	let source = Source::new(&args.source);
+	let pairs = soft_match::filter(&args.soft_match, find_pairs(&results.hashes, args.threshold), args.threshold, &source);
+
+	// Read the EXIF of every path that is part of a pair, once
+	let captures = if args.corroborate_exif || args.group_by.is_some() {
+		let mut paths = pairs.iter().flat_map(|pair| [&pair.a, &pair.b]).collect::<Vec<_>>();
+		paths.sort_unstable();
+		paths.dedup();
+
+		paths
+			.into_par_iter()
+			.map(|path| {
+				let capture = match source.read(path) {
+					Ok(input) => read_capture(&input.data),
+					Err(err) => {
+						error!("Error reading EXIF of {}: {}", path.display(), err);
+						Capture::default()
+					},
+				};
+				(path.clone(), capture)
+			})
+			.collect::<BTreeMap<_, _>>()
+	} else {
+		BTreeMap::new()
+	};
+
+	// Each pair with its geotag label, if grouping, and the key it is listed by
+	let mut listed = pairs.iter().map(|pair| (pair, None)).collect::<Vec<(&Pair, Option<((u8, usize), String)>)>>();
+
+	if let Some(GroupBy::Geotag(radius)) = args.group_by {
+		let located = captures.iter().filter_map(|(path, capture)| Some((path, capture.location?))).collect::<Vec<_>>();
+		let locations = located.iter().map(|(_, location)| *location).collect::<Vec<_>>();
+		let groups = located.iter().map(|(path, _)| *path).zip(group_locations(&locations, radius)).collect::<BTreeMap<_, _>>();
+
+		for (pair, label) in &mut listed {
+			*label = Some(match (groups.get(&pair.a), groups.get(&pair.b)) {
+				(Some(a), Some(b)) if a == b => ((0, *a), format!("geotag={}", a)),
+				(Some(_), Some(_)) => ((1, 0), "geotag=apart".to_string()),
+				_ => ((2, 0), "geotag=none".to_string()),
+			});
+		}
+
+		// Grouped pairs first, in group order, then those taken apart, then those that can't be placed
+		listed.sort_by_key(|(_, label)| label.as_ref().map(|(key, _)| *key));
+	}
+
+	let clustered = (args.clusters || args.report.is_some() || args.action.enabled()).then(|| clusters(&pairs));
+	if let Some((report, clustered)) = args.report.as_ref().zip(clustered.as_ref()) {
+		let settings = Settings::from_metadata(&results.metadata).unwrap_or_else(|err| {
+			error!("Error: can't report on {}: {}", args.output.display(), err);
+			std::process::exit(1);
+		});
+		report::write(report, clustered, &source, &settings).unwrap_or_else(|err| {
+			error!("Error writing {}: {}", report.display(), err);
+			std::process::exit(1);
+		});
+		info!("Wrote {} clusters to {}", clustered.len(), report.display());
+	}
+	if let Some(clustered) = clustered.as_ref().filter(|_| args.clusters) {
+		for (id, members) in clustered.iter().enumerate() {
+			println!("{}", serde_json::json!({ "id": id, "representative": largest_file(members), "members": members }));
+		}
+	}
+
+	let outcome = action::run(&args.action, clustered.as_deref().unwrap_or_default(), &results.hashes, args.threshold, |path, cached| {
+		action::unchanged(hasher.as_ref().unwrap(), path, cached)
+	});
+
+	for (pair, label) in listed.iter().filter(|_| !args.clusters && !args.action.enabled()) {
+		let mut line = format!("{}\t{}\t{}", pair.a.display(), pair.b.display(), pair.distance);
+
+		if let Some((a, b)) = &pair.tiles {
+			line.push_str(&format!("\ttile={}:{}", a, b));
+		}
+
+		if args.corroborate_exif {
+			match captures[&pair.a].agreement(&captures[&pair.b]) {
+				Some(agreement) => line.push_str(&format!("\texif={:.2}", agreement)),
+				None => line.push_str("\texif=none"),
+			}
+		}
+
+		if let Some((_, label)) = label {
+			line.push('\t');
+			line.push_str(label);
+		}
+
+		println!("{}", line);
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"dedupe",
+		serde_json::json!({
+			"output": args.output,
+			"threshold": args.threshold,
+			"group_by": args.group_by.map(|group_by| group_by.to_string()),
+			"pairs": pairs.len(),
+			"clusters": clustered.as_ref().map(Vec::len),
+			"report": args.report,
+		}),
+	);
+
+	match clustered.as_ref().filter(|_| args.clusters) {
+		Some(clustered) => info!("Found {} clusters of duplicates among {} paths", clustered.len(), results.hashes.len()),
+		None => info!("Found {} duplicate pairs among {} paths", pairs.len(), results.hashes.len()),
+	}
+
+	if outcome.is_some_and(|outcome| outcome.failed > 0) {
+		std::process::exit(1);
+	}
+}
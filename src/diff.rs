@@ -0,0 +1,104 @@
+//! Compares two output files path by path, e.g. to check whether re-encoding a library visibly altered any image.
+use std::path::PathBuf;
+use tracing::{error, info};
+
+use crate::cache::{format_phash, read_result_file};
+
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+	/// Older output file.
+	old: PathBuf,
+
+	/// Newer output file, written with the same settings.
+	new: PathBuf,
+
+	/// Only report hashes that changed by more than this Hamming distance.
+	#[arg(short, long, default_value_t = 0)]
+	threshold: u32,
+}
+
+
+/// Print `added\tpath` and `removed\tpath` for paths in only one of the files, and `changed\tpath\told\tnew\tdistance`
+/// for each entry of a path in both whose hash changed by more than the threshold (`-` as distance if either is
+/// trivial).  Paths whose number of entries changed, e.g. videos of a different length, are reported as
+/// `changed\tpath\tN entries\tM entries`.  Entries are compared in order, as they were written.
+pub fn run(args: DiffArgs) {
+	let read = |path: &PathBuf| {
+		read_result_file(path).unwrap_or_else(|err| {
+			error!("Error reading {}: {}", path.display(), err);
+			std::process::exit(1);
+		})
+	};
+	let old = read(&args.old);
+	let new = read(&args.new);
+
+	if old.metadata != new.metadata {
+		error!(
+			"Error: {} was written with different parameters ({}) than {} ({})",
+			args.old.display(),
+			old.metadata,
+			args.new.display(),
+			new.metadata
+		);
+		std::process::exit(1);
+	}
+
+	let mut paths = old.hashes.keys().chain(new.hashes.keys().filter(|path| !old.hashes.contains_key(*path))).collect::<Vec<_>>();
+	paths.sort_unstable();
+
+	let (mut added, mut removed, mut changed) = (0, 0, 0);
+	for path in paths {
+		let (old_entries, new_entries) = match (old.hashes.get(path), new.hashes.get(path)) {
+			(Some(old_entries), Some(new_entries)) => (old_entries, new_entries),
+			(None, _) => {
+				println!("added\t{}", path.display());
+				added += 1;
+				continue;
+			},
+			(_, None) => {
+				println!("removed\t{}", path.display());
+				removed += 1;
+				continue;
+			},
+		};
+
+		if old_entries.len() != new_entries.len() {
+			println!("changed\t{}\t{} entries\t{} entries", path.display(), old_entries.len(), new_entries.len());
+			changed += 1;
+			continue;
+		}
+
+		let mut differs = false;
+		for (old_phash, new_phash) in old_entries.iter().map(|entry| entry.phash).zip(new_entries.iter().map(|entry| entry.phash)) {
+			let distance = match (old_phash, new_phash) {
+				(Some(old_phash), Some(new_phash)) => Some((old_phash ^ new_phash).count_ones()),
+				(None, None) => Some(0),
+				_ => None,
+			};
+			if distance.is_some_and(|distance| distance <= args.threshold) {
+				continue;
+			}
+
+			let distance = distance.map(|distance| distance.to_string()).unwrap_or_else(|| "-".to_string());
+			println!("changed\t{}\t{}\t{}\t{}", path.display(), format_phash(old_phash), format_phash(new_phash), distance);
+			differs = true;
+		}
+		changed += differs as usize;
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"diff",
+		serde_json::json!({
+			"old": args.old,
+			"new": args.new,
+			"threshold": args.threshold,
+			"added": added,
+			"removed": removed,
+			"changed": changed,
+		}),
+	);
+
+	info!("{} paths added, {} removed and {} changed between {} and {}", added, removed, changed, args.old.display(), args.new.display());
+}
@@ -0,0 +1,219 @@
+//! Explains what thresholds mean for the hashes of given hashing options (`explain-threshold`), by measuring how far
+//! common alterations move the hashes of a built-in corpus of generated scenes, so that thresholds such as `dedupe -t`
+//! or `match --max-distance` can be chosen from measurements.
+use image::{imageops::FilterType, DynamicImage, Rgb, RgbImage};
+use tracing::info;
+
+use crate::{
+	compare::hashes,
+	hasher::Hasher,
+	recommend::reencode,
+	settings::SettingsArgs,
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ExplainThresholdArgs {
+	#[command(flatten)]
+	settings: SettingsArgs,
+}
+
+
+/// Bits in a hash, and so the largest possible distance.
+const BITS: u32 = 64;
+
+/// Number of scenes in the corpus.
+const SCENES: u32 = 24;
+
+/// Fraction of a transformation's copies that must match at a threshold for it to count as surviving it.
+const SURVIVING: f64 = 0.9;
+
+/// Largest threshold explained.
+const MAX_THRESHOLD: u32 = 24;
+
+/// Largest chance of two unrelated images with independent bits matching that the suggested threshold may have.
+const MAX_RANDOM_MATCH: f64 = 1e-6;
+
+
+/// An alteration of an image.
+type Transformation = fn(&DynamicImage) -> DynamicImage;
+
+/// Alterations measured, from mild to severe.
+const TRANSFORMATIONS: &[(&str, Transformation)] = &[
+	("jpeg-90", |img| reencode(img, 90)),
+	("jpeg-50", |img| reencode(img, 50)),
+	("jpeg-20", |img| reencode(img, 20)),
+	("resize-50", |img| resize(img, 2)),
+	("resize-25", |img| resize(img, 4)),
+	("brightness-20", |img| img.brighten(20)),
+	("brightness-60", |img| img.brighten(60)),
+	("contrast-30", |img| img.adjust_contrast(30.0)),
+	("blur-1", |img| img.blur(1.0)),
+	("blur-3", |img| img.blur(3.0)),
+	("crop-5", |img| crop(img, 20)),
+	("crop-10", |img| crop(img, 10)),
+	("mirror", DynamicImage::fliph),
+];
+
+
+fn resize(img: &DynamicImage, factor: u32) -> DynamicImage {
+	img.resize_exact((img.width() / factor).max(1), (img.height() / factor).max(1), FilterType::Triangle)
+}
+
+
+/// Crop a `1/fraction` of each side.
+fn crop(img: &DynamicImage, fraction: u32) -> DynamicImage {
+	let (x, y) = (img.width() / fraction, img.height() / fraction);
+	img.crop_imm(x, y, img.width() - 2 * x, img.height() - 2 * y)
+}
+
+
+/// A scene of overlapping rectangles and discs of random colours on a gradient, with fine noise, much as photographs
+/// are mostly areas of smooth colour.  Computed with integer arithmetic only so that it is the same on every platform.
+fn scene(seed: u32) -> RgbImage {
+	// xorshift32
+	let mut state = seed.wrapping_mul(0x9E37_79B9) | 1;
+	let mut next = |n: u32| {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		state % n
+	};
+
+	let (width, height) = (320, 240);
+	let top = [next(256), next(256), next(256)];
+	let bottom = [next(256), next(256), next(256)];
+	let shapes = (0..4 + next(6))
+		.map(|_| (next(2) == 0, next(width) as i64, next(height) as i64, 15 + next(90) as i64, 15 + next(70) as i64, [next(256), next(256), next(256)]))
+		.collect::<Vec<_>>();
+
+	RgbImage::from_fn(width, height, |x, y| {
+		let mut colour = std::array::from_fn(|c| (top[c] * (height - y) + bottom[c] * y) / height);
+		for (disc, cx, cy, rx, ry, fill) in &shapes {
+			let (dx, dy) = (x as i64 - cx, y as i64 - cy);
+			let inside = if *disc { dx * dx * ry * ry + dy * dy * rx * rx <= rx * rx * ry * ry } else { dx.abs() <= *rx && dy.abs() <= *ry };
+			if inside {
+				colour = *fill;
+			}
+		}
+
+		let noise = (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663) ^ seed).wrapping_mul(0x2C1B_3C6D) >> 28;
+		Rgb(colour.map(|value| (value + noise).saturating_sub(8).min(255) as u8))
+	})
+}
+
+
+/// For each algorithm of the hashing options, print a `transformation\talgorithm\tname\tmedian\tp90\tmax` line with
+/// how far each alteration moved the hashes of the corpus's scenes, then a
+/// `threshold\talgorithm\tN\tsurvived\tunrelated\trandom` line for each threshold: the alterations at least 90% of
+/// whose copies still match at it, how many pairs of different scenes match, and the chance that two unrelated images
+/// with independent, evenly distributed bits would.
+pub fn run(args: ExplainThresholdArgs) {
+	let settings = args.settings.settings();
+	let algorithms = settings.algorithms.clone();
+	let hasher = Hasher::new(settings, Source::new(&SourceArgs::default()));
+	let hash = |img: &DynamicImage| hashes(hasher.hash_image(img).first()?, &algorithms, &algorithms);
+
+	// The hashes of each scene and of each of its altered copies, where they aren't trivial
+	let corpus = (0..SCENES)
+		.filter_map(|seed| {
+			let img = DynamicImage::ImageRgb8(scene(seed));
+			Some((hash(&img)?, TRANSFORMATIONS.iter().map(|(_, transform)| hash(&transform(&img))).collect::<Vec<_>>()))
+		})
+		.collect::<Vec<_>>();
+
+	for (a, algorithm) in algorithms.iter().enumerate() {
+		// Distances of each alteration's copies from their originals, sorted
+		let moved = (0..TRANSFORMATIONS.len())
+			.map(|t| {
+				let mut distances =
+					corpus.iter().filter_map(|(original, copies)| Some((original[a] ^ copies[t].as_ref()?[a]).count_ones())).collect::<Vec<_>>();
+				distances.sort_unstable();
+				distances
+			})
+			.collect::<Vec<_>>();
+
+		let mut unrelated = Vec::new();
+		for (i, (x, _)) in corpus.iter().enumerate() {
+			for (y, _) in &corpus[i + 1..] {
+				unrelated.push((x[a] ^ y[a]).count_ones());
+			}
+		}
+
+		for ((name, _), distances) in TRANSFORMATIONS.iter().zip(&moved) {
+			let Some(max) = distances.last() else {
+				continue;
+			};
+			println!("transformation\t{}\t{}\t{}\t{}\t{}", algorithm, name, distances[distances.len() / 2], surviving(distances), max);
+		}
+
+		let survived = |threshold: u32| {
+			TRANSFORMATIONS
+				.iter()
+				.zip(&moved)
+				.filter(|(_, distances)| !distances.is_empty() && surviving(distances) <= threshold)
+				.map(|((name, _), _)| *name)
+				.collect::<Vec<_>>()
+		};
+
+		for threshold in 0..=MAX_THRESHOLD {
+			let survived = survived(threshold);
+			let matched = unrelated.iter().filter(|distance| **distance <= threshold).count();
+			println!(
+				"threshold\t{}\t{}\t{}\t{}/{}\t{:.1e}",
+				algorithm,
+				threshold,
+				if survived.is_empty() { "-".to_string() } else { survived.join(",") },
+				matched,
+				unrelated.len(),
+				random_match(threshold)
+			);
+		}
+
+		// The largest threshold at which different scenes don't match, nor would unrelated images with independent bits
+		let threshold = (0..=MAX_THRESHOLD)
+			.take_while(|threshold| random_match(*threshold) <= MAX_RANDOM_MATCH && unrelated.iter().all(|distance| distance > threshold))
+			.last();
+		match threshold {
+			Some(threshold) => info!(
+				"{}: up to a threshold of {} (e.g. `dedupe -t {}` or `match --max-distance {}`), no two different scenes match and unrelated \
+				 images with independent bits would {:.1e} of the time; most copies survive {}",
+				algorithm,
+				threshold,
+				threshold,
+				threshold,
+				random_match(threshold),
+				if survived(threshold).is_empty() { "none of the alterations".to_string() } else { survived(threshold).join(", ") }
+			),
+			None => info!("{}: even identical hashes match different scenes of the corpus", algorithm),
+		}
+	}
+
+	info!(
+		"Hashes have {} bits, so distances range from 0 to {}; unrelated images are about {} apart, and real collections, whose \
+		 bits aren't independent, match more often than the random column suggests (see `stats` and `recommend`)",
+		BITS,
+		BITS,
+		BITS / 2
+	);
+}
+
+
+/// The distance within which `SURVIVING` of a transformation's sorted distances are.
+fn surviving(distances: &[u32]) -> u32 {
+	distances[((distances.len() as f64 * SURVIVING).ceil() as usize).clamp(1, distances.len()) - 1]
+}
+
+
+/// The chance that two hashes with independent, evenly distributed bits are within a distance of each other.
+fn random_match(threshold: u32) -> f64 {
+	let mut combinations = 1.0;
+	let mut total = 0.0;
+	for k in 0..=threshold {
+		total += combinations;
+		combinations *= (BITS - k) as f64 / (k + 1) as f64;
+	}
+
+	total / 2f64.powi(BITS as i32)
+}
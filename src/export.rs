@@ -0,0 +1,253 @@
+//! Writes lists derived from an output file for use by other tools.
+use clap::ValueEnum;
+use std::{
+	collections::{BTreeSet, HashMap},
+	path::{Path, PathBuf},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::{read_result_file, Entry},
+	dedupe::{clusters, find_pairs, largest_file, within_threshold},
+	soft_match::{self, SoftMatchArgs},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+	/// Output file to export from.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// What to write.
+	#[arg(long, value_enum)]
+	format: Format,
+
+	/// Maximum Hamming distance between the hashes of duplicates.
+	#[arg(short, long, default_value_t = 4)]
+	threshold: u32,
+
+	/// Directory that rsync copies from, or PhotoPrism's originals folder.  Paths are written relative to it, and paths
+	/// outside it are left out.  Without it, paths are written as they appear in the output file.
+	#[arg(long)]
+	root: Option<PathBuf>,
+
+	/// With `immich`, the directory Immich sees `--root` as, e.g. where an external library is mounted in its
+	/// container.  Paths relative to `--root` are looked up under it.
+	#[cfg(feature = "http")]
+	#[arg(long, requires = "root")]
+	library_root: Option<PathBuf>,
+
+	/// With `immich` and `photoprism`, the base URL of the server, e.g. `http://localhost:2283`.
+	#[cfg(feature = "http")]
+	#[arg(long, required_if_eq_any = [("format", "immich"), ("format", "photoprism")])]
+	url: Option<String>,
+
+	/// With `immich`, an API key with the asset.read and asset.update permissions; with `photoprism`, an app password.
+	/// Best given in the config file rather than on the command line.
+	#[cfg(feature = "http")]
+	#[arg(long, required_if_eq_any = [("format", "immich"), ("format", "photoprism")])]
+	api_key: Option<String>,
+
+	#[command(flatten)]
+	soft_match: SoftMatchArgs,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+	/// rsync filter rules excluding the duplicates of the largest member of each cluster of duplicates.
+	RsyncExclude,
+	/// rsync filter rules including every hashed image except the duplicates of the largest member of each cluster of
+	/// duplicates, and excluding everything else.
+	RsyncInclude,
+	/// Marks each cluster of duplicates as duplicates in Immich, to review under Utilities > Review duplicates.
+	#[cfg(feature = "http")]
+	Immich,
+	/// Adds each cluster of duplicates to an album of its own in PhotoPrism, named after its largest member.
+	#[cfg(feature = "http")]
+	Photoprism,
+}
+
+
+/// Write the export on stdout, or push it to a photo manager.
+///
+/// The rsync formats are filter rules for `rsync -a --filter='merge FILE' SRC/ DST/`.  With `rsync-include`, add
+/// `--prune-empty-dirs` to skip directories that end up empty.
+///
+/// The photo manager formats push clusters of the largest member and its duplicates, looked up by path.  Files the
+/// photo manager doesn't know about are left out, as are clusters with fewer than two files it knows.
+pub fn run(args: ExportArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	let pairs = find_pairs(&results.hashes, args.threshold);
+	let pairs = soft_match::filter(&args.soft_match, pairs, args.threshold, &Source::new(&args.source));
+	let clusters = clusters(&pairs);
+	let groups = groups(&clusters, &results.hashes, args.threshold);
+	let duplicates = groups.iter().flat_map(|(_, duplicates)| duplicates.iter().copied()).collect::<BTreeSet<_>>();
+
+	let (rule, listed) = match args.format {
+		Format::RsyncExclude => ('-', duplicates.iter().copied().collect::<Vec<_>>()),
+		Format::RsyncInclude => {
+			let mut kept = results.hashes.keys().filter(|path| !duplicates.contains(path)).collect::<Vec<_>>();
+			kept.sort_unstable();
+			('+', kept)
+		},
+		#[cfg(feature = "http")]
+		Format::Immich | Format::Photoprism => return push(&args, &groups),
+	};
+
+	let mut written = 0;
+	for path in listed {
+		match pattern(path, args.root.as_deref()) {
+			Some(pattern) => {
+				println!("{} {}", rule, pattern);
+				written += 1;
+			},
+			None => warn!("Warning: {} is outside {}, it will be left out", path.display(), args.root.as_deref().unwrap().display()),
+		}
+	}
+
+	// Directories have to be included for rsync to descend into them
+	if args.format == Format::RsyncInclude {
+		println!("+ */");
+		println!("- *");
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"export",
+		serde_json::json!({
+			"output": args.output,
+			"format": args.format.to_possible_value().unwrap().get_name(),
+			"threshold": args.threshold,
+			"paths": written,
+		}),
+	);
+
+	info!("Exported {} paths; {} duplicates in {} clusters", written, duplicates.len(), clusters.len());
+}
+
+
+/// The largest member of each cluster, and the other members within `threshold` of it, which are left out of backups.
+/// Clusters are linked through chains of pairs, and members that are only duplicates of other members aren't
+/// necessarily duplicates of the largest.  Clusters left without duplicates are skipped.
+fn groups<'a>(clusters: &'a [Vec<PathBuf>], hashes: &HashMap<PathBuf, Vec<Entry>>, threshold: u32) -> Vec<(&'a PathBuf, Vec<&'a PathBuf>)> {
+	clusters
+		.iter()
+		.map(|members| {
+			let keep = largest_file(members);
+			let duplicates = members.iter().filter(|path| *path != keep && within_threshold(hashes, keep, path, threshold)).collect::<Vec<_>>();
+			(keep, duplicates)
+		})
+		.filter(|(_, duplicates)| !duplicates.is_empty())
+		.collect()
+}
+
+
+/// Push the groups to the photo manager of the format.
+#[cfg(feature = "http")]
+fn push(args: &ExportArgs, groups: &[(&PathBuf, Vec<&PathBuf>)]) {
+	use crate::photo_managers::{push_immich, push_photoprism, remote_path, Group, Server};
+
+	if args.format == Format::Photoprism && args.root.is_none() {
+		error!("Error: --format photoprism needs --root, PhotoPrism's originals folder, as it knows files by paths relative to it");
+		std::process::exit(1);
+	}
+
+	let library_root = args.library_root.as_deref().filter(|_| args.format == Format::Immich);
+	let remote = |path: &Path| {
+		let remote = remote_path(path, args.root.as_deref(), library_root);
+		if remote.is_none() {
+			warn!("Warning: {} is outside {}, it will be left out", path.display(), args.root.as_deref().unwrap().display());
+		}
+		remote
+	};
+	let groups = groups
+		.iter()
+		.filter_map(|(keep, duplicates)| {
+			Some(Group {
+				keep: remote(keep)?,
+				duplicates: duplicates.iter().filter_map(|path| remote(path)).collect(),
+			})
+		})
+		.collect::<Vec<_>>();
+
+	let server = Server {
+		url: args.url.as_deref().unwrap().trim_end_matches('/'),
+		api_key: args.api_key.as_deref().unwrap(),
+	};
+	let pushed = match args.format {
+		Format::Immich => push_immich(&server, &groups),
+		_ => push_photoprism(&server, &groups),
+	}
+	.unwrap_or_else(|err| {
+		error!("Error pushing to {}: {}", server.url, err);
+		std::process::exit(1);
+	});
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"export",
+		serde_json::json!({
+			"output": args.output,
+			"format": args.format.to_possible_value().unwrap().get_name(),
+			"threshold": args.threshold,
+			"url": server.url,
+			"clusters": pushed,
+		}),
+	);
+
+	info!("Pushed {} of {} clusters of duplicates to {}", pushed, groups.len(), server.url);
+}
+
+
+/// An rsync pattern matching exactly this path, anchored at the root of the transfer.
+fn pattern(path: &Path, root: Option<&Path>) -> Option<String> {
+	let path = match root {
+		Some(root) => path.strip_prefix(root).ok()?,
+		None => path,
+	};
+	let path = path.to_str()?.trim_start_matches('/');
+
+	// Backslashes escape wildcards in patterns that contain any
+	let mut pattern = String::from("/");
+	for c in path.chars() {
+		if matches!(c, '*' | '?' | '[' | '\\') {
+			pattern.push('\\');
+		}
+		pattern.push(c);
+	}
+
+	Some(pattern)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keeps_members_only_chained_to_the_largest() {
+		// None of the paths exist, so the largest is the first by path, `a`; `c` is only within the threshold of `b`
+		let paths = ["a", "b", "c"].map(|name| Path::new("/nonexistent/hasher-export").join(name));
+		let hashes = paths
+			.iter()
+			.cloned()
+			.zip([0, 0b111, 0b111_111])
+			.map(|(path, phash)| (path, vec![Entry { phash: Some(phash), extra: Vec::new() }]))
+			.collect();
+		let pairs = find_pairs(&hashes, 4);
+		let clusters = clusters(&pairs);
+
+		assert_eq!(clusters, vec![paths.to_vec()]);
+		assert_eq!(groups(&clusters, &hashes, 4), vec![(&paths[0], vec![&paths[1]])]);
+	}
+}
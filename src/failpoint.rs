@@ -0,0 +1,105 @@
+//! Injects failures into hashing runs (`--failpoint`), so that deployments can check that their monitoring, retries and
+//! recovery cope with them before they happen for real.
+use image::error::{DecodingError, ImageError, ImageFormatHint};
+use rand::Rng;
+use std::{str::FromStr, sync::OnceLock, time::Duration};
+
+
+#[derive(clap::Args, Debug)]
+pub struct FailpointArgs {
+	/// Inject failures at a stage of hashing, at a rate from 0 to 1: `decode-error=RATE` fails decoding inputs,
+	/// `slow-read=RATE:DELAY` delays reading them and `writer-stall=RATE:DELAY` delays writing the output file, with
+	/// delays such as `500ms` or `30s`.  May be given several times.
+	#[arg(long, value_name = "STAGE=RATE[:DELAY]", hide = true)]
+	failpoint: Vec<Failpoint>,
+}
+
+
+/// Where a failure is injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+	DecodeError,
+	SlowRead,
+	WriterStall,
+}
+
+
+/// A failure injected at a stage, at some rate.
+#[derive(Debug, Clone)]
+pub struct Failpoint {
+	stage: Stage,
+	rate: f64,
+	delay: Duration,
+}
+
+impl FromStr for Failpoint {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (stage, rest) = s.split_once('=').ok_or("expected STAGE=RATE[:DELAY]")?;
+		let (rate, delay) = match rest.split_once(':') {
+			Some((rate, delay)) => (rate, Some(delay)),
+			None => (rest, None),
+		};
+
+		let stage = match stage {
+			"decode-error" => Stage::DecodeError,
+			"slow-read" => Stage::SlowRead,
+			"writer-stall" => Stage::WriterStall,
+			_ => return Err(format!("unknown stage: {}", stage)),
+		};
+		let rate = rate.parse::<f64>().ok().filter(|rate| (0.0..=1.0).contains(rate)).ok_or(format!("invalid rate: {}", rate))?;
+		let delay = match (stage, delay) {
+			(Stage::DecodeError, None) => Duration::ZERO,
+			(Stage::DecodeError, Some(_)) => return Err("decode-error takes no delay".to_string()),
+			(_, Some(delay)) => parse_delay(delay).ok_or(format!("invalid delay: {}", delay))?,
+			(_, None) => return Err("slow-read and writer-stall take a delay, e.g. slow-read=0.1:500ms".to_string()),
+		};
+
+		Ok(Failpoint { stage, rate, delay })
+	}
+}
+
+
+/// A delay such as `500ms` or `30s`.
+fn parse_delay(s: &str) -> Option<Duration> {
+	if let Some(ms) = s.strip_suffix("ms") {
+		ms.parse().ok().map(Duration::from_millis)
+	} else {
+		s.strip_suffix('s')?.parse().ok().map(Duration::from_secs_f64)
+	}
+}
+
+
+static FAILPOINTS: OnceLock<Vec<Failpoint>> = OnceLock::new();
+
+
+/// Inject the failures given on the command line for the rest of the process.
+pub fn configure(args: &FailpointArgs) {
+	FAILPOINTS.set(args.failpoint.clone()).unwrap();
+}
+
+
+/// Whether to inject the failure of a stage this time; if so, with its delay.
+fn hit(stage: Stage) -> Option<Duration> {
+	let failpoint = FAILPOINTS.get()?.iter().find(|failpoint| failpoint.stage == stage)?;
+	rand::thread_rng().gen_bool(failpoint.rate).then_some(failpoint.delay)
+}
+
+
+/// Sleep at a stage that is to be slowed down this time.
+pub fn delay(stage: Stage) {
+	if let Some(delay) = hit(stage) {
+		std::thread::sleep(delay);
+	}
+}
+
+
+/// Fail decoding an input, if it is to fail this time, as a corrupt image would.
+pub fn decode() -> anyhow::Result<()> {
+	match hit(Stage::DecodeError) {
+		Some(_) => Err(anyhow::Error::new(ImageError::Decoding(DecodingError::from_format_hint(ImageFormatHint::Unknown)))
+			.context("Error decoding image (injected by --failpoint)")),
+		None => Ok(()),
+	}
+}
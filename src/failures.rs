@@ -0,0 +1,107 @@
+//! Records inputs that couldn't be hashed in a file of their own (`--errors`), so that inputs which will never hash,
+//! such as corrupt images, aren't tried again on every run.
+use anyhow::Context;
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{self, BufRead, BufReader, Write},
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct FailureArgs {
+	/// Record inputs that fail to hash in this file, as `path\tcategory\terror` lines, where the category is one of
+	/// `unsupported`, `decode`, `not-found`, `permission`, `io` or `other`.  Inputs that failed with an unsupported
+	/// format or a corrupt image are skipped by later runs with the same file; the others are tried again.
+	#[arg(long, value_name = "FILE")]
+	errors: Option<PathBuf>,
+
+	/// Try inputs recorded in the `--errors` file again, whatever they failed with, e.g. after repairing them or
+	/// building with support for more formats.
+	#[arg(long, requires = "errors")]
+	retry_failed: bool,
+}
+
+
+/// Categories of failures that trying again won't fix.
+const PERMANENT: &[&str] = &["unsupported", "decode"];
+
+
+/// The `--errors` file of a run.
+pub struct Failures {
+	/// The category each recorded input last failed with.
+	recorded: HashMap<PathBuf, String>,
+	file: Mutex<File>,
+}
+
+impl Failures {
+	/// Open the `--errors` file, if any.  Records of inputs that have since been hashed, according to `hashed`, are
+	/// dropped, as are all but the latest record of each input.
+	pub fn open(args: &FailureArgs, hashed: impl Fn(&Path) -> bool) -> anyhow::Result<Option<Self>> {
+		let Some(path) = &args.errors else {
+			return Ok(None);
+		};
+
+		let mut records = Vec::new();
+		match File::open(path) {
+			Ok(file) => {
+				for line in BufReader::new(file).lines() {
+					let line = line.with_context(|| format!("Error reading {}", path.display()))?;
+					let mut parts = line.splitn(3, '\t');
+					if let (Some(input), Some(category)) = (parts.next(), parts.next()) {
+						records.push((PathBuf::from(input), category.to_string(), parts.next().unwrap_or_default().to_string()));
+					}
+				}
+			},
+			Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+			Err(err) => return Err(err).with_context(|| format!("Error reading {}", path.display())),
+		}
+
+		// Keep the latest record of each input that is still unhashed, in the order they were recorded
+		let mut latest = HashMap::new();
+		for (index, (input, _, _)) in records.iter().enumerate() {
+			latest.insert(input.clone(), index);
+		}
+		records = records
+			.into_iter()
+			.enumerate()
+			.filter(|(index, (input, _, _))| latest[input] == *index && !hashed(input))
+			.map(|(_, record)| record)
+			.collect();
+
+		// Rewritten beside the file and moved over it, so that the records survive a crash
+		let mut rewritten = path.as_os_str().to_owned();
+		rewritten.push(".tmp");
+		let write = || -> io::Result<File> {
+			let mut file = File::create(&rewritten)?;
+			for (input, category, message) in &records {
+				writeln!(file, "{}\t{}\t{}", input.display(), category, message)?;
+			}
+			std::fs::rename(&rewritten, path)?;
+			File::options().append(true).open(path)
+		};
+		let file = write().with_context(|| format!("Error writing {}", path.display()))?;
+
+		Ok(Some(Failures {
+			recorded: if args.retry_failed { HashMap::new() } else { records.into_iter().map(|(input, category, _)| (input, category)).collect() },
+			file: Mutex::new(file),
+		}))
+	}
+
+	/// Whether an input is recorded as failing in a way that trying again won't fix, and is to be skipped.
+	pub fn is_permanent(&self, path: &Path) -> bool {
+		self.recorded.get(path).is_some_and(|category| PERMANENT.contains(&category.as_str()))
+	}
+
+	/// Record that an input failed.  Inputs whose paths aren't UTF-8 or contain tabs or newlines can't be recorded and are left out.
+	pub fn record(&self, path: &Path, category: &str, err: &anyhow::Error) -> io::Result<()> {
+		let Some(path) = path.to_str().filter(|path| !path.contains('\t') && !path.contains('\n')) else {
+			return Ok(());
+		};
+
+		let message = format!("{:#}", err).replace(['\t', '\n', '\r'], " ");
+		writeln!(self.file.lock().unwrap(), "{}\t{}\t{}", path, category, message)
+	}
+}
@@ -0,0 +1,45 @@
+//! C bindings to the library.
+use std::panic::catch_unwind;
+
+
+/// `phash_compute` succeeded.
+pub const PHASH_OK: i32 = 0;
+/// `phash_compute` was given a null pointer.
+pub const PHASH_ERROR_ARGUMENT: i32 = 1;
+/// The image couldn't be decoded, or is in a format this build doesn't support.
+pub const PHASH_ERROR_DECODE: i32 = 2;
+/// Hashing failed unexpectedly.
+pub const PHASH_ERROR_INTERNAL: i32 = 3;
+
+
+/// Compute the perceptual hash of an encoded image of `len` bytes at `buf`, storing it in `out_hash`.
+///
+/// Returns `PHASH_OK`, or one of the `PHASH_ERROR_*` codes, leaving `out_hash` untouched.  Safe to call from several
+/// threads at once.
+///
+/// # Safety
+///
+/// `buf` must point to `len` readable bytes and `out_hash` to a writable `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn phash_compute(buf: *const u8, len: usize, out_hash: *mut u64) -> i32 {
+	if buf.is_null() || out_hash.is_null() {
+		return PHASH_ERROR_ARGUMENT;
+	}
+	let data = std::slice::from_raw_parts(buf, len);
+
+	match catch_unwind(|| crate::hash_bytes(data)) {
+		Ok(Ok(phash)) => {
+			*out_hash = phash;
+			PHASH_OK
+		},
+		Ok(Err(_)) => PHASH_ERROR_DECODE,
+		Err(_) => PHASH_ERROR_INTERNAL,
+	}
+}
+
+
+/// The Hamming distance between two hashes: the number of bits in which they differ, from 0 to 64.
+#[no_mangle]
+pub extern "C" fn phash_distance(a: u64, b: u64) -> u32 {
+	crate::distance(a, b)
+}
@@ -0,0 +1,401 @@
+use anyhow::Context;
+use image::{DynamicImage, GrayImage};
+use std::{path::Path, time::Instant};
+use tracing::{debug, debug_span, trace};
+
+use crate::{
+	animation::decode_frames,
+	budget::{estimate_pixels, PixelBudget},
+	cache::Entry,
+	codecs::{decode, decode_scaled, Decoded, UnsupportedFormat},
+	orientation::{apply_orientation, dihedral, read_orientation},
+	phash::{ahash, dhash, downscale, downscale_jittered, is_trivial, phash, Dct},
+	preprocess::{apply_image_steps, apply_steps, composite},
+	settings::{Algorithm, Frames, InvariantStore, Settings},
+	source::{Input, Source},
+	thumbnail::{read_thumbnail, THUMBNAIL_HEAD},
+};
+
+
+/// Computes the entries for inputs according to a set of `Settings`.
+pub struct Hasher {
+	pub settings: Settings,
+	source: Source,
+	budget: Option<PixelBudget>,
+	dct: Dct,
+}
+
+impl Hasher {
+	pub fn new(settings: Settings, source: Source) -> Self {
+		Hasher {
+			settings,
+			source,
+			budget: None,
+			dct: Dct::new(),
+		}
+	}
+
+	/// Limit how many pixels are decoded at once across all threads using this hasher.
+	pub fn with_pixel_budget(mut self, max: Option<u64>) -> Self {
+		self.budget = max.map(PixelBudget::new);
+		self
+	}
+
+	/// Compute every entry for an input.  Most inputs produce a single entry, videos produce one per frame.
+	/// Inputs read with `--forensic` also get columns identifying the file they were read from.
+	pub fn hash(&self, path: &Path) -> anyhow::Result<Vec<Entry>> {
+		self.hash_prefetched(path, self.prefetch(path)?)
+	}
+
+	/// Read as much of an input as hashing it needs, so that reading can happen on a different thread: all of it, just
+	/// the start with `--use-thumbnails`, or nothing for videos, which are read by ffmpeg itself.
+	pub fn prefetch(&self, path: &Path) -> anyhow::Result<Option<Input>> {
+		let _span = debug_span!("input", path = %path.display()).entered();
+		let start = now();
+
+		#[cfg(feature = "failpoints")]
+		crate::failpoint::delay(crate::failpoint::Stage::SlowRead);
+
+		let input = match self.settings.video {
+			Some(_) if crate::video::is_video(path) => return Ok(None),
+			_ if self.settings.thumbnails => self.source.read_head(path, THUMBNAIL_HEAD)?,
+			_ => self.source.read(path)?,
+		};
+		trace!(bytes = input.data.len(), read_ms = millis(start), "Read");
+
+		Ok(Some(input))
+	}
+
+	/// Compute every entry for an input from what `prefetch` read of it.
+	pub fn hash_prefetched(&self, path: &Path, input: Option<Input>) -> anyhow::Result<Vec<Entry>> {
+		let _span = debug_span!("input", path = %path.display()).entered();
+		let start = now();
+
+		let (mut entries, extra) = match (input, self.settings.video) {
+			(None, Some(frames)) => (self.hash_video(path, frames)?, self.source.provenance(path)),
+			(None, None) => unreachable!("only videos aren't prefetched"),
+			(Some(head), _) if self.settings.thumbnails => match self.hash_thumbnail(&head.data) {
+				Some(entries) => (entries, head.extra),
+				None => {
+					// Small files were read in full already
+					let input = if (head.data.len() as u64) < THUMBNAIL_HEAD { head } else { self.source.read(path)? };
+					(self.hash_data(path, &input.data)?, input.extra)
+				},
+			},
+			(Some(input), _) => (self.hash_data(path, &input.data)?, input.extra),
+		};
+
+		for entry in &mut entries {
+			entry.extra.extend(extra.iter().cloned());
+		}
+		debug!(entries = entries.len(), hash_ms = millis(start), "Hashed");
+
+		Ok(entries)
+	}
+
+	/// Compute every entry for the contents of an input that isn't a video.
+	fn hash_data(&self, path: &Path, data: &[u8]) -> anyhow::Result<Vec<Entry>> {
+		#[cfg(not(feature = "raw"))]
+		let _ = path;
+
+		// Held until the decoded image has been hashed
+		let _permit = self.budget.as_ref().map(|budget| budget.acquire(estimate_pixels(data)));
+
+		if let Some(page) = self.settings.pdf_page {
+			if crate::pdf::is_pdf(data) {
+				return self.hash_pdf(data, page);
+			}
+		}
+
+		#[cfg(feature = "raw")]
+		if crate::raw::is_raw(path) {
+			return self.hash_raw(data);
+		}
+
+		if self.settings.frames != Frames::First {
+			if let Some(entries) = self.hash_frames(data)? {
+				return Ok(entries);
+			}
+		}
+
+		self.hash_bytes(data)
+	}
+
+	/// Hash the frames of an animated image, or return None if it isn't one.
+	fn hash_frames(&self, data: &[u8]) -> anyhow::Result<Option<Vec<Entry>>> {
+		let Some(frames) = decode_frames(data).context("Error decoding image")? else {
+			return Ok(None);
+		};
+
+		// Frames are hashed as they are decoded, so that long animations don't have to be held in memory
+		let mut hashed = frames
+			.map(|frame| frame.map(|frame| self.hash_image(&DynamicImage::ImageRgba8(frame.into_buffer()))))
+			.collect::<Result<Vec<_>, _>>()
+			.context("Error decoding image")?;
+
+		if hashed.len() <= 1 {
+			return Ok(None);
+		}
+
+		let indices = match self.settings.frames {
+			Frames::First => vec![0],
+			Frames::All => (0..hashed.len()).collect(),
+			Frames::Representative => representative_frames(hashed.len()),
+		};
+
+		Ok(Some(
+			indices
+				.into_iter()
+				.flat_map(|index| labelled(std::mem::take(&mut hashed[index]), format!("frame={}", index)))
+				.collect(),
+		))
+	}
+
+	/// Compute the entries of an encoded image.
+	pub fn hash_bytes(&self, data: &[u8]) -> anyhow::Result<Vec<Entry>> {
+		Ok(self.hash_image(&self.decode_image(data)?))
+	}
+
+	/// Decode an image as it is hashed, oriented according to the settings.
+	pub fn decode_image(&self, data: &[u8]) -> anyhow::Result<DynamicImage> {
+		let decoded = self.decode(data)?;
+		let mut img = decoded.image;
+
+		if self.settings.exif_orientation && !decoded.oriented {
+			img = apply_orientation(img, read_orientation(data));
+		}
+
+		Ok(img)
+	}
+
+	/// Compute the entries of the EXIF thumbnail at the start of an input, or return None if it doesn't have one that
+	/// can be decoded.
+	fn hash_thumbnail(&self, head: &[u8]) -> Option<Vec<Entry>> {
+		let (thumbnail, orientation) = read_thumbnail(head)?;
+		let mut img = decode(&thumbnail).ok()?.image;
+
+		if self.settings.exif_orientation {
+			img = apply_orientation(img, orientation);
+		}
+
+		let mut entries = self.hash_image(&img);
+		for entry in &mut entries {
+			entry.extra.push("thumbnail=exif".to_string());
+		}
+
+		Some(entries)
+	}
+
+	/// Compute the entries of a camera RAW file from its embedded preview.
+	#[cfg(feature = "raw")]
+	fn hash_raw(&self, data: &[u8]) -> anyhow::Result<Vec<Entry>> {
+		let preview = crate::raw::extract_preview(data).ok_or_else(|| anyhow::anyhow!("No embedded preview found in RAW file"))?;
+		let mut img = self.decode(preview)?.image;
+
+		// Previews are stored unrotated; the orientation is in the RAW file's own metadata, or else the preview's
+		if self.settings.exif_orientation {
+			let orientation = match read_orientation(data) {
+				1 => read_orientation(preview),
+				orientation => orientation,
+			};
+			img = apply_orientation(img, orientation);
+		}
+
+		Ok(self.hash_image(&img))
+	}
+
+	fn decode(&self, data: &[u8]) -> anyhow::Result<Decoded> {
+		#[cfg(feature = "failpoints")]
+		crate::failpoint::decode()?;
+
+		let start = now();
+		let decoded = if self.settings.scaled_jpeg { decode_scaled(data)? } else { decode(data)? };
+		debug!(width = decoded.image.width(), height = decoded.image.height(), decode_ms = millis(start), "Decoded");
+
+		Ok(decoded)
+	}
+
+	/// Compute the entries of a decoded image: a single one unless `--invariant` stores the hash of every transform or
+	/// `--tiles` also hashes each tile.
+	pub fn hash_image(&self, img: &DynamicImage) -> Vec<Entry> {
+		let img = composite(img, self.settings.background);
+		let img = apply_image_steps(&img, &self.settings.preprocess);
+
+		let Some(n) = self.settings.tiles else {
+			return self.hash_region(&img);
+		};
+
+		let (width, height) = (img.width(), img.height());
+		let mut entries = labelled(self.hash_region(&img), "tile=full".to_string()).collect::<Vec<_>>();
+
+		for row in 0..n {
+			for col in 0..n {
+				let (x, y) = (col * width / n, row * height / n);
+				let tile = img.crop_imm(x, y, ((col + 1) * width / n - x).max(1), ((row + 1) * height / n - y).max(1));
+				entries.extend(labelled(self.hash_region(&tile), format!("tile=r{}c{}", row, col)));
+			}
+		}
+
+		entries
+	}
+
+	/// Compute the entries of an image or tile of one, after compositing.
+	/// Trivial images have a single entry without a hash when those are skipped.
+	fn hash_region(&self, img: &DynamicImage) -> Vec<Entry> {
+		if let Some(compat) = self.settings.compat {
+			let hash = compat.hash(img);
+			return vec![Entry {
+				phash: Some(hash),
+				extra: compat.columns(hash),
+			}];
+		}
+
+		if let Some(count) = self.settings.ensemble {
+			let copies = downscale_jittered(img, self.settings.grayscale, self.settings.filter, count);
+			if self.settings.skip_trivial && is_trivial(&copies[0]) {
+				return vec![Entry { phash: None, extra: Vec::new() }];
+			}
+
+			let hashes = copies.into_iter().map(|img| self.hashes(img)).collect::<Vec<_>>();
+			let consensus = (0..self.settings.algorithms.len()).map(|a| majority(hashes.iter().map(|hashes| hashes[a]))).collect();
+			return vec![self.entry_of(consensus)];
+		}
+
+		let img = downscale(img, self.settings.grayscale, self.settings.filter);
+
+		if self.settings.skip_trivial && is_trivial(&img) {
+			return vec![Entry { phash: None, extra: Vec::new() }];
+		}
+
+		let Some(invariant) = self.settings.invariant else {
+			return vec![self.entry(img)];
+		};
+
+		// Transforming the downscaled image is much cheaper than transforming the original, and the filters are
+		// symmetric, so the results are all but identical
+		let entries = dihedral(&img, invariant.rotate, invariant.flip).into_iter().map(|(name, img)| (name, self.entry(img)));
+
+		match invariant.store {
+			InvariantStore::Min => entries.map(|(_, entry)| entry).min_by_key(|entry| entry.phash).into_iter().collect(),
+			InvariantStore::All => entries.flat_map(|(name, entry)| labelled(vec![entry], format!("transform={}", name))).collect(),
+		}
+	}
+
+	/// Compute the entry of a downscaled image, with the first algorithm's hash and a column for each other.
+	fn entry(&self, img: GrayImage) -> Entry {
+		self.entry_of(self.hashes(img))
+	}
+
+	/// Compute each algorithm's hash of a downscaled image.
+	fn hashes(&self, img: GrayImage) -> Vec<u64> {
+		let img = apply_steps(img, &self.settings.preprocess);
+		self.settings
+			.algorithms
+			.iter()
+			.map(|algorithm| match algorithm {
+				Algorithm::Phash => phash(&img, &self.dct),
+				Algorithm::Dhash => dhash(&img),
+				Algorithm::Ahash => ahash(&img),
+			})
+			.collect()
+	}
+
+	/// The entry of each algorithm's hash.
+	fn entry_of(&self, hashes: Vec<u64>) -> Entry {
+		Entry {
+			phash: hashes.first().copied(),
+			extra: self.settings.algorithms[1..].iter().zip(&hashes[1..]).map(|(algorithm, hash)| format!("{}={}", algorithm, hash)).collect(),
+		}
+	}
+
+	#[cfg(feature = "pdf")]
+	fn hash_pdf(&self, data: &[u8], page: u32) -> anyhow::Result<Vec<Entry>> {
+		Ok(self.hash_image(&crate::pdf::render_page(data, page)?))
+	}
+
+	#[cfg(not(feature = "pdf"))]
+	fn hash_pdf(&self, _data: &[u8], _page: u32) -> anyhow::Result<Vec<Entry>> {
+		anyhow::bail!("PDF inputs require building with the `pdf` feature")
+	}
+
+	#[cfg(feature = "video")]
+	fn hash_video(&self, path: &Path, frames: crate::settings::VideoFrames) -> anyhow::Result<Vec<Entry>> {
+		let frames = crate::video::read_frames(&self.source.resolve(path), frames)?;
+
+		Ok(frames.into_iter().flat_map(|(timestamp, img)| labelled(self.hash_image(&img), format!("t={}", timestamp))).collect())
+	}
+
+	#[cfg(not(feature = "video"))]
+	fn hash_video(&self, _path: &Path, _frames: crate::settings::VideoFrames) -> anyhow::Result<Vec<Entry>> {
+		anyhow::bail!("Video inputs require building with the `video` feature")
+	}
+}
+
+
+/// What kind of failure an error computing an input's entries is: `unsupported` for formats this build can't decode,
+/// `decode` for corrupt images, `not-found`, `permission` or `io` for inputs that couldn't be read, else `other`.
+pub fn error_category(err: &anyhow::Error) -> &'static str {
+	if err.is::<UnsupportedFormat>() {
+		return "unsupported";
+	}
+
+	for cause in err.chain() {
+		if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+			return match err.kind() {
+				std::io::ErrorKind::NotFound => "not-found",
+				std::io::ErrorKind::PermissionDenied => "permission",
+				_ => "io",
+			};
+		}
+		if cause.is::<image::ImageError>() {
+			return "decode";
+		}
+	}
+
+	"other"
+}
+
+
+/// When a step started, for logging how long it took.  None in the browser, where `Instant` has no clock to read.
+fn now() -> Option<Instant> {
+	#[cfg(target_arch = "wasm32")]
+	return None;
+	#[cfg(not(target_arch = "wasm32"))]
+	Some(Instant::now())
+}
+
+
+/// Milliseconds since `start` to two decimal places, for logging.
+fn millis(start: Option<Instant>) -> Option<f64> {
+	start.map(|start| (start.elapsed().as_secs_f64() * 100_000.0).round() / 100.0)
+}
+
+
+/// The hash with each bit set that is set in more than half of some hashes.
+fn majority(hashes: impl Iterator<Item = u64> + Clone) -> u64 {
+	let count = hashes.clone().count();
+	(0..64).filter(|bit| hashes.clone().filter(|hash| hash >> bit & 1 == 1).count() * 2 > count).fold(0, |hash, bit| hash | 1 << bit)
+}
+
+
+/// Prefix the extra columns of entries with a column identifying the part of the input they were computed from.
+fn labelled(entries: Vec<Entry>, label: String) -> impl Iterator<Item = Entry> {
+	entries.into_iter().map(move |mut entry| {
+		entry.extra.insert(0, label.clone());
+		entry
+	})
+}
+
+
+/// Number of frames hashed for `Frames::Representative`.
+const REPRESENTATIVE_FRAMES: usize = 5;
+
+
+/// Indices of frames spread evenly across an animation of `count` frames, always including the first and last.
+fn representative_frames(count: usize) -> Vec<usize> {
+	if count <= REPRESENTATIVE_FRAMES {
+		return (0..count).collect();
+	}
+
+	(0..REPRESENTATIVE_FRAMES).map(|i| i * (count - 1) / (REPRESENTATIVE_FRAMES - 1)).collect()
+}
@@ -0,0 +1,110 @@
+//! Finding hashes within a Hamming distance of each other.
+
+
+/// Trees with at most this many hashes are always searched by scanning every hash.
+const SCAN_SIZE: usize = 1024;
+
+/// Searches within a larger threshold scan every hash too: they visit so much of the tree that the scan, which
+/// vectorizes, is faster.
+const SCAN_THRESHOLD: u32 = 2;
+
+
+/// A BK-tree over hashes, each carrying a value.
+pub struct BkTree<T> {
+	nodes: Vec<Node<T>>,
+	/// The hash of each node, contiguous for scanning.
+	phashes: Vec<u64>,
+}
+
+struct Node<T> {
+	phash: u64,
+	value: T,
+	/// Child node indices, by their distance from this node.
+	children: Vec<(u32, usize)>,
+}
+
+impl<T> BkTree<T> {
+	pub fn new() -> Self {
+		BkTree {
+			nodes: Vec::new(),
+			phashes: Vec::new(),
+		}
+	}
+
+	pub fn insert(&mut self, phash: u64, value: T) {
+		let new = self.nodes.len();
+		self.nodes.push(Node {
+			phash,
+			value,
+			children: Vec::new(),
+		});
+		self.phashes.push(phash);
+
+		if new == 0 {
+			return;
+		}
+
+		let mut current = 0;
+		loop {
+			let distance = (self.nodes[current].phash ^ phash).count_ones();
+			match self.nodes[current].children.iter().find(|(d, _)| *d == distance) {
+				Some(&(_, child)) => current = child,
+				None => {
+					self.nodes[current].children.push((distance, new));
+					return;
+				},
+			}
+		}
+	}
+
+	/// Every value whose hash is within `threshold` of `phash`, with its distance.
+	pub fn find(&self, phash: u64, threshold: u32) -> Vec<(&T, u32)> {
+		if self.nodes.len() <= SCAN_SIZE || threshold >= SCAN_THRESHOLD {
+			let mut distances = Vec::new();
+			hamming_distances(phash, &self.phashes, &mut distances);
+
+			return distances
+				.into_iter()
+				.zip(&self.nodes)
+				.filter(|(distance, _)| *distance <= threshold)
+				.map(|(distance, node)| (&node.value, distance))
+				.collect();
+		}
+
+		let mut found = Vec::new();
+		let mut pending = if self.nodes.is_empty() { vec![] } else { vec![0] };
+
+		while let Some(index) = pending.pop() {
+			let node = &self.nodes[index];
+			let distance = (node.phash ^ phash).count_ones();
+
+			if distance <= threshold {
+				found.push((&node.value, distance));
+			}
+
+			// By the triangle inequality, matches can only be under children at a distance within threshold of this one
+			pending.extend(node.children.iter().filter(|(d, _)| d.abs_diff(distance) <= threshold).map(|(_, child)| *child));
+		}
+
+		found
+	}
+}
+
+
+/// Number of hashes `hamming_distances` compares at once.
+const LANES: usize = 8;
+
+
+/// Replace `distances` with the Hamming distance between `phash` and each of `phashes`.  Hashes are compared in
+/// fixed-size blocks, which the compiler turns into SIMD instructions.
+pub fn hamming_distances(phash: u64, phashes: &[u64], distances: &mut Vec<u32>) {
+	distances.clear();
+	distances.reserve(phashes.len());
+
+	let mut blocks = phashes.chunks_exact(LANES);
+	for block in &mut blocks {
+		let block: [u32; LANES] = std::array::from_fn(|i| (block[i] ^ phash).count_ones());
+		distances.extend_from_slice(&block);
+	}
+	distances.extend(blocks.remainder().iter().map(|other| (other ^ phash).count_ones()));
+}
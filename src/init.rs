@@ -0,0 +1,90 @@
+//! Sets up a new output file (`init`): checks that images can be hashed with the chosen parameters and that the output
+//! file can be written where it is meant to be kept, then writes its header so that later runs resume it.
+use image::{imageops::FilterType, DynamicImage, RgbImage};
+use std::{io::Cursor, path::PathBuf};
+
+use crate::{
+	cache::{read_result, write_environment, write_header},
+	hasher::Hasher,
+	settings::SettingsArgs,
+	source::{Source, SourceArgs},
+	output_error, storage,
+};
+use tracing::{error, info};
+
+
+#[derive(clap::Args, Debug)]
+pub struct InitArgs {
+	/// Output file to create: a local path or, with the `object-store` feature, an `s3://`, `gs://` or `az://` URL.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	#[command(flatten)]
+	settings: SettingsArgs,
+}
+
+
+/// Size of the test image hashed to check the parameters.
+const TEST_IMAGE_SIZE: u32 = 256;
+
+
+/// Create the output file with a header recording the hashing parameters, after hashing a test image with them.  An
+/// output file that already has the same parameters is left as it is.
+pub fn run(args: InitArgs) {
+	let settings = args.settings.settings();
+	let metadata = settings.metadata();
+
+	// Hash a test image and a smaller copy of it, which should match
+	let hasher = Hasher::new(settings, Source::new(&SourceArgs::default()));
+	let img = DynamicImage::ImageRgb8(RgbImage::from_fn(TEST_IMAGE_SIZE, TEST_IMAGE_SIZE, |x, y| image::Rgb([x as u8, y as u8, ((x ^ y) & 0xc0) as u8])));
+	let copy = img.resize_exact(TEST_IMAGE_SIZE / 2, TEST_IMAGE_SIZE / 2, FilterType::Triangle);
+	let hash = |img: &DynamicImage| hasher.hash_image(img).first().and_then(|entry| entry.phash);
+
+	let Some(distance) = hash(&img).zip(hash(&copy)).map(|(a, b)| (a ^ b).count_ones()) else {
+		error!("Error: the test image couldn't be hashed with these parameters ({})", metadata);
+		std::process::exit(1);
+	};
+
+	let mut output = storage::open(&args.output, Default::default()).unwrap_or_else(|err| output_error(&args.output, err));
+	if let Some(contents) = output.read().unwrap_or_else(|err| output_error(&args.output, err)).filter(|contents| !contents.is_empty()) {
+		let existing = read_result(&mut Cursor::new(&contents)).metadata;
+		if existing != metadata {
+			error!("Error: {} already exists with different parameters ({}) than these ({})", args.output.display(), existing, metadata);
+			if let Some(flags) = existing.legacy_flags(&metadata) {
+				error!("It was written before these defaults changed; initialize it with {}", flags.join(" "));
+			}
+			std::process::exit(1);
+		}
+
+		info!("{} already exists with these parameters", args.output.display());
+		return;
+	}
+
+	let mut header = Cursor::new(Vec::new());
+	write_header(&mut header, &metadata).unwrap();
+	write_environment(&mut header, crate::version::environment()).unwrap();
+	output.append(header.get_ref()).unwrap_or_else(|err| output_error(&args.output, err));
+	output.flush().unwrap_or_else(|err| output_error(&args.output, err));
+
+	// Read the header back, as a later run will
+	let written = storage::open(&args.output, Default::default()).and_then(|mut output| output.read()).unwrap_or_else(|err| output_error(&args.output, err));
+	if written.is_none_or(|written| read_result(&mut Cursor::new(&written)).metadata != metadata) {
+		output_error(&args.output, anyhow::anyhow!("the header written to it couldn't be read back"));
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"init",
+		serde_json::json!({
+			"output": args.output,
+			"metadata": metadata.to_string(),
+		}),
+	);
+
+	info!("Created {} for hashes with {}", args.output.display(), metadata);
+	info!("A test image and a half-size copy of it hashed {} bits apart", distance);
+	info!(
+		"Hash images into it with `hasher -i <list of images> -o {}` and the same hashing options as given here",
+		args.output.display()
+	);
+}
@@ -0,0 +1,90 @@
+//! The hashing core as a library, for applications that would otherwise run the CLI once per image: a Rust API with
+//! the `lib` feature, used by the Python bindings in `python/`, and C bindings with the `ffi` feature (see `Cargo.toml`),
+//! declared in `include/phash.h`.  Hashing decoded pixels also builds for `wasm32-unknown-unknown`, for the JavaScript
+//! bindings in `wasm/`.
+//!
+//! Images are hashed with the CLI's default parameters, so that hashes match those `hasher` writes without options.
+#![cfg(feature = "lib")]
+// The CLI's modules, of which the bindings only use the hashing core
+#![allow(dead_code)]
+
+mod animation;
+mod budget;
+mod cache;
+mod codecs;
+mod compat;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "failpoints")]
+mod failpoint;
+mod hasher;
+mod orientation;
+mod pdf;
+mod phash;
+#[cfg(any(feature = "video", feature = "pdf"))]
+mod ppm;
+mod preprocess;
+#[cfg(feature = "raw")]
+mod raw;
+mod settings;
+mod shards;
+mod snapshot;
+mod source;
+mod storage;
+mod thumbnail;
+mod video;
+
+use clap::{Args, Command, FromArgMatches};
+use image::{DynamicImage, RgbaImage};
+use std::{path::Path, sync::OnceLock};
+
+use crate::{
+	hasher::Hasher,
+	settings::SettingsArgs,
+	source::{Source, SourceArgs},
+};
+
+
+/// A hasher with the CLI's defaults, shared by every call.
+fn hasher() -> &'static Hasher {
+	static HASHER: OnceLock<Hasher> = OnceLock::new();
+
+	HASHER.get_or_init(|| {
+		let matches = SettingsArgs::augment_args(Command::new("phash")).get_matches_from(["phash"]);
+		let settings = SettingsArgs::from_arg_matches(&matches).expect("defaults are valid").settings();
+		Hasher::new(settings, Source::new(&SourceArgs::default()))
+	})
+}
+
+
+/// The perceptual hash of an encoded image.
+pub fn hash_bytes(data: &[u8]) -> anyhow::Result<u64> {
+	first_hash(hasher().hash_bytes(data)?)
+}
+
+
+/// The perceptual hash of an image already decoded into RGBA pixels, 4 bytes each, row by row.  Pixels are hashed as
+/// given, so EXIF orientation must already be applied to match `hash_bytes`.  Needs neither a filesystem nor threads,
+/// so that the hash can be computed in a browser (see `wasm/`).
+pub fn hash_rgba(pixels: Vec<u8>, width: u32, height: u32) -> anyhow::Result<u64> {
+	let img = RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow::anyhow!("Expected {} bytes of pixels", width as u64 * height as u64 * 4))?;
+	first_hash(hasher().hash_image(&DynamicImage::ImageRgba8(img)))
+}
+
+
+/// The perceptual hash of an image file, or of the first frame of a video with the `video` feature.
+pub fn hash_file(path: &Path) -> anyhow::Result<u64> {
+	first_hash(hasher().hash(path)?)
+}
+
+
+fn first_hash(entries: Vec<cache::Entry>) -> anyhow::Result<u64> {
+	// Every image has a hash unless trivial ones are skipped, which they aren't by default
+	entries.first().and_then(|entry| entry.phash).ok_or_else(|| anyhow::anyhow!("No hash computed"))
+}
+
+
+/// The Hamming distance between two hashes: the number of bits in which they differ, from 0 to 64.
+pub fn distance(a: u64, b: u64) -> u32 {
+	(a ^ b).count_ones()
+}
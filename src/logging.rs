@@ -0,0 +1,34 @@
+//! Logs to stderr with `tracing`, at a verbosity chosen with `-v` and `-q`.
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+
+
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+	/// Log more: `-v` also logs an event for each input, such as how long it took to decode and how large it is, and
+	/// `-vv` everything, including the events of libraries.
+	#[arg(short, long, global = true, action = clap::ArgAction::Count)]
+	verbose: u8,
+
+	/// Quiet mode.  Only logs warnings and errors, and suppresses progress bars.
+	#[arg(short, long, global = true, conflicts_with = "verbose")]
+	quiet: bool,
+}
+
+impl LogArgs {
+	/// Start logging.  Messages are written as they always were, followed by the fields of structured events and the
+	/// input they concern, if any.
+	pub fn init(&self) {
+		let (level, libraries) = match (self.quiet, self.verbose) {
+			(true, _) => (LevelFilter::WARN, LevelFilter::WARN),
+			(false, 0) => (LevelFilter::INFO, LevelFilter::WARN),
+			(false, 1) => (LevelFilter::DEBUG, LevelFilter::WARN),
+			(false, _) => (LevelFilter::TRACE, LevelFilter::TRACE),
+		};
+
+		tracing_subscriber::registry()
+			.with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr).without_time().with_target(false).with_level(false))
+			.with(Targets::new().with_target(env!("CARGO_CRATE_NAME"), level).with_default(libraries))
+			.init();
+	}
+}
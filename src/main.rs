@@ -1,212 +1,951 @@
+mod action;
+mod animation;
+#[cfg(feature = "attest")]
+mod attest;
+#[cfg(feature = "audit")]
+mod audit;
+mod budget;
+#[cfg(feature = "bundle")]
+mod bundle;
+mod cache;
+mod capture;
+mod carve;
+mod codecs;
+mod compat;
+mod compare;
+mod config;
+#[cfg(feature = "conformance")]
+mod conformance;
+mod dedupe;
+mod diff;
+mod explain_threshold;
+mod export;
+#[cfg(feature = "failpoints")]
+mod failpoint;
+mod failures;
+mod hasher;
+mod index;
+mod init;
+mod logging;
+mod manifest;
+mod matching;
+mod merge;
+mod new_since;
+mod notify;
+mod offline;
+mod orientation;
+mod pdf;
+mod phash;
+#[cfg(feature = "http")]
+mod photo_managers;
+#[cfg(feature = "photos")]
+mod photos;
+#[cfg(any(feature = "video", feature = "pdf"))]
+mod ppm;
+mod preprocess;
+mod priority;
+mod progress;
+#[cfg(feature = "raw")]
+mod raw;
+mod recommend;
+mod report;
+mod robustness;
+mod sample;
+mod self_test;
+mod settings;
+mod shadow;
+mod shards;
+mod snapshot;
+mod soft_match;
+mod source;
+mod stats;
+mod storage;
+#[cfg(feature = "takeout")]
+mod takeout;
+mod thumbnail;
+mod verify;
+mod version;
+mod video;
+mod xattr;
+
 use anyhow::Context;
-use clap::Parser;
-use image::{self, imageops, io::Reader as ImageReader};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use indicatif::{ParallelProgressIterator, ProgressBar};
-use nalgebra::SMatrix;
 use rayon::prelude::*;
 use std::{
-	collections::{HashMap, HashSet},
+	collections::HashMap,
 	fs::File,
-	io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+	io::{BufRead, BufReader, Cursor, Read},
 	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		mpsc::RecvTimeoutError,
+	},
 	thread,
+	time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::{format_phash, read_result, write_entry, write_environment, write_header, write_run_environment, Entry},
+	codecs::UnsupportedFormat,
+	failures::{FailureArgs, Failures},
+	hasher::{error_category, Hasher},
+	logging::LogArgs,
+	manifest::ManifestArgs,
+	notify::{Notifier, NotifyArgs, Summary},
+	offline::OfflineArgs,
+	progress::ProgressFormat,
+	sample::SampleBy,
+	settings::SettingsArgs,
+	shadow::ShadowArgs,
+	shards::ShardArgs,
+	snapshot::{Snapshot, SnapshotSpec},
+	source::{Source, SourceArgs},
+	storage::FlushPolicy,
+	xattr::Lookup,
 };
-
-type Matrix32x32 = SMatrix<f32, 32, 32>;
 
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+#[command(after_help = "Hashing runs exit with status 0 if every input was hashed, 2 if some failed, and 1 on fatal errors, including \
+                        stopping early with --fail-fast or --max-errors.")]
+struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[command(flatten)]
+	args: Args,
+
+	#[command(flatten)]
+	log: LogArgs,
+
+	/// Append a record of this invocation, its parameters and its results to a hash-chained audit log.
+	#[cfg(feature = "audit")]
+	#[arg(long, global = true)]
+	audit_log: Option<PathBuf>,
+
+	/// Number of threads to decode and hash with.  Defaults to one per CPU.
+	#[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+	threads: Option<u32>,
+
+	/// Run at a low CPU and I/O priority, so as not to slow down anything else, e.g. when running in the background on a
+	/// desktop.  I/O priority is only lowered on Linux, where inputs are then read only while the disk is otherwise idle.
+	#[arg(long, global = true)]
+	nice: bool,
+}
+
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Create an output file for hashing with the given parameters, after checking that images can be hashed with them
+	/// and the output file written.
+	Init(init::InitArgs),
+
+	/// Re-compute hashes for cached entries and report any that no longer match.
+	Verify(verify::VerifyArgs),
+
+	/// Combine several output files into one.
+	Merge(merge::MergeArgs),
+
+	/// List pairs of perceptual duplicates in an output file.
+	Dedupe(dedupe::DedupeArgs),
+
+	/// List pairs of similar inputs by a weighted combination of the distances between several algorithms' hashes, or
+	/// compare two inputs that way.
+	Compare(compare::CompareArgs),
+
+	/// Report how the hashes of an output file are distributed, to tune thresholds and check algorithms.
+	Stats(stats::StatsArgs),
+
+	/// Hash a sample of a collection's images and their altered copies with each algorithm, and recommend an algorithm
+	/// and threshold for the collection.
+	Recommend(recommend::RecommendArgs),
+
+	/// Show how far common alterations move the hashes of the built-in test patterns with the given hashing options,
+	/// and which of them each threshold still matches, to choose thresholds such as `dedupe -t` by.
+	ExplainThreshold(explain_threshold::ExplainThresholdArgs),
+
+	/// Write a list derived from an output file, such as rsync filter rules that skip duplicates.
+	Export(export::ExportArgs),
+
+	/// List the paths added to, removed from and changed between two output files.
+	Diff(diff::DiffArgs),
+
+	/// List the images in an output file that are new since an older one, ignoring copies of images already in it.
+	NewSince(new_since::NewSinceArgs),
+
+	/// Hash a list of images and report those that match any in a reference output file, without adding them to it.
+	Match(matching::MatchArgs),
+
+	/// Check that this build reproduces the known hashes of generated test patterns, e.g. after upgrading dependencies.
+	SelfTest(self_test::SelfTestArgs),
+
+	/// Hash the images listed in an input file into an output file, or a single image, printing its hash to stdout.
+	/// Running `hasher` without a subcommand is the same as `hasher hash`.
+	Hash(Box<HashArgs>),
+
+	/// Sign an output file with a minisign key.
+	#[cfg(feature = "attest")]
+	Attest(attest::AttestArgs),
+
+	/// Check an output file against the signature made by `attest`.
+	#[cfg(feature = "attest")]
+	VerifyAttestation(attest::VerifyAttestationArgs),
+
+	/// Check that an audit log's records are intact.
+	#[cfg(feature = "audit")]
+	VerifyAuditLog(audit::VerifyAuditLogArgs),
+
+	/// Package an output file, its settings, thumbnails of clustered images and an audit log into a tar archive.
+	#[cfg(feature = "bundle")]
+	Bundle(bundle::BundleArgs),
+
+	/// Hash a list of images with each `--compat` mode and with the library it reproduces, and report how often they agree.
+	#[cfg(feature = "conformance")]
+	Conformance(conformance::ConformanceArgs),
+}
+
+
+#[derive(clap::Args, Debug)]
 struct Args {
-	/// Input file. Each line is expected to be a path to an image. If "-", read from stdin.
+	/// Input file. Each line is expected to be a path to an image, or a URL with the `http` feature. If "-", read from stdin.
+	/// A line of the form `path@offset:len` hashes the `len` bytes of the file at `offset`, e.g. an image embedded in an
+	/// archive as found by a carving tool, without extracting it.  Offsets and lengths can be hexadecimal with `0x`.
 	#[arg(short, long, default_value = "-")]
 	input: String,
 
+	/// Hash the originals of a macOS Photos library instead of reading an input file.
+	/// Each entry gets a `uuid=` column with its asset UUID.
+	#[cfg(feature = "photos")]
+	#[arg(long, conflicts_with = "input")]
+	photos_library: Option<PathBuf>,
+
+	/// Hash the media of an extracted Google Takeout export instead of reading an input file.
+	/// Each entry gets `taken=` and `album=` columns from the export's JSON metadata, when available.
+	#[cfg(feature = "takeout")]
+	#[arg(long, conflicts_with = "input")]
+	takeout: Option<PathBuf>,
+
+	/// Hash the JPEG and PNG images found in a raw disk image or other container instead of reading an input file.
+	/// Each is hashed in place as a `path@offset:len` input, recording where it was found, with a `carved=` column
+	/// naming its format.
+	#[arg(long, value_name = "DISK_IMAGE", conflicts_with = "input")]
+	carve: Option<PathBuf>,
+
 	/// Output file.  Will be re-read on subsequent runs to avoid recomputing phashes.
-	#[arg(short, long)]
-	output: PathBuf,
+	/// With the `object-store` feature it can be an `s3://bucket/key`, `gs://bucket/key` or `az://container/key` URL,
+	/// configured by the usual `AWS_*`, `GOOGLE_*` or `AZURE_*` environment variables.  Several workers can share one
+	/// such output file.
+	#[arg(short, long, required = true)]
+	output: Option<PathBuf>,
+
+	/// How progress is shown.  Only `bar` shows a progress bar.
+	#[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+	progress: ProgressFormat,
+
+	/// Take a read-only snapshot of a filesystem and hash inputs under it from the snapshot, releasing it when done.
+	/// One of `zfs:<dataset>`, `btrfs:<subvolume>` or `lvm:<vg>/<lv>`.  Paths in the output file are unchanged.
+	#[arg(long, value_name = "SPEC", conflicts_with = "forensic")]
+	snapshot: Option<SnapshotSpec>,
+
+	/// Only hash a sample of this many inputs, then estimate how many duplicates the whole input list holds.
+	/// Sampled hashes are written to the output file as usual.  Inputs are sampled by a hash of their paths, so a run
+	/// resumed with the same input list samples the same ones, in whatever order they are listed, and one resumed with
+	/// a changed list only samples differently where paths were added or removed.
+	#[arg(long, value_name = "N")]
+	sample: Option<usize>,
+
+	/// How the sample is drawn.
+	#[arg(long, value_enum, default_value_t = SampleBy::Random, requires = "sample")]
+	sample_by: SampleBy,
+
+	/// Maximum Hamming distance at which sampled inputs count as duplicates.
+	#[arg(long, default_value_t = 0, requires = "sample")]
+	sample_threshold: u32,
+
+	/// Limit how many pixels are decoded at once (e.g. `500M`), waiting to decode images that would exceed it.  Sizes
+	/// are read from image headers; images whose size can't be told aren't limited.  Use when decoding many large
+	/// images in parallel runs out of memory; decoded images take about 4 bytes per pixel, plus working copies.
+	#[arg(long, value_name = "PIXELS", value_parser = budget::parse_pixels)]
+	max_pixels_in_flight: Option<u64>,
+
+	/// Read inputs on this many dedicated threads, in path order, ahead of the threads that decode and hash them.
+	/// Keeps the CPUs busy when reads are slow and random reads slower still, as on spinning disks and network
+	/// filesystems.  By default each hashing thread reads its own inputs.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+	readers: Option<u32>,
+
+	/// Also cache each input's entries in an extended attribute on the file (an alternate data stream on Windows), along
+	/// with the parameters they were computed with and the file's size and modification time, and use them when those
+	/// still match.  Lets runs skip files hashed before without the output file they were written to.  Filesystems
+	/// without extended attributes or streams, byte ranges and URLs are hashed as usual.
+	#[arg(long, conflicts_with = "forensic")]
+	xattr_cache: bool,
+
+	/// Stop hashing as soon as an input fails, as with `--max-errors 1`.
+	#[arg(long, conflicts_with = "max_errors")]
+	fail_fast: bool,
+
+	/// Stop hashing once this many inputs have failed, when something is systematically wrong, e.g. the disk they are
+	/// on has disappeared.  Hashes computed until then are kept in the output file.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+	max_errors: Option<u64>,
+
+	#[command(flatten)]
+	offline: OfflineArgs,
+
+	#[command(flatten)]
+	failures: FailureArgs,
+
+	#[command(flatten)]
+	manifest: ManifestArgs,
+
+	#[command(flatten)]
+	shadow: ShadowArgs,
+
+	#[cfg(feature = "failpoints")]
+	#[command(flatten)]
+	failpoints: failpoint::FailpointArgs,
+
+	#[command(flatten)]
+	flush: FlushArgs,
+
+	#[command(flatten)]
+	shards: ShardArgs,
+
+	#[command(flatten)]
+	settings: SettingsArgs,
+
+	#[command(flatten)]
+	source: SourceArgs,
+
+	#[command(flatten)]
+	notify: NotifyArgs,
+}
+
+
+#[derive(clap::Args, Debug)]
+struct FlushArgs {
+	/// Write hashes to the output file once this many inputs have been hashed, rather than after every one (the
+	/// default for local files).  Output files stay readable after a crash; only the unwritten hashes are lost.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+	flush_every: Option<u32>,
+
+	/// Write hashes to the output file at least this often, in seconds.  With `--flush-every`, whichever comes first.
+	/// Output files in object storage are written every 30 seconds by default.
+	#[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+	flush_interval: Option<u64>,
+}
 
-	/// Quiet mode.  Suppresses progress bar.
-	#[arg(short, long, default_value = "false")]
-	quiet: bool,
+impl FlushArgs {
+	fn policy(&self) -> FlushPolicy {
+		FlushPolicy {
+			every: self.flush_every.map(|every| every as usize),
+			interval: self.flush_interval.map(Duration::from_secs),
+		}
+	}
+}
+
+
+#[derive(clap::Args, Debug)]
+#[command(mut_arg("output", |arg| arg.required(false).required_unless_present("image")))]
+struct HashArgs {
+	/// Image to hash on its own instead of an input file, without touching any output file.  If "-", the image's raw
+	/// bytes are read from stdin.
+	#[arg(conflicts_with_all = ["input", "output"])]
+	image: Option<PathBuf>,
+
+	#[command(flatten)]
+	args: Args,
 }
 
 
 fn main() {
-	let args = Args::parse();
+	let command = config::apply(Cli::command());
+	let matches = command.clone().get_matches();
+	check_bare_args(command, &matches);
+	let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+	cli.log.init();
+
+	// Before any threads are started, so that they all inherit the signal mask and the priority
+	let snapshotting = match &cli.command {
+		None => cli.args.snapshot.is_some(),
+		Some(Command::Hash(hash)) => hash.args.snapshot.is_some(),
+		Some(_) => false,
+	};
+	if snapshotting {
+		snapshot::release_on_signals();
+	}
+	if cli.nice {
+		priority::lower();
+	}
+
+	if let Some(threads) = cli.threads {
+		rayon::ThreadPoolBuilder::new().num_threads(threads as usize).build_global().unwrap();
+	}
+
+	#[cfg(feature = "audit")]
+	if let Some(log) = &cli.audit_log {
+		audit::open(log).unwrap_or_else(|err| {
+			error!("Error opening audit log {}: {:#}", log.display(), err);
+			std::process::exit(1);
+		});
+		audit::record("invoke", serde_json::json!({ "args": std::env::args().collect::<Vec<_>>() }));
+	}
 
-	let dct_matrix = get_dct_matrix(32);
-	let dct_matrix_t = dct_matrix.transpose();
+	match cli.command {
+		Some(Command::Init(args)) => init::run(args),
+		Some(Command::Verify(args)) => verify::run(args),
+		Some(Command::Merge(args)) => merge::run(args),
+		Some(Command::Dedupe(args)) => dedupe::run(args),
+		Some(Command::Compare(args)) => compare::run(args),
+		Some(Command::Stats(args)) => stats::run(args),
+		Some(Command::Recommend(args)) => recommend::run(args),
+		Some(Command::ExplainThreshold(args)) => explain_threshold::run(args),
+		Some(Command::Export(args)) => export::run(args),
+		Some(Command::Diff(args)) => diff::run(args),
+		Some(Command::NewSince(args)) => new_since::run(args),
+		Some(Command::Match(args)) => matching::run(args),
+		Some(Command::SelfTest(args)) => self_test::run(args),
+		Some(Command::Hash(hash)) => match *hash {
+			HashArgs { image: Some(image), args } => run_hash_one(&image, args),
+			HashArgs { image: None, args } => run_hash(args),
+		},
+		#[cfg(feature = "attest")]
+		Some(Command::Attest(args)) => attest::run_attest(args),
+		#[cfg(feature = "attest")]
+		Some(Command::VerifyAttestation(args)) => attest::run_verify_attestation(args),
+		#[cfg(feature = "audit")]
+		Some(Command::VerifyAuditLog(args)) => audit::run_verify(args),
+		#[cfg(feature = "bundle")]
+		Some(Command::Bundle(args)) => bundle::run(args),
+		#[cfg(feature = "conformance")]
+		Some(Command::Conformance(args)) => conformance::run(args),
+		None => run_hash(cli.args),
+	}
+}
+
+
+/// Exit with a usage error if options of the bare invocation, such as `--output`, are given with a subcommand, which
+/// would ignore them.  Global options, such as `--threads`, can be given before or after the subcommand.
+fn check_bare_args(mut command: clap::Command, matches: &clap::ArgMatches) {
+	let Some(subcommand) = matches.subcommand_name() else {
+		return;
+	};
+
+	let bare = <Args as clap::Args>::augment_args(clap::Command::new("hash"));
+	let given = bare.get_arguments().find(|arg| matches.value_source(arg.get_id().as_str()) == Some(clap::parser::ValueSource::CommandLine));
+	if let Some(given) = given {
+		command.build();
+		let arg = command.get_arguments().find(|arg| arg.get_id() == given.get_id()).map(|arg| arg.to_string()).unwrap_or_default();
+		command.error(clap::error::ErrorKind::ArgumentConflict, format!("the subcommand '{}' cannot be used with '{}'", subcommand, arg)).exit();
+	}
+}
+
+
+fn run_hash(args: Args) {
+	let start = Instant::now();
+	#[cfg(feature = "failpoints")]
+	failpoint::configure(&args.failpoints);
+	let replay = manifest::replay(&args.manifest).unwrap_or_else(|err| {
+		error!("Error replaying run: {:#}", err);
+		exit(1);
+	});
+	let (settings, replay_inputs) = match replay.map(|manifest| manifest.into_run()).transpose() {
+		Ok(Some((settings, inputs))) => (settings, Some(inputs)),
+		Ok(None) => (args.settings.settings(), None),
+		Err(err) => {
+			error!("Error replaying run: {:#}", err);
+			exit(1);
+		},
+	};
+	let notifier = Notifier::new(&args.notify);
 
 	// Read output
-	let mut output_file = File::options().read(true).write(true).create(true).open(&args.output).unwrap();
+	let output_path = args.output.as_ref().expect("clap enforces --output");
+	let mut output = storage::open(output_path, args.flush.policy()).unwrap_or_else(|err| output_error(output_path, err));
+	let contents = output.read().unwrap_or_else(|err| output_error(output_path, err)).unwrap_or_default();
+
+	// A sharded output file is a manifest, and its shards are each read and written like an unsharded one
+	let sharding = shards::resolve(&args.shards, output_path, &mut *output, &contents).unwrap_or_else(|err| output_error(output_path, err));
+	let mut outputs = match &sharding {
+		None => vec![(output_path.clone(), output, contents)],
+		Some(manifest) => manifest
+			.paths
+			.iter()
+			.map(|path| {
+				let mut shard = storage::open(path, args.flush.policy()).unwrap_or_else(|err| output_error(path, err));
+				let contents = shard.read().unwrap_or_else(|err| output_error(path, err)).unwrap_or_default();
+				(path.clone(), shard, contents)
+			})
+			.collect(),
+	};
+
+	let metadata = settings.metadata();
+	let environment = version::environment();
+	let mut cache = HashMap::new();
+	let mut environment_checked = false;
+	for (path, output, contents) in &mut outputs {
+		// Read output file to get the list of images that have already been processed
+		let mut reader = Cursor::new(&*contents);
+		let results = read_result(&mut reader);
+		cache.extend(results.hashes);
+
+		// Drop anything after the last complete line, which an interrupted run may have left
+		let valid_len = reader.position();
+		if valid_len < contents.len() as u64 {
+			output.truncate(valid_len).unwrap_or_else(|err| output_error(path, err));
+		}
+
+		// Refuse to mix hashes computed with different parameters in the same file
+		let is_new = valid_len == 0;
+		if !is_new && results.metadata != metadata {
+			error!("Error: output file was written with different parameters ({}) than this run ({})", results.metadata, metadata);
+			if let Some(flags) = results.metadata.legacy_flags(&metadata) {
+				error!("It was written before these defaults changed; resume it with {}", flags.join(" "));
+			}
+			exit(1);
+		}
+
+		// Unlike the parameters, the build and machine may differ, but it's worth knowing if they do
+		let last = results.resumed.last().unwrap_or(&results.environment);
+		if !is_new && !last.is_empty() && !environment_checked {
+			environment_checked = true;
+			let changes = environment
+				.iter()
+				.filter_map(|(key, value)| {
+					let recorded = last.get(*key).map(String::as_str).unwrap_or("none");
+					(recorded != value).then(|| format!("{} {} -> {}", key, recorded, value))
+				})
+				.collect::<Vec<_>>();
+			if !changes.is_empty() {
+				warn!("Warning: output file was last written by a different build or machine; any hashes that differ may be due to: {}", changes.join("; "));
+			}
+		}
+
+		// Every run records its build and machine, before the entries it writes
+		let mut header = Cursor::new(Vec::new());
+		if is_new {
+			write_header(&mut header, &metadata).unwrap();
+			write_environment(&mut header, environment.iter().cloned()).unwrap();
+		} else {
+			write_run_environment(&mut header, results.resumed.len() + 2, environment.iter().cloned()).unwrap();
+		}
+		output.append(header.get_ref()).unwrap_or_else(|err| output_error(path, err));
+	}
+	let mut outputs = outputs.into_iter().map(|(path, output, _)| (path, output)).collect::<Vec<_>>();
+
+	// Read the list of images from the input file, along with any extra columns the input provides
+	let mut inputs = replay_inputs.unwrap_or_else(|| read_inputs(&args).collect::<HashMap<_, _>>());
+	let population = inputs.len();
+	if !cache.is_empty() {
+		report_resumption(&inputs, &cache);
+	}
+	if let Some(size) = args.sample {
+		inputs = sample::choose(inputs, size, args.sample_by);
+	}
+	manifest::save(&args.manifest, &settings, &inputs).unwrap_or_else(|err| {
+		error!("Error saving manifest: {:#}", err);
+		exit(1);
+	});
 
-	// Read output file to get the list of images that have already been processed
-	let cache = read_result(&mut output_file);
+	// Cached hashes of sampled inputs count towards the estimate along with the newly computed ones
+	let mut sampled_phashes = Vec::new();
+	if args.sample.is_some() {
+		sampled_phashes.extend(inputs.keys().filter_map(|path| cache.get(path)).map(|entries| entries.iter().filter_map(|entry| entry.phash).collect()));
+	}
+
+	// Re-hash a sample of the images in the cache, to check that they haven't changed since
+	let shadow_sample = shadow::choose(&args.shadow, inputs.keys().filter(|path| cache.contains_key(*path)));
 
-	// Read the list of images from the input file
 	// Skip images that are already in the cache
-	let images = read_input_list(&args.input).filter(|path| !cache.contains_key(path)).collect::<HashSet<_>>();
+	let mut images = inputs.into_iter().filter(|(path, _)| !cache.contains_key(path)).collect::<HashMap<_, _>>();
+
+	// And those that failed before in ways that trying again won't fix
+	let failures = Failures::open(&args.failures, |path| cache.contains_key(path)).unwrap_or_else(|err| {
+		error!("Error opening errors file: {:#}", err);
+		exit(1);
+	});
+	if let Some(failures) = &failures {
+		let before = images.len();
+		images.retain(|path, _| !failures.is_permanent(path));
+		if images.len() < before {
+			info!("Skipping {} inputs that failed before; try them again with --retry-failed", before - images.len());
+		}
+	}
+
+	let snapshot = args.snapshot.as_ref().map(|spec| {
+		Snapshot::create(spec).unwrap_or_else(|err| {
+			error!("Error creating snapshot: {:#}", err);
+			exit(1);
+		})
+	});
+	let source = Source::new(&args.source).with_snapshot(snapshot);
+
+	// Nor images whose entries are cached in their extended attributes, which only whole files have
+	let digest = xattr::digest(&metadata);
+	let mut stamps = HashMap::new();
+	let mut xattr_hits = Vec::new();
+	if args.xattr_cache {
+		let lookups = images
+			.par_iter()
+			.filter_map(|(path, _)| {
+				let file = source.whole_file(path)?.into_owned();
+				let lookup = xattr::lookup(&file, digest);
+				Some((path.clone(), file, lookup))
+			})
+			.collect::<Vec<_>>();
+		for (path, file, lookup) in lookups {
+			match lookup {
+				Lookup::Hit(entries) => {
+					let extra = images.remove(&path).unwrap();
+					xattr_hits.push((path, entries, extra));
+				},
+				Lookup::Miss(Some(stamp)) => {
+					stamps.insert(path, (file, stamp));
+				},
+				Lookup::Miss(None) => (),
+			}
+		}
+
+		info!("Found entries for {} inputs in extended attributes", xattr_hits.len());
+	}
+
+	// Hash offline inputs last, if at all, so that they are recalled from archive storage in one go
+	let tiers = offline::split(&args.offline, &images, |path| source.resolve(path).into_owned()).unwrap_or_else(|err| {
+		error!("Error reading offline list: {:#}", err);
+		exit(1);
+	});
+	if tiers.deferred > 0 {
+		info!("Leaving {} offline inputs for a later run", tiers.deferred);
+	}
+	let total = (tiers.online.len() + tiers.offline.len()) as u64;
+
+	let hasher = Hasher::new(settings, source).with_pixel_budget(args.max_pixels_in_flight);
+	let shadow = shadow_sample.map(|paths| shadow::check(&hasher, &paths, &cache));
 
 	// Compute phashes for remaining hashes
-	let (tx, rx): (std::sync::mpsc::SyncSender<(PathBuf, u64)>, _) = std::sync::mpsc::sync_channel(256);
+	let (tx, rx): (std::sync::mpsc::SyncSender<(PathBuf, Vec<Entry>)>, _) = std::sync::mpsc::sync_channel(256);
 
 	// This thread writes the phashes to the file
+	let sampling = args.sample.is_some();
 	let collector_thread = thread::spawn(move || {
-		// Write phashes to the output file
-		for (path, phash) in rx.iter() {
-			let path = path.to_str().unwrap();
-
-			if path.contains('\t') || path.contains('\n') {
-				eprintln!("Warning: path contains tab or newline, it will be skipped: {}", path);
-				continue;
+		let mut written = Vec::new();
+
+		// Write phashes to the output file, waking up now and then to write buffered ones while inputs are slow to hash
+		loop {
+			let (path, entries) = match rx.recv_timeout(POLL_INTERVAL) {
+				Ok(hashed) => hashed,
+				Err(RecvTimeoutError::Timeout) => {
+					for (path, output) in &mut outputs {
+						output.poll().unwrap_or_else(|err| output_error(path, err));
+					}
+					continue;
+				},
+				Err(RecvTimeoutError::Disconnected) => break,
+			};
+
+			let mut lines = Vec::new();
+			for entry in &entries {
+				write_entry(&mut lines, &path, entry).unwrap();
 			}
+			let (output_path, output) = &mut outputs[sharding.as_ref().map_or(0, |manifest| manifest.shard_of(&path))];
+			output.append(&lines).unwrap_or_else(|err| output_error(output_path, err));
 
-			writeln!(output_file, "{}\t{}", path, phash).unwrap();
-			output_file.flush().unwrap();
+			if sampling {
+				written.push(entries.iter().filter_map(|entry| entry.phash).collect::<Vec<_>>());
+			}
 		}
-	});
 
-	let mut iter = images.par_iter().progress_count(images.len() as u64);
+		for (path, output) in &mut outputs {
+			output.flush().unwrap_or_else(|err| output_error(path, err));
+		}
 
-	if args.quiet {
-		iter.progress = ProgressBar::hidden();
-	}
+		written
+	});
 
-	iter.for_each_with(tx, |tx, path| {
-		let phash = match image_path_to_phash(path, &dct_matrix, &dct_matrix_t) {
-			Ok(phash) => phash,
+	let progress = if args.progress == ProgressFormat::Bar { progress::bar(total) } else { ProgressBar::hidden() };
+
+	let hashed = AtomicU64::new(0);
+	let failed = AtomicU64::new(0);
+	let failure_rate_notified = AtomicBool::new(false);
+	let max_errors = if args.fail_fast { Some(1) } else { args.max_errors };
+	let stopped = AtomicBool::new(false);
+	let summary = |event| Summary {
+		event,
+		output: output_path.display().to_string(),
+		total,
+		hashed: hashed.load(Ordering::Relaxed),
+		failed: failed.load(Ordering::Relaxed),
+		elapsed_secs: start.elapsed().as_secs_f64(),
+		shadow_checked: shadow.map(|shadow| shadow.checked),
+		shadow_mismatched: shadow.map(|shadow| shadow.mismatched),
+	};
+
+	let process = |tx: &mut std::sync::mpsc::SyncSender<_>, path: &PathBuf, extra: &Vec<String>, entries: anyhow::Result<Vec<Entry>>| {
+		let mut entries = match entries {
+			Ok(entries) => entries,
 			Err(err) => {
-				eprintln!("Error computing phash for {}: {}", path.display(), err);
+				if let Some(UnsupportedFormat(format)) = err.downcast_ref() {
+					warn!("Unsupported format for {}: {}", path.display(), format);
+				} else {
+					error!(category = %error_category(&err), "Error computing phash for {}: {}", path.display(), err);
+				}
+				if let Some(Err(err)) = failures.as_ref().map(|failures| failures.record(path, error_category(&err), &err)) {
+					warn!("Warning: couldn't record failure of {} in errors file: {}", path.display(), err);
+				}
+
+				let failed = failed.fetch_add(1, Ordering::Relaxed) + 1;
+				let processed = failed + hashed.load(Ordering::Relaxed);
+				if notifier.failure_rate_exceeded(processed, failed) && !failure_rate_notified.swap(true, Ordering::Relaxed) {
+					notifier.notify(&summary("failure-rate"));
+				}
+				if max_errors.is_some_and(|max| failed >= max) && !stopped.swap(true, Ordering::Relaxed) {
+					error!("Error: stopping after {} failed inputs", failed);
+				}
 				return;
 			},
 		};
 
-		tx.send((path.clone(), phash)).unwrap();
-	});
+		hashed.fetch_add(1, Ordering::Relaxed);
 
-	collector_thread.join().unwrap();
-}
+		if let Some((file, stamp)) = stamps.get(path) {
+			xattr::store(file, digest, *stamp, &entries);
+		}
 
+		for entry in &mut entries {
+			entry.extra.extend(extra.iter().cloned());
+		}
 
-fn read_input_list(path_or_stdin: &str) -> impl Iterator<Item = PathBuf> {
-	if path_or_stdin == "-" {
-		let stdin = std::io::stdin();
-		let reader = stdin.lock();
-		Box::new(reader.lines().map_while(Result::ok).map(|line| PathBuf::from(line.trim()))) as Box<dyn Iterator<Item = PathBuf>>
-	} else {
-		let file = File::open(path_or_stdin).unwrap();
-		let reader = BufReader::new(file);
-		Box::new(reader.lines().map_while(Result::ok).map(|line| PathBuf::from(line.trim()))) as Box<dyn Iterator<Item = PathBuf>>
-	}
-}
+		tx.send((path.clone(), entries)).unwrap();
+	};
 
+	let hash_inputs = |inputs: Vec<(&PathBuf, &Vec<String>)>, readers: Option<u32>, tx: std::sync::mpsc::SyncSender<_>| match readers {
+		None => inputs.into_par_iter().progress_with(progress.clone()).for_each_with(tx, |tx, (path, extra)| {
+			if !stopped.load(Ordering::Relaxed) {
+				process(tx, path, extra, hasher.hash(path))
+			}
+		}),
+		Some(readers) => {
+			// Readers take inputs in path order, so that files in the same directory are read one after another
+			let mut queue = inputs;
+			queue.sort_unstable();
+			let queue = std::sync::Mutex::new(queue.into_iter());
+
+			let (input_tx, input_rx) = std::sync::mpsc::sync_channel(PREFETCH_PER_THREAD * rayon::current_num_threads());
+			thread::scope(|scope| {
+				for _ in 0..readers {
+					let (input_tx, queue, hasher, stopped) = (input_tx.clone(), &queue, &hasher, &stopped);
+					scope.spawn(move || loop {
+						let Some((path, extra)) = queue.lock().unwrap().next().filter(|_| !stopped.load(Ordering::Relaxed)) else {
+							break;
+						};
+						input_tx.send((path, extra, hasher.prefetch(path))).unwrap();
+					});
+				}
+				drop(input_tx);
+
+				input_rx.into_iter().par_bridge().progress_with(progress.clone()).for_each_with(tx, |tx, (path, extra, input)| {
+					process(tx, path, extra, input.and_then(|input| hasher.hash_prefetched(path, input)))
+				});
+			});
+		},
+	};
+
+	for (path, mut entries, extra) in xattr_hits {
+		for entry in &mut entries {
+			entry.extra.extend(extra.iter().cloned());
+		}
+		tx.send((path, entries)).unwrap();
+	}
 
-/// Read the output file and return a map of paths to phashes.
-/// Handles the case where the file is truncated.
-/// Leaves the file pointer at the end of the file, ready for appending.
-fn read_result<R: Read + Seek>(reader: &mut R) -> HashMap<PathBuf, u64> {
-	let mut cache = HashMap::new();
+	let (hashing_tx, hashing_rx) = std::sync::mpsc::channel::<()>();
+	thread::scope(|scope| {
+		if args.progress != ProgressFormat::Bar {
+			let counts = || (hashed.load(Ordering::Relaxed) + failed.load(Ordering::Relaxed), failed.load(Ordering::Relaxed));
+			scope.spawn(move || progress::report(args.progress, total, counts, hashing_rx));
+		}
 
-	let mut valid_len = 0;
+		hash_inputs(tiers.online, args.readers, tx.clone());
+		// Offline inputs are read by a single reader unless told otherwise, so that they are recalled one at a time in path order
+		hash_inputs(tiers.offline, Some(args.readers.unwrap_or(1)), tx);
+		drop(hashing_tx);
+	});
 
-	reader.seek(SeekFrom::Start(0)).unwrap();
+	sampled_phashes.extend(collector_thread.join().unwrap());
 
-	let mut reader = BufReader::new(reader);
+	if let Some(size) = args.sample {
+		sample::report(population, size.min(population), &sampled_phashes, args.sample_threshold);
+	}
 
-	loop {
-		let mut line = String::new();
-		match reader.read_line(&mut line) {
-			Ok(0) | Err(_) => break,
-			Ok(_) => (),
-		}
+	let event = if stopped.load(Ordering::Relaxed) { "stopped" } else { "finished" };
 
-		// If it doesn't end in a newline, it's truncated
-		if !line.ends_with('\n') {
-			break;
-		}
+	#[cfg(feature = "audit")]
+	{
+		let mut record = serde_json::to_value(summary(event)).unwrap();
+		record["environment"] = serde_json::json!(environment.iter().cloned().collect::<std::collections::BTreeMap<_, _>>());
+		audit::record("hash-run", record);
+	}
 
-		// Split by tab
-		let mut parts = line.split('\t').map(|part| part.trim()).collect::<Vec<_>>();
+	notifier.notify(&summary(event));
 
-		if parts.len() != 2 {
-			break;
-		}
+	if stopped.load(Ordering::Relaxed) {
+		exit(1);
+	}
+	if failed.load(Ordering::Relaxed) > 0 {
+		exit(EXIT_SOME_FAILED);
+	}
+}
 
-		let phash = match parts.pop().unwrap().parse::<u64>() {
-			Ok(phash) => phash,
-			Err(_) => break,
-		};
 
-		let path = PathBuf::from(parts.pop().unwrap());
+/// Log how the input list of a resumed run differs from the inputs in its output file.  Which inputs a run hashes and
+/// samples, and which shard each goes in, only depend on the paths listed, so adding inputs to the input list, removing
+/// them or reordering it since the output file was written changes nothing for the inputs that stay.
+fn report_resumption(inputs: &HashMap<PathBuf, Vec<String>>, cache: &HashMap<PathBuf, Vec<Entry>>) {
+	let cached = inputs.keys().filter(|path| cache.contains_key(*path)).count();
+	let unlisted = cache.len() - cached;
 
-		cache.insert(path, phash);
-		valid_len = reader.stream_position().unwrap();
+	info!("Resuming: {} of {} inputs are in the output file, {} aren't yet", cached, inputs.len(), inputs.len() - cached);
+	if unlisted > 0 {
+		info!("{} paths in the output file are no longer in the input list; their entries are kept", unlisted);
 	}
 
-	let reader = reader.into_inner();
+	#[cfg(feature = "audit")]
+	audit::record("resume", serde_json::json!({ "inputs": inputs.len(), "cached": cached, "new": inputs.len() - cached, "unlisted": unlisted }));
+}
+
+
+/// Exit status of hashing runs in which some inputs failed.
+const EXIT_SOME_FAILED: i32 = 2;
+
+
+/// Number of inputs `--readers` read ahead for each hashing thread.
+const PREFETCH_PER_THREAD: usize = 2;
+
 
-	reader.seek(SeekFrom::Start(valid_len)).unwrap();
+/// How often the thread writing the output file checks whether buffered hashes are due to be written.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-	cache
+
+/// Exit after failing to read or write the output file.
+fn output_error(path: &Path, err: anyhow::Error) -> ! {
+	error!("Error accessing output file {}: {:#}", path.display(), err);
+	exit(1);
 }
 
 
-/// Compute the phash for an image.
-fn image_path_to_phash(path: &Path, dct_matrix: &Matrix32x32, dct_matrix_t: &Matrix32x32) -> anyhow::Result<u64> {
-	let data = std::fs::read(path).context("Error reading image")?;
+/// Exit a hashing run, releasing any snapshot first, as exiting skips destructors.
+fn exit(code: i32) -> ! {
+	snapshot::release_all();
+	std::process::exit(code);
+}
 
-	let img = ImageReader::new(Cursor::new(data))
-		.with_guessed_format()
-		.context("Error guessing image format")?
-		.decode()
-		.context("Error decoding image")?;
 
-	// Convert to a 32x32 grayscale image
-	let img = imageops::grayscale(&img);
-	let img = imageops::resize(&img, 32, 32, imageops::FilterType::Lanczos3);
+/// Hash a single image without touching any output file.
+fn run_hash_one(image: &Path, args: Args) {
+	let hasher = Hasher::new(args.settings.settings(), Source::new(&args.source));
 
-	// Convert to a 32x32 matrix
-	let img = img.into_vec();
-	let img = Matrix32x32::from_row_iterator(img.iter().map(|v| *v as f32));
+	let entries = if image.as_os_str() == "-" {
+		let mut data = Vec::new();
+		std::io::stdin()
+			.lock()
+			.read_to_end(&mut data)
+			.context("Error reading image from stdin")
+			.and_then(|_| hasher.hash_bytes(&data))
+	} else {
+		hasher.hash(image)
+	};
+
+	#[cfg(feature = "audit")]
+	audit::record(
+		"hash",
+		match &entries {
+			Ok(entries) => serde_json::json!({ "image": image, "phashes": entries.iter().map(|entry| entry.phash).collect::<Vec<_>>() }),
+			Err(err) => serde_json::json!({ "image": image, "error": format!("{:#}", err) }),
+		},
+	);
+
+	match entries {
+		Ok(entries) => {
+			for entry in entries {
+				let mut line = format_phash(entry.phash);
+				for extra in &entry.extra {
+					line.push('\t');
+					line.push_str(extra);
+				}
+				println!("{}", line);
+			}
+		},
+		Err(err) if args.source.forensic => {
+			error!("Error computing phash for {}: {:#}", image.display(), err);
+			std::process::exit(1);
+		},
+		Err(err) => {
+			error!("Error computing phash for {}: {}", image.display(), err);
+			std::process::exit(1);
+		},
+	}
+}
 
-	// Compute DCT
-	let dct_vals = dct_matrix * img * dct_matrix_t;
 
-	// We only want the upper-left 8x8 block, ignoring the first row and column
-	let dct_vals = dct_vals.fixed_view::<8, 8>(1, 1);
+/// The images to hash, with the extra columns to record for each.
+fn read_inputs(args: &Args) -> Box<dyn Iterator<Item = (PathBuf, Vec<String>)>> {
+	#[cfg(feature = "photos")]
+	if let Some(library) = &args.photos_library {
+		let assets = photos::read_library(library).unwrap_or_else(|err| {
+			error!("Error reading Photos library {}: {:#}", library.display(), err);
+			std::process::exit(1);
+		});
 
-	// Convert to a 1D array
-	let dct_vals = dct_vals.iter().collect::<Vec<_>>();
+		return Box::new(assets.into_iter());
+	}
 
-	// Calculate median
-	let mut sorted = dct_vals.clone();
-	sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-	let median = (sorted[31] + sorted[32]) / 2.0;
+	#[cfg(feature = "takeout")]
+	if let Some(export) = &args.takeout {
+		let media = takeout::read_export(export).unwrap_or_else(|err| {
+			error!("Error reading Takeout export {}: {:#}", export.display(), err);
+			std::process::exit(1);
+		});
 
-	// Convert to a bit array
-	let dct_vals = dct_vals.into_iter().map(|v| if *v >= median { 1 } else { 0 }).collect::<Vec<_>>();
+		return Box::new(media.into_iter());
+	}
 
-	// Convert to a u64
-	let mut hash = 0;
-	for (i, v) in dct_vals.iter().enumerate() {
-		if *v == 1 {
-			hash |= 1 << i;
-		}
+	if let Some(image) = &args.carve {
+		let carved = carve::scan(image).unwrap_or_else(|err| {
+			error!("{:#}", err);
+			std::process::exit(1);
+		});
+
+		return Box::new(carved.into_iter());
 	}
 
-	Ok(hash)
+	Box::new(read_input_list(&args.input).map(|path| (path, Vec::new())))
 }
 
 
-// Based on pHash
-fn get_dct_matrix(size: usize) -> Matrix32x32 {
-	let c1 = (2.0 / (size as f32)).sqrt();
+fn read_input_list(path_or_stdin: &str) -> impl Iterator<Item = PathBuf> {
+	let reader = if path_or_stdin == "-" {
+		Box::new(std::io::stdin().lock()) as Box<dyn BufRead>
+	} else {
+		Box::new(BufReader::new(File::open(path_or_stdin).unwrap()))
+	};
 
-	Matrix32x32::from_fn(|y, x| {
-		if y == 0 {
-			return 1.0 / (size as f32).sqrt();
-		}
-		c1 * ((std::f32::consts::PI / 2.0 / (size as f32)) * (y as f32) * ((2 * x + 1) as f32)).cos()
-	})
+	reader.split(b'\n').map_while(Result::ok).filter_map(|line| input_path(&line))
+}
+
+
+/// The path on a line of an input list.  Surrounding whitespace, such as the `\r` of CRLF line endings, and a byte
+/// order mark are ignored.  Paths that aren't UTF-8, as lists written in a legacy code page may hold, can't be written
+/// to the output file and are skipped with a warning, rather than ending the list.
+fn input_path(line: &[u8]) -> Option<PathBuf> {
+	let line = line.strip_prefix("\u{feff}".as_bytes()).unwrap_or(line);
+	match std::str::from_utf8(line) {
+		Ok(line) => Some(PathBuf::from(line.trim())),
+		Err(_) => {
+			warn!("Warning: path isn't valid UTF-8, it will be skipped: {}", String::from_utf8_lossy(line).trim());
+			None
+		},
+	}
 }
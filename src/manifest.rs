@@ -0,0 +1,126 @@
+//! Records the inputs and settings of a hashing run in a manifest (`--save-manifest`), so that the identical run can be
+//! repeated later (`--replay`), e.g. to reproduce the results of a research pipeline.
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufReader, BufWriter, Write},
+	path::PathBuf,
+};
+use tracing::warn;
+
+use crate::{cache::Metadata, settings::Settings};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ManifestArgs {
+	/// Write the inputs of this run, after sampling, along with its settings and the version of this tool, to a JSON
+	/// manifest.
+	#[arg(long, value_name = "FILE")]
+	save_manifest: Option<PathBuf>,
+
+	/// Hash the inputs of a run saved with `--save-manifest`, with its settings, instead of those given on the command
+	/// line.  Warns if this build's version or features differ from the run's.
+	#[arg(long, value_name = "FILE", conflicts_with_all = ["input", "sample"])]
+	replay: Option<PathBuf>,
+}
+
+
+/// A saved run.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+	/// Version of the tool the run was made with.
+	version: String,
+
+	/// Its cargo features.
+	features: Vec<String>,
+
+	/// Hashing parameters, as recorded in output files.
+	settings: Metadata,
+
+	/// Inputs in path order, with the extra columns recorded for each.
+	inputs: Vec<Input>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Input {
+	path: PathBuf,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	extra: Vec<String>,
+}
+
+
+impl Manifest {
+	/// The settings and inputs of the run.
+	pub fn into_run(self) -> anyhow::Result<(Settings, HashMap<PathBuf, Vec<String>>)> {
+		let settings = Settings::from_metadata(&self.settings)?;
+		Ok((settings, self.inputs.into_iter().map(|input| (input.path, input.extra)).collect()))
+	}
+}
+
+
+/// Read the manifest to replay, if any, warning if it was saved by a different build.
+pub fn replay(args: &ManifestArgs) -> anyhow::Result<Option<Manifest>> {
+	let Some(path) = &args.replay else {
+		return Ok(None);
+	};
+
+	let file = File::open(path).context("Error opening manifest")?;
+	let manifest: Manifest = serde_json::from_reader(BufReader::new(file)).context("Error reading manifest")?;
+
+	if manifest.version != env!("CARGO_PKG_VERSION") {
+		warn!("Warning: {} was saved by version {} of hasher, not {}; hashes may differ", path.display(), manifest.version, env!("CARGO_PKG_VERSION"));
+	}
+	let features = crate::version::enabled_features();
+	if manifest.features != features {
+		warn!(
+			"Warning: {} was saved by a build with features {}, not {}; some inputs may fail or hash differently",
+			path.display(),
+			manifest.features.join(","),
+			features.join(",")
+		);
+	}
+
+	Ok(Some(manifest))
+}
+
+
+/// Save the run's settings and inputs, if asked to.  Paths that aren't UTF-8 can't be written to JSON and are skipped
+/// with a warning.
+pub fn save(args: &ManifestArgs, settings: &Settings, inputs: &HashMap<PathBuf, Vec<String>>) -> anyhow::Result<()> {
+	let Some(path) = &args.save_manifest else {
+		return Ok(());
+	};
+
+	let mut inputs = inputs
+		.iter()
+		.filter(|(path, _)| {
+			let utf8 = path.to_str().is_some();
+			if !utf8 {
+				warn!("Warning: skipping non-UTF-8 path in manifest: {}", path.display());
+			}
+			utf8
+		})
+		.map(|(path, extra)| Input {
+			path: path.clone(),
+			extra: extra.clone(),
+		})
+		.collect::<Vec<_>>();
+	inputs.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+	let manifest = Manifest {
+		version: env!("CARGO_PKG_VERSION").to_string(),
+		features: crate::version::enabled_features().into_iter().map(str::to_string).collect(),
+		settings: settings.metadata(),
+		inputs,
+	};
+
+	let mut writer = BufWriter::new(File::create(path).context("Error creating manifest")?);
+	serde_json::to_writer_pretty(&mut writer, &manifest).context("Error writing manifest")?;
+	writer.flush().context("Error writing manifest")?;
+
+	Ok(())
+}
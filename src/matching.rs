@@ -0,0 +1,146 @@
+//! Checks new images against a reference output file, e.g. incoming uploads against a known collection, without adding
+//! them to it.
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::{collections::BTreeMap, path::PathBuf};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::read_result_file,
+	dedupe::{tile, FULL},
+	hasher::{error_category, Hasher},
+	index::BkTree,
+	progress,
+	read_input_list,
+	settings::Settings,
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct MatchArgs {
+	/// Reference output file to match against.  Images are hashed with the parameters it was written with.
+	#[arg(long)]
+	db: PathBuf,
+
+	/// File listing the images to check, one path per line.  If "-", the list is read from stdin.
+	#[arg(short, long)]
+	input: String,
+
+	/// Maximum Hamming distance at which an image matches one in the reference.
+	#[arg(short = 't', long, default_value_t = 4)]
+	max_distance: u32,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+/// Print each match as `path\treference\tdistance`, by path and then distance, followed by a `tile=` column holding the
+/// matching region of each (e.g. `tile=full:r0c1`) if the reference was hashed with `--tiles`.  As with `dedupe`, a
+/// tile only matches a whole image.  Images that match nothing aren't listed.
+pub fn run(args: MatchArgs) {
+	let reference = read_result_file(&args.db).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.db.display(), err);
+		std::process::exit(1);
+	});
+	let settings = Settings::from_metadata(&reference.metadata).unwrap_or_else(|err| {
+		error!("Error: can't match against {}: {}", args.db.display(), err);
+		std::process::exit(1);
+	});
+	let hasher = Hasher::new(settings, Source::new(&args.source));
+
+	let mut paths = reference.hashes.keys().collect::<Vec<_>>();
+	paths.sort_unstable();
+
+	let mut tree = BkTree::new();
+	for (index, path) in paths.iter().enumerate() {
+		for entry in &reference.hashes[*path] {
+			if let Some(phash) = entry.phash {
+				tree.insert(phash, (index, tile(entry)));
+			}
+		}
+	}
+
+	let mut inputs = read_input_list(&args.input).collect::<Vec<_>>();
+	inputs.sort_unstable();
+	inputs.dedup();
+
+	// Each input's best match with every reference path it matches: distance, number of tiles involved, and their regions
+	let matches = inputs
+		.par_iter()
+		.progress_with(progress::bar(inputs.len() as u64))
+		.map(|input| {
+			let entries = match hasher.hash(input) {
+				Ok(entries) => entries,
+				Err(err) => {
+					error!(category = %error_category(&err), "Error computing phash for {}: {}", input.display(), err);
+					return None;
+				},
+			};
+
+			let mut found = BTreeMap::<_, (u32, usize, _, _)>::new();
+			for entry in &entries {
+				let Some(phash) = entry.phash else {
+					continue;
+				};
+				let tile_a = tile(entry);
+
+				for (&(index, tile_b), distance) in tree.find(phash, args.max_distance) {
+					let tiled = [tile_a, tile_b].iter().filter(|tile| tile.is_some_and(|tile| tile != FULL)).count();
+					if tiled > 1 {
+						continue;
+					}
+
+					let candidate = (distance, tiled, tile_a.map(str::to_string), tile_b);
+					match found.get_mut(&index) {
+						Some(best) if *best <= candidate => (),
+						Some(best) => *best = candidate,
+						None => {
+							found.insert(index, candidate);
+						},
+					}
+				}
+			}
+
+			Some(found)
+		})
+		.collect::<Vec<_>>();
+
+	let failed = matches.iter().filter(|matches| matches.is_none()).count();
+	let mut matched = 0;
+	for (input, matches) in inputs.iter().zip(&matches) {
+		let Some(matches) = matches.as_ref().filter(|matches| !matches.is_empty()) else {
+			continue;
+		};
+
+		let mut matches = matches.iter().collect::<Vec<_>>();
+		matches.sort_by_key(|(index, (distance, ..))| (*distance, **index));
+		for (index, (distance, _, tile_a, tile_b)) in matches {
+			let mut line = format!("{}\t{}\t{}", input.display(), paths[*index].display(), distance);
+			if let Some((a, b)) = tile_a.as_ref().zip(*tile_b) {
+				line.push_str(&format!("\ttile={}:{}", a, b));
+			}
+			println!("{}", line);
+		}
+		matched += 1;
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"match",
+		serde_json::json!({
+			"db": args.db,
+			"input": args.input,
+			"max_distance": args.max_distance,
+			"inputs": inputs.len(),
+			"matched": matched,
+			"failed": failed,
+		}),
+	);
+
+	if failed > 0 {
+		warn!("Warning: {} of {} inputs couldn't be hashed", failed, inputs.len());
+	}
+	info!("{} of {} inputs match images in {}", matched, inputs.len(), args.db.display());
+}
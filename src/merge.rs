@@ -0,0 +1,139 @@
+use std::{
+	collections::{hash_map, BTreeMap, HashMap},
+	fs::File,
+	io::{BufWriter, Write},
+	path::PathBuf,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::{format_phash, read_result_file, write_entry, write_environment, write_header, Entry},
+	shards::{Manifest, ShardArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+	/// Output files to merge.
+	#[arg(required = true)]
+	inputs: Vec<PathBuf>,
+
+	/// Merged output file.  Overwritten if it exists.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Also sharding the merged output file, or resharding sharded ones.
+	#[command(flatten)]
+	shards: ShardArgs,
+}
+
+
+/// Merge several output files, deduplicating by path.
+/// Paths with conflicting hashes are reported and left out of the merged file, so the next run recomputes them.
+pub fn run(args: MergeArgs) {
+	let mut metadata = None;
+	let mut environment = None;
+	let mut merged: HashMap<PathBuf, (Vec<Entry>, usize)> = HashMap::new();
+	let mut conflicts: BTreeMap<PathBuf, Vec<(usize, String)>> = BTreeMap::new();
+
+	for (index, input) in args.inputs.iter().enumerate() {
+		let results = match read_result_file(input) {
+			Ok(results) => results,
+			Err(err) => {
+				error!("Error reading {}: {}", input.display(), err);
+				std::process::exit(1);
+			},
+		};
+
+		// All inputs must have been computed with the same parameters
+		match &metadata {
+			None => metadata = Some(results.metadata),
+			Some(metadata) if *metadata != results.metadata => {
+				error!(
+					"Error: {} was written with different parameters ({}) than {} ({})",
+					input.display(),
+					results.metadata,
+					args.inputs[0].display(),
+					metadata
+				);
+				std::process::exit(1);
+			},
+			Some(_) => (),
+		}
+
+		// The merged file keeps the build and machine that wrote the inputs, if they were all the same
+		let same = results.resumed.iter().all(|resumed| *resumed == results.environment);
+		match &environment {
+			None if same => environment = Some(results.environment),
+			Some(first) if same && *first == results.environment => (),
+			_ => environment = Some(BTreeMap::new()),
+		}
+
+		for (path, entries) in results.hashes {
+			match merged.entry(path) {
+				hash_map::Entry::Vacant(vacant) => {
+					vacant.insert((entries, index));
+				},
+				hash_map::Entry::Occupied(occupied) => {
+					let (existing, existing_index) = occupied.get();
+
+					if !existing.iter().map(|entry| entry.phash).eq(entries.iter().map(|entry| entry.phash)) {
+						conflicts
+							.entry(occupied.key().clone())
+							.or_insert_with(|| vec![(*existing_index, format_phashes(existing))])
+							.push((index, format_phashes(&entries)));
+					}
+				},
+			}
+		}
+	}
+
+	for (path, hashes) in &conflicts {
+		let hashes = hashes.iter().map(|(index, phash)| format!("{}={}", args.inputs[*index].display(), phash)).collect::<Vec<_>>();
+		warn!("Conflict: {}: {}", path.display(), hashes.join(", "));
+	}
+
+	let mut entries = merged.into_iter().filter(|(path, _)| !conflicts.contains_key(path)).collect::<Vec<_>>();
+	entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+	let manifest = args.shards.shards.map(|count| Manifest::new(&args.output, count, args.shards.shard_by));
+	let paths = manifest.as_ref().map_or_else(|| vec![args.output.clone()], |manifest| manifest.paths.clone());
+	let metadata = metadata.unwrap_or_default();
+
+	let mut writers = paths
+		.iter()
+		.map(|path| {
+			let mut writer = BufWriter::new(File::create(path).unwrap());
+			write_header(&mut writer, &metadata).unwrap();
+			write_environment(&mut writer, environment.iter().flatten()).unwrap();
+			writer
+		})
+		.collect::<Vec<_>>();
+
+	for (path, (path_entries, _)) in &entries {
+		let writer = &mut writers[manifest.as_ref().map_or(0, |manifest| manifest.shard_of(path))];
+		for entry in path_entries {
+			write_entry(writer, path, entry).unwrap();
+		}
+	}
+
+	for writer in &mut writers {
+		writer.flush().unwrap();
+	}
+	if let Some(manifest) = &manifest {
+		std::fs::write(&args.output, manifest.contents()).unwrap();
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"merge",
+		serde_json::json!({ "inputs": args.inputs, "output": args.output, "entries": entries.len(), "conflicts": conflicts.len() }),
+	);
+
+	info!("Merged {} entries from {} files ({} conflicting paths left out)", entries.len(), args.inputs.len(), conflicts.len());
+}
+
+
+fn format_phashes(entries: &[Entry]) -> String {
+	entries.iter().map(|entry| format_phash(entry.phash)).collect::<Vec<_>>().join("/")
+}
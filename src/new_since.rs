@@ -0,0 +1,83 @@
+//! Lists the images in an output file that aren't in an older one, for backing up only new imagery.
+use std::path::PathBuf;
+use tracing::{error, info};
+
+use crate::{cache::read_result_file, index::BkTree};
+
+
+#[derive(clap::Args, Debug)]
+pub struct NewSinceArgs {
+	/// Output file listing the current images.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Older output file to compare against, written with the same settings.
+	#[arg(short, long)]
+	baseline: PathBuf,
+
+	/// Maximum Hamming distance at which an image counts as already present in the baseline.
+	#[arg(short, long, default_value_t = 4)]
+	threshold: u32,
+}
+
+
+/// Print every path of the output file none of whose hashes are within the threshold of a hash in the baseline, wherever
+/// the matching image is.  Moved, renamed and re-encoded copies of baseline images are left out; edited ones may not be.
+/// Trivial entries never match, so trivial images are always listed.
+pub fn run(args: NewSinceArgs) {
+	let read = |path: &PathBuf| {
+		read_result_file(path).unwrap_or_else(|err| {
+			error!("Error reading {}: {}", path.display(), err);
+			std::process::exit(1);
+		})
+	};
+	let current = read(&args.output);
+	let baseline = read(&args.baseline);
+
+	if current.metadata != baseline.metadata {
+		error!(
+			"Error: {} was written with different parameters ({}) than {} ({})",
+			args.baseline.display(),
+			baseline.metadata,
+			args.output.display(),
+			current.metadata
+		);
+		std::process::exit(1);
+	}
+
+	let mut tree = BkTree::new();
+	for phash in baseline.hashes.values().flatten().filter_map(|entry| entry.phash) {
+		tree.insert(phash, ());
+	}
+
+	let mut paths = current.hashes.iter().collect::<Vec<_>>();
+	paths.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+	let mut novel = 0;
+	let mut moved = 0;
+	for (path, entries) in paths {
+		if entries.iter().filter_map(|entry| entry.phash).any(|phash| !tree.find(phash, args.threshold).is_empty()) {
+			if !baseline.hashes.contains_key(path) {
+				moved += 1;
+			}
+			continue;
+		}
+
+		println!("{}", path.display());
+		novel += 1;
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"new-since",
+		serde_json::json!({ "output": args.output, "baseline": args.baseline, "threshold": args.threshold, "novel": novel }),
+	);
+
+	info!(
+		"{} of {} paths are new since {}; {} new paths are copies of images in it",
+		novel,
+		current.hashes.len(),
+		args.baseline.display(),
+		moved
+	);
+}
@@ -0,0 +1,102 @@
+//! Notifications fired with a run summary, for unattended servers.
+use serde::Serialize;
+use std::{
+	io::Write,
+	process::{Command, Stdio},
+};
+use tracing::error;
+
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct NotifyArgs {
+	/// POST the run summary as JSON to this URL when the run finishes.
+	#[cfg(feature = "http")]
+	#[arg(long)]
+	webhook: Option<String>,
+
+	/// Run this shell command when the run finishes, with the run summary as JSON on its stdin.
+	#[arg(long)]
+	notify_cmd: Option<String>,
+
+	/// Also notify, once, as soon as the fraction of failed images exceeds this rate (0-1).
+	#[arg(long)]
+	notify_failure_rate: Option<f64>,
+}
+
+
+/// Summary of a hashing run.
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+	/// Why the notification was sent: "finished", "stopped" after `--max-errors` or "failure-rate".
+	pub event: &'static str,
+	pub output: String,
+	/// Images that needed hashing, excluding those already in the output file.
+	pub total: u64,
+	pub hashed: u64,
+	pub failed: u64,
+	pub elapsed_secs: f64,
+	/// With `--shadow-sample`, cached images that were re-hashed, and how many of them no longer match.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub shadow_checked: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub shadow_mismatched: Option<u64>,
+}
+
+
+/// Minimum number of processed images before the failure rate is considered meaningful.
+const MIN_FAILURE_RATE_SAMPLES: u64 = 100;
+
+
+pub struct Notifier {
+	args: NotifyArgs,
+}
+
+impl Notifier {
+	pub fn new(args: &NotifyArgs) -> Self {
+		Notifier { args: args.clone() }
+	}
+
+	/// Whether a failure rate notification should be sent for these counts.
+	pub fn failure_rate_exceeded(&self, processed: u64, failed: u64) -> bool {
+		match self.args.notify_failure_rate {
+			Some(rate) => processed >= MIN_FAILURE_RATE_SAMPLES && failed as f64 > rate * processed as f64,
+			None => false,
+		}
+	}
+
+	/// Send the summary to every configured destination.  Failures are reported but never abort the run.
+	pub fn notify(&self, summary: &Summary) {
+		let json = serde_json::to_string(summary).unwrap();
+
+		#[cfg(feature = "http")]
+		if let Some(url) = &self.args.webhook {
+			if let Err(err) = ureq::post(url).set("Content-Type", "application/json").send_string(&json) {
+				error!("Error sending webhook to {}: {}", url, err);
+			}
+		}
+
+		if let Some(cmd) = &self.args.notify_cmd {
+			if let Err(err) = run_command(cmd, &json) {
+				error!("Error running notify command: {}", err);
+			}
+		}
+	}
+}
+
+
+fn run_command(cmd: &str, input: &str) -> anyhow::Result<()> {
+	let mut child = if cfg!(windows) {
+		Command::new("cmd").args(["/C", cmd]).stdin(Stdio::piped()).spawn()?
+	} else {
+		Command::new("sh").args(["-c", cmd]).stdin(Stdio::piped()).spawn()?
+	};
+
+	child.stdin.take().unwrap().write_all(input.as_bytes())?;
+
+	let status = child.wait()?;
+	if !status.success() {
+		anyhow::bail!("command exited with {}", status);
+	}
+
+	Ok(())
+}
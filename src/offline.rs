@@ -0,0 +1,94 @@
+//! Detects inputs whose data is offline, such as files a hierarchical storage manager (HSM) has migrated to tape, so
+//! that runs over archives can avoid recalling them by accident.
+use std::{
+	collections::HashSet,
+	fs::File,
+	io::{BufRead, BufReader},
+	path::{Path, PathBuf},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct OfflineArgs {
+	/// Don't hash inputs whose data is offline.  They are left out of the output file, so a later run hashes them.
+	#[arg(long, conflicts_with = "recall_batch_size")]
+	skip_offline: bool,
+
+	/// Recall and hash at most this many offline inputs per run, in path order, leaving the rest for later runs.
+	#[arg(long, value_name = "N")]
+	recall_batch_size: Option<usize>,
+
+	/// File listing inputs to treat as offline, one path per line, in addition to those the filesystem reports.
+	#[arg(long, value_name = "FILE")]
+	offline_list: Option<PathBuf>,
+}
+
+
+/// Whether a file's data is offline.  Windows flags such files as offline or recalled on access; elsewhere HSMs leave
+/// a stub with no blocks allocated despite its size.
+fn is_offline(path: &Path) -> bool {
+	let Ok(metadata) = std::fs::metadata(path) else {
+		return false;
+	};
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+		metadata.len() > 0 && metadata.blocks() == 0
+	}
+
+	#[cfg(windows)]
+	{
+		use std::os::windows::fs::MetadataExt;
+		const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+		const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+		const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+		metadata.file_attributes() & (FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS) != 0
+	}
+
+	#[cfg(not(any(unix, windows)))]
+	{
+		let _ = metadata;
+		false
+	}
+}
+
+
+/// The inputs of a run, split by whether their data is online.
+pub struct Tiers<T> {
+	/// Inputs to hash first.
+	pub online: Vec<T>,
+	/// Offline inputs to recall and hash afterwards, in path order.
+	pub offline: Vec<T>,
+	/// Offline inputs left for later runs.
+	pub deferred: usize,
+}
+
+
+/// Split inputs by whether their data is online, given a function finding the local file each is read from.
+pub fn split<'a, T>(
+	args: &OfflineArgs,
+	inputs: impl IntoIterator<Item = (&'a PathBuf, T)>,
+	local: impl Fn(&Path) -> PathBuf,
+) -> anyhow::Result<Tiers<(&'a PathBuf, T)>> {
+	let listed = match &args.offline_list {
+		Some(list) => BufReader::new(File::open(list)?)
+			.lines()
+			.map(|line| line.map(|line| PathBuf::from(line.trim())))
+			.collect::<Result<HashSet<_>, _>>()?,
+		None => HashSet::new(),
+	};
+
+	let (online, mut offline): (Vec<_>, Vec<_>) = inputs.into_iter().partition(|(path, _)| !listed.contains(*path) && !is_offline(&local(path)));
+	offline.sort_unstable_by_key(|(path, _)| *path);
+
+	let recalled = match (args.skip_offline, args.recall_batch_size) {
+		(true, _) => 0,
+		(false, Some(size)) => size.min(offline.len()),
+		(false, None) => offline.len(),
+	};
+	let deferred = offline.len() - recalled;
+	offline.truncate(recalled);
+
+	Ok(Tiers { online, offline, deferred })
+}
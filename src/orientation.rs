@@ -0,0 +1,56 @@
+//! Reads the EXIF orientation tag so that images can be hashed the way they are displayed.
+use image::{imageops, DynamicImage, GrayImage};
+use std::io::Cursor;
+
+
+/// The EXIF orientation of an encoded image, 1 (upright) if absent or unreadable.
+pub fn read_orientation(data: &[u8]) -> u32 {
+	let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) else {
+		return 1;
+	};
+
+	exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+		.and_then(|field| field.value.get_uint(0))
+		.unwrap_or(1)
+}
+
+
+/// Transform an image from its stored orientation to its display orientation.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+	match orientation {
+		2 => img.fliph(),
+		3 => img.rotate180(),
+		4 => img.flipv(),
+		5 => img.rotate90().fliph(),
+		6 => img.rotate90(),
+		7 => img.rotate270().fliph(),
+		8 => img.rotate270(),
+		_ => img,
+	}
+}
+
+
+/// The image under each combination of rotation by multiples of 90 degrees (if `rotate`) and mirroring (if `flip`),
+/// named as recorded in `transform=` columns.  The first is always the image itself.
+pub fn dihedral(img: &GrayImage, rotate: bool, flip: bool) -> Vec<(&'static str, GrayImage)> {
+	let mut transforms = vec![("identity", img.clone())];
+	if rotate {
+		transforms.extend([("rot90", imageops::rotate90(img)), ("rot180", imageops::rotate180(img)), ("rot270", imageops::rotate270(img))]);
+	}
+
+	if flip {
+		let flipped = imageops::flip_horizontal(img);
+		if rotate {
+			transforms.extend([
+				("flip", flipped.clone()),
+				("flip-rot90", imageops::rotate90(&flipped)),
+				("flip-rot180", imageops::rotate180(&flipped)),
+				("flip-rot270", imageops::rotate270(&flipped)),
+			]);
+		} else {
+			transforms.push(("flip", flipped));
+		}
+	}
+
+	transforms
+}
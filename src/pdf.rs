@@ -0,0 +1,65 @@
+//! Renders pages of PDF documents by running `pdftoppm`, from poppler, which must be on `PATH`.
+#[cfg(feature = "pdf")]
+use {
+	crate::ppm::read_ppm,
+	anyhow::Context,
+	image::DynamicImage,
+	std::{
+		io::{BufReader, Read, Write},
+		process::{Command, Stdio},
+		thread,
+	},
+};
+
+
+/// Resolution pages are rendered at.  Fixed, so that a document always renders to the same image.
+#[cfg(feature = "pdf")]
+const PDF_DPI: u32 = 100;
+
+
+/// Whether the data is a PDF document.
+pub fn is_pdf(data: &[u8]) -> bool {
+	data.starts_with(b"%PDF-")
+}
+
+
+/// Render a page of a PDF document, counting from 1.
+#[cfg(feature = "pdf")]
+pub fn render_page(data: &[u8], page: u32) -> anyhow::Result<DynamicImage> {
+	let page = page.to_string();
+	let mut child = Command::new("pdftoppm")
+		// Without an output prefix, the page is written to stdout
+		.args(["-f", &page, "-l", &page, "-r", &PDF_DPI.to_string(), "-"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.context("Error running pdftoppm")?;
+
+	// Feed the document from another thread, as pdftoppm may start writing before it has read everything
+	let mut stdin = child.stdin.take().unwrap();
+	let data = data.to_vec();
+	let writer_thread = thread::spawn(move || stdin.write_all(&data));
+
+	let mut stderr = child.stderr.take().unwrap();
+	let log_thread = thread::spawn(move || {
+		let mut log = String::new();
+		let _ = stderr.read_to_string(&mut log);
+		log
+	});
+
+	let img = read_ppm(&mut BufReader::new(child.stdout.take().unwrap()));
+	let status = child.wait().context("Error running pdftoppm")?;
+	let log = log_thread.join().unwrap();
+	// pdftoppm stops reading early for documents it can't parse, so a broken pipe is reported through its status instead
+	let _ = writer_thread.join().unwrap();
+
+	if !status.success() {
+		anyhow::bail!("pdftoppm failed: {}", log.trim());
+	}
+
+	match img.context("Error reading page from pdftoppm")? {
+		Some(img) => Ok(DynamicImage::ImageRgb8(img)),
+		None => anyhow::bail!("PDF has no page {}", page),
+	}
+}
@@ -0,0 +1,236 @@
+use image::{self, imageops, DynamicImage, GrayImage, Luma};
+
+use crate::settings::{Filter, Grayscale};
+
+
+/// Convert an image to the 32x32 grayscale image that is hashed.
+pub fn downscale(img: &DynamicImage, grayscale: Grayscale, filter: Filter) -> GrayImage {
+	resize(&to_grayscale(img, grayscale), 32, filter)
+}
+
+
+/// Size jittered copies are resampled at before finishing the downscale, so that a shift of half a hashed pixel is two
+/// of its pixels.
+const JITTER_SIZE: u32 = 128;
+
+/// Jitters of `--ensemble`, as horizontal and vertical shifts in hashed pixels and a rotation in degrees, in the order
+/// they are used.  The first is the image itself.
+const JITTERS: &[(f32, f32, f32)] = &[
+	(0.0, 0.0, 0.0),
+	(0.5, 0.0, 0.0),
+	(-0.5, 0.0, 0.0),
+	(0.0, 0.5, 0.0),
+	(0.0, -0.5, 0.0),
+	(0.0, 0.0, 1.0),
+	(0.0, 0.0, -1.0),
+	(0.5, 0.5, 0.0),
+	(-0.5, -0.5, 0.0),
+];
+
+
+/// Convert an image to the 32x32 grayscale images of `count` jittered copies of it, the first unjittered.  Copies are
+/// resampled from the image scaled to an intermediate size, so the first isn't quite the image's `downscale`.
+pub fn downscale_jittered(img: &DynamicImage, grayscale: Grayscale, filter: Filter, count: u32) -> Vec<GrayImage> {
+	let img = resize(&to_grayscale(img, grayscale), JITTER_SIZE, filter);
+	let scale = (JITTER_SIZE / 32) as f32;
+
+	JITTERS[..count as usize].iter().map(|(dx, dy, degrees)| resize(&jitter(&img, dx * scale, dy * scale, *degrees), 32, filter)).collect()
+}
+
+
+/// Shift an image and rotate it about its centre, sampling bilinearly and extending its edges.
+fn jitter(img: &GrayImage, dx: f32, dy: f32, degrees: f32) -> GrayImage {
+	if (dx, dy, degrees) == (0.0, 0.0, 0.0) {
+		return img.clone();
+	}
+
+	let (sin, cos) = degrees.to_radians().sin_cos();
+	let (cx, cy) = ((img.width() - 1) as f32 / 2.0, (img.height() - 1) as f32 / 2.0);
+	let max = ((img.width() - 1) as f32, (img.height() - 1) as f32);
+
+	GrayImage::from_fn(img.width(), img.height(), |x, y| {
+		// Where the output pixel comes from in the input
+		let (x, y) = (x as f32 - cx - dx, y as f32 - cy - dy);
+		let sx = (cos * x + sin * y + cx).clamp(0.0, max.0);
+		let sy = (cos * y - sin * x + cy).clamp(0.0, max.1);
+
+		let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+		let (x1, y1) = ((x0 + 1).min(img.width() - 1), (y0 + 1).min(img.height() - 1));
+		let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+		let value = |x, y| img.get_pixel(x, y).0[0] as f32;
+
+		let top = value(x0, y0) * (1.0 - fx) + value(x1, y0) * fx;
+		let bottom = value(x0, y1) * (1.0 - fx) + value(x1, y1) * fx;
+		Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+	})
+}
+
+
+/// Standard deviation of pixel values below which a downscaled image is considered uniform.
+const TRIVIAL_STDDEV: f32 = 2.0;
+
+
+/// Whether a downscaled image is essentially uniform, such as a blank page, so its hash would be meaningless.
+pub fn is_trivial(img: &GrayImage) -> bool {
+	let count = img.as_raw().len() as f32;
+	let mean = img.as_raw().iter().map(|v| *v as f32).sum::<f32>() / count;
+	let variance = img.as_raw().iter().map(|v| (*v as f32 - mean).powi(2)).sum::<f32>() / count;
+
+	variance.sqrt() < TRIVIAL_STDDEV
+}
+
+
+/// The rows of the 32x32 DCT matrix that produce the hashed coefficients, 1 through 8, both as rows and as columns.
+pub struct Dct {
+	rows: [[f32; 32]; 8],
+	columns: [[f32; 8]; 32],
+}
+
+impl Dct {
+	pub fn new() -> Self {
+		let matrix = get_dct_matrix(32);
+
+		Dct {
+			rows: std::array::from_fn(|y| matrix[y + 1]),
+			columns: std::array::from_fn(|x| std::array::from_fn(|y| matrix[y + 1][x])),
+		}
+	}
+}
+
+
+/// Compute the phash of a downscaled image.
+pub fn phash(img: &GrayImage, dct: &Dct) -> u64 {
+	// Only the upper-left 8x8 block of the DCT, ignoring the first row and column, is hashed, so only the rows and
+	// columns of the separable transform that contribute to it are computed.  The inner loops run along contiguous rows
+	// so that they vectorize, and every coefficient is summed in the same order as by a full matrix product.
+	let pixels = img.as_raw();
+	let mut partial = [[0f32; 32]; 8];
+	for (partial, dct_row) in partial.iter_mut().zip(&dct.rows) {
+		for (weight, pixel_row) in dct_row.iter().zip(pixels.chunks_exact(32)) {
+			for (value, pixel) in partial.iter_mut().zip(pixel_row) {
+				*value += weight * *pixel as f32;
+			}
+		}
+	}
+
+	let mut block = [[0f32; 8]; 8];
+	for (block, partial) in block.iter_mut().zip(&partial) {
+		for (value, dct_column) in partial.iter().zip(&dct.columns) {
+			for (coefficient, weight) in block.iter_mut().zip(dct_column) {
+				*coefficient += value * weight;
+			}
+		}
+	}
+
+	// Convert to a 1D array, column by column
+	let dct_vals = (0..8).flat_map(|x| block.iter().map(move |row| &row[x])).collect::<Vec<_>>();
+
+	// Calculate median
+	let mut sorted = dct_vals.clone();
+	sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	let median = (sorted[31] + sorted[32]) / 2.0;
+
+	// Convert to a bit array
+	let dct_vals = dct_vals.into_iter().map(|v| if *v >= median { 1 } else { 0 }).collect::<Vec<_>>();
+
+	// Convert to a u64
+	let mut hash = 0;
+	for (i, v) in dct_vals.iter().enumerate() {
+		if *v == 1 {
+			hash |= 1 << i;
+		}
+	}
+
+	hash
+}
+
+
+/// Compute the average hash of a downscaled image: whether each cell of an 8x8 grid is brighter than the mean.
+pub fn ahash(img: &GrayImage) -> u64 {
+	// Each cell averages a 4x4 block of the 32x32 image
+	let mut cells = [0u32; 64];
+	for (x, y, pixel) in img.enumerate_pixels() {
+		cells[(y / 4 * 8 + x / 4) as usize] += pixel.0[0] as u32;
+	}
+	let mean = cells.iter().sum::<u32>() / 64;
+
+	cells.iter().enumerate().filter(|(_, cell)| **cell > mean).fold(0, |hash, (i, _)| hash | 1 << i)
+}
+
+
+/// Compute the difference hash of a downscaled image: whether each pixel of a 9x8 version is brighter than the one to
+/// its right.
+pub fn dhash(img: &GrayImage) -> u64 {
+	let img = imageops::resize(img, 9, 8, imageops::FilterType::Triangle);
+
+	(0..8u32)
+		.flat_map(|y| (0..8u32).map(move |x| (x, y)))
+		.enumerate()
+		.filter(|(_, (x, y))| img.get_pixel(*x, *y).0[0] > img.get_pixel(x + 1, *y).0[0])
+		.fold(0, |hash, (i, _)| hash | 1 << i)
+}
+
+
+/// Convert an image to 8-bit grayscale.
+fn to_grayscale(img: &DynamicImage, grayscale: Grayscale) -> GrayImage {
+	// image's own conversion, which hashes have always used
+	if grayscale == Grayscale::Rec709 {
+		return imageops::grayscale(img);
+	}
+
+	let img = img.to_rgb8();
+	GrayImage::from_fn(img.width(), img.height(), |x, y| {
+		let [r, g, b] = img.get_pixel(x, y).0.map(u32::from);
+		let luma = match grayscale {
+			Grayscale::Rec601 => (299 * r + 587 * g + 114 * b + 500) / 1000,
+			Grayscale::Rec709 => unreachable!(),
+			Grayscale::Average => (r + g + b + 1) / 3,
+			Grayscale::Red => r,
+			Grayscale::Green => g,
+			Grayscale::Blue => b,
+		};
+
+		Luma([luma as u8])
+	})
+}
+
+
+/// Scale a grayscale image to a square.
+fn resize(img: &GrayImage, size: u32, filter: Filter) -> GrayImage {
+	let filter = match filter {
+		Filter::Lanczos3 => imageops::FilterType::Lanczos3,
+		Filter::Triangle => imageops::FilterType::Triangle,
+		Filter::Nearest => imageops::FilterType::Nearest,
+		Filter::CatmullRom => imageops::FilterType::CatmullRom,
+		Filter::Gaussian => imageops::FilterType::Gaussian,
+		Filter::BoxLanczos3 => {
+			// Area-average large images down to a few times the final size first, then finish with Lanczos3
+			if (img.width() > BOX_SIZE || img.height() > BOX_SIZE) && size < BOX_SIZE {
+				let img = imageops::thumbnail(img, BOX_SIZE.min(img.width()), BOX_SIZE.min(img.height()));
+				return imageops::resize(&img, size, size, imageops::FilterType::Lanczos3);
+			}
+			imageops::FilterType::Lanczos3
+		},
+	};
+
+	imageops::resize(img, size, size, filter)
+}
+
+
+/// Intermediate size of `Filter::BoxLanczos3`.
+const BOX_SIZE: u32 = 128;
+
+
+// Based on pHash
+fn get_dct_matrix(size: usize) -> [[f32; 32]; 32] {
+	let c1 = (2.0 / (size as f32)).sqrt();
+
+	std::array::from_fn(|y| {
+		std::array::from_fn(|x| {
+			if y == 0 {
+				return 1.0 / (size as f32).sqrt();
+			}
+			c1 * ((std::f32::consts::PI / 2.0 / (size as f32)) * (y as f32) * ((2 * x + 1) as f32)).cos()
+		})
+	})
+}
@@ -0,0 +1,168 @@
+//! Pushes clusters of duplicates to the self-hosted photo managers that many libraries are browsed with, through their
+//! HTTP APIs, so that duplicates can be reviewed there.
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+
+/// How to reach a photo manager's API.
+pub struct Server<'a> {
+	/// Base URL, e.g. `http://localhost:2283`.
+	pub url: &'a str,
+	pub api_key: &'a str,
+}
+
+
+/// A cluster of duplicates, as paths the photo manager knows the files by.
+pub struct Group {
+	pub keep: String,
+	pub duplicates: Vec<String>,
+}
+
+
+#[derive(Deserialize)]
+struct ImmichSearch {
+	assets: ImmichAssets,
+}
+
+#[derive(Deserialize)]
+struct ImmichAssets {
+	items: Vec<ImmichAsset>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImmichAsset {
+	id: String,
+	original_path: String,
+}
+
+
+/// Mark the assets of each group as duplicates of each other in Immich, which lists them under Utilities > Review
+/// duplicates for choosing which to keep.  Assets are found by their original path, as Immich sees it.  Returns the
+/// number of groups pushed; files Immich doesn't know about are left out.
+pub fn push_immich(server: &Server, groups: &[Group]) -> anyhow::Result<usize> {
+	let agent = ureq::agent();
+	let mut pushed = 0;
+
+	for group in groups {
+		let mut ids = Vec::new();
+		for path in std::iter::once(&group.keep).chain(&group.duplicates) {
+			// Immich matches paths containing the one searched for, so the exact one is picked from the results
+			let search: ImmichSearch = agent
+				.post(&format!("{}/api/search/metadata", server.url))
+				.set("x-api-key", server.api_key)
+				.send_json(json!({ "originalPath": path }))?
+				.into_json()?;
+			match search.assets.items.into_iter().find(|asset| asset.original_path == *path) {
+				Some(asset) => ids.push(asset.id),
+				None => warn!("Warning: Immich has no asset at {}, it will be left out", path),
+			}
+		}
+
+		if ids.len() < 2 {
+			continue;
+		}
+
+		agent
+			.put(&format!("{}/api/assets", server.url))
+			.set("x-api-key", server.api_key)
+			.send_json(json!({ "ids": ids, "duplicateId": random_uuid() }))?;
+		pushed += 1;
+	}
+
+	Ok(pushed)
+}
+
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PhotoPrismPhoto {
+	#[serde(rename = "UID")]
+	uid: String,
+	file_name: String,
+}
+
+#[derive(Deserialize)]
+struct PhotoPrismAlbum {
+	#[serde(rename = "UID")]
+	uid: String,
+}
+
+
+/// Create an album in PhotoPrism for each group, named after the file kept, holding it and its duplicates.  PhotoPrism
+/// has no notion of duplicates that merely look alike, so albums are where they can be reviewed.  Photos are found by
+/// their file name relative to the originals folder.  Returns the number of groups pushed; files PhotoPrism doesn't
+/// know about are left out.
+pub fn push_photoprism(server: &Server, groups: &[Group]) -> anyhow::Result<usize> {
+	let agent = ureq::agent();
+	let authorization = format!("Bearer {}", server.api_key);
+	let mut pushed = 0;
+
+	for group in groups {
+		let mut uids = Vec::new();
+		for path in std::iter::once(&group.keep).chain(&group.duplicates) {
+			let photos: Vec<PhotoPrismPhoto> = agent
+				.get(&format!("{}/api/v1/photos", server.url))
+				.set("Authorization", &authorization)
+				.query("count", "10")
+				.query("merged", "true")
+				.query("filename", path)
+				.call()?
+				.into_json()?;
+			match photos.into_iter().find(|photo| photo.file_name == *path) {
+				Some(photo) if !uids.contains(&photo.uid) => uids.push(photo.uid),
+				// Files PhotoPrism stacked into a single photo are already together
+				Some(_) => (),
+				None => warn!("Warning: PhotoPrism has no photo at {}, it will be left out", path),
+			}
+		}
+
+		if uids.len() < 2 {
+			continue;
+		}
+
+		let name = Path::new(&group.keep).file_name().map_or_else(|| group.keep.clone(), |name| name.to_string_lossy().into_owned());
+		let album: PhotoPrismAlbum = agent
+			.post(&format!("{}/api/v1/albums", server.url))
+			.set("Authorization", &authorization)
+			.send_json(json!({ "Title": format!("Duplicates of {}", name) }))?
+			.into_json()?;
+		agent
+			.post(&format!("{}/api/v1/albums/{}/photos", server.url, album.uid))
+			.set("Authorization", &authorization)
+			.send_json(json!({ "photos": uids }))?;
+		pushed += 1;
+	}
+
+	Ok(pushed)
+}
+
+
+/// The path a photo manager knows a file by: relative to `root`, and under `library_root` if given.
+pub fn remote_path(path: &Path, root: Option<&Path>, library_root: Option<&Path>) -> Option<String> {
+	let path = match root {
+		Some(root) => path.strip_prefix(root).ok()?,
+		None => path,
+	};
+	let path = match library_root {
+		Some(library_root) => library_root.join(path),
+		None => PathBuf::from(path),
+	};
+
+	path.to_str().map(str::to_string)
+}
+
+
+/// A random (version 4) UUID, which Immich identifies groups of duplicates by.
+fn random_uuid() -> String {
+	let mut bytes = [0u8; 16];
+	rand::thread_rng().fill_bytes(&mut bytes);
+	bytes[6] = (bytes[6] & 0x0f) | 0x40;
+	bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+	let hex = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+	format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
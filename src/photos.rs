@@ -0,0 +1,41 @@
+//! Enumerates the originals in a macOS Photos library.
+use anyhow::Context;
+use rusqlite::{Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+
+
+/// Return the path of every original in the library, along with an extra `uuid=` column holding its asset UUID.
+/// Assets in the trash are skipped.  Originals that only live in iCloud will fail to read like any other missing file.
+pub fn read_library(library: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+	let database = library.join("database").join("Photos.sqlite");
+	let conn = Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+		.with_context(|| format!("Error opening {}", database.display()))?;
+
+	// Photos 5 (macOS 10.15) and later keep assets in ZASSET, earlier versions in ZGENERICASSET
+	let table = ["ZASSET", "ZGENERICASSET"]
+		.into_iter()
+		.find(|table| {
+			conn.query_row("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1", [table], |_| Ok(()))
+				.is_ok()
+		})
+		.context("Photos database contains no asset table")?;
+
+	let mut stmt = conn.prepare(&format!(
+		"SELECT ZUUID, ZDIRECTORY, ZFILENAME FROM {} WHERE ZTRASHEDSTATE = 0 AND ZDIRECTORY IS NOT NULL AND ZFILENAME IS NOT NULL",
+		table
+	))?;
+
+	let originals = library.join("originals");
+	let assets = stmt
+		.query_map([], |row| {
+			let uuid: String = row.get(0)?;
+			let directory: String = row.get(1)?;
+			let filename: String = row.get(2)?;
+
+			Ok((originals.join(directory).join(filename), vec![format!("uuid={}", uuid)]))
+		})?
+		.collect::<Result<Vec<_>, _>>()
+		.context("Error reading Photos database")?;
+
+	Ok(assets)
+}
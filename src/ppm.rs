@@ -0,0 +1,43 @@
+//! Reads the PPM streams that external decoders write.
+use image::RgbImage;
+use std::io::BufRead;
+
+
+/// Read a single binary PPM (P6, 8-bit) image, as written by ffmpeg and pdftoppm.
+/// Returns None at the end of the stream.
+pub fn read_ppm<R: BufRead>(reader: &mut R) -> anyhow::Result<Option<RgbImage>> {
+	if reader.fill_buf()?.is_empty() {
+		return Ok(None);
+	}
+
+	let mut fields = Vec::new();
+	while fields.len() < 4 {
+		let mut field = Vec::new();
+
+		loop {
+			let mut byte = [0u8];
+			reader.read_exact(&mut byte)?;
+
+			if byte[0].is_ascii_whitespace() {
+				if !field.is_empty() {
+					break;
+				}
+			} else {
+				field.push(byte[0]);
+			}
+		}
+
+		fields.push(String::from_utf8(field)?);
+	}
+
+	if fields[0] != "P6" || fields[3] != "255" {
+		anyhow::bail!("Unexpected PPM format: {} {}", fields[0], fields[3]);
+	}
+
+	let width = fields[1].parse::<u32>()?;
+	let height = fields[2].parse::<u32>()?;
+	let mut data = vec![0u8; width as usize * height as usize * 3];
+	reader.read_exact(&mut data)?;
+
+	Ok(RgbImage::from_raw(width, height, data))
+}
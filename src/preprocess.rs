@@ -0,0 +1,257 @@
+//! Adjustments made to images before they are hashed.
+use image::{imageops, DynamicImage, GrayImage, Rgb, RgbImage};
+use std::{borrow::Cow, fmt, str::FromStr};
+
+
+/// What transparent images are composited onto before hashing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Background {
+	/// Ignore the alpha channel, hashing whatever color transparent pixels happen to store.
+	#[default]
+	None,
+	/// A solid color.
+	Color([u8; 3]),
+	/// A checkerboard of light and mid gray squares, `CHECKER_SQUARES` across the longer side of the image.
+	Checker,
+}
+
+impl fmt::Display for Background {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Background::None => write!(f, "none"),
+			Background::Color([r, g, b]) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+			Background::Checker => write!(f, "checker"),
+		}
+	}
+}
+
+impl FromStr for Background {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"none" => return Ok(Background::None),
+			"checker" => return Ok(Background::Checker),
+			"white" => return Ok(Background::Color([255, 255, 255])),
+			"black" => return Ok(Background::Color([0, 0, 0])),
+			_ => (),
+		}
+
+		let hex = s.strip_prefix('#').unwrap_or(s);
+		if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			anyhow::bail!("Invalid background: {} (expected none, checker, white, black or #rrggbb)", s);
+		}
+
+		let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+		Ok(Background::Color([channel(0), channel(2), channel(4)]))
+	}
+}
+
+
+const CHECKER_SQUARES: u32 = 16;
+const CHECKER_COLORS: [[u8; 3]; 2] = [[204, 204, 204], [153, 153, 153]];
+
+
+/// Composite an image with an alpha channel onto the background.  Opaque images are returned unchanged.
+pub fn composite(img: &DynamicImage, background: Background) -> Cow<'_, DynamicImage> {
+	if background == Background::None || !img.color().has_alpha() {
+		return Cow::Borrowed(img);
+	}
+
+	let rgba = img.to_rgba8();
+	let square = (rgba.width().max(rgba.height()) / CHECKER_SQUARES).max(1);
+
+	let composited = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+		let [r, g, b, a] = rgba.get_pixel(x, y).0;
+		let bg = match background {
+			Background::Color(color) => color,
+			_ => CHECKER_COLORS[((x / square + y / square) % 2) as usize],
+		};
+		let blend = |fg: u8, bg: u8| ((fg as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8;
+
+		Rgb([blend(r, bg[0]), blend(g, bg[1]), blend(b, bg[2])])
+	});
+
+	Cow::Owned(DynamicImage::ImageRgb8(composited))
+}
+
+
+/// An adjustment made to images before they are hashed: to the full image before it is downscaled (`trim`, `crop`),
+/// or to the downscaled grayscale image before its DCT is computed (the rest).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+	/// Cut off uniform borders, such as letterboxing or a scanner's margins.
+	Trim,
+	/// Keep this fraction of the width and height, around the center.
+	Crop(f32),
+	/// Stretch pixel values linearly to cover the full range.
+	Normalize,
+	/// Histogram equalization.
+	Equalize,
+	/// Gaussian blur with this standard deviation, in pixels of the downscaled image.
+	Blur(f32),
+}
+
+impl fmt::Display for Step {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Step::Trim => write!(f, "trim"),
+			Step::Crop(fraction) => write!(f, "crop:{}", fraction),
+			Step::Normalize => write!(f, "normalize"),
+			Step::Equalize => write!(f, "equalize"),
+			Step::Blur(sigma) => write!(f, "blur:{}", sigma),
+		}
+	}
+}
+
+impl FromStr for Step {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			None if s == "trim" => Ok(Step::Trim),
+			Some(("crop", fraction)) => match fraction.parse()? {
+				fraction if fraction > 0.0 && fraction <= 1.0 => Ok(Step::Crop(fraction)),
+				_ => anyhow::bail!("Invalid crop: {} (expected a fraction from 0 to 1)", fraction),
+			},
+			None if s == "normalize" => Ok(Step::Normalize),
+			None if s == "equalize" => Ok(Step::Equalize),
+			Some(("blur", sigma)) => match sigma.parse()? {
+				sigma if f32::is_finite(sigma) && sigma > 0.0 => Ok(Step::Blur(sigma)),
+				_ => anyhow::bail!("Invalid blur: {} (expected a positive standard deviation)", sigma),
+			},
+			_ => anyhow::bail!("Unknown preprocessing step: {}", s),
+		}
+	}
+}
+
+
+impl Step {
+	/// Whether the step adjusts the full image, before it is downscaled.
+	pub fn is_full_image(&self) -> bool {
+		matches!(self, Step::Trim | Step::Crop(_))
+	}
+}
+
+
+/// Write a list of steps as recorded in metadata: comma separated, or `none`.
+pub fn format_steps(steps: &[Step]) -> String {
+	if steps.is_empty() {
+		return "none".to_string();
+	}
+
+	steps.iter().map(Step::to_string).collect::<Vec<_>>().join(",")
+}
+
+
+/// Parse a list of steps written by `format_steps`.
+pub fn parse_steps(s: &str) -> anyhow::Result<Vec<Step>> {
+	if s == "none" {
+		return Ok(Vec::new());
+	}
+
+	s.split(',').map(str::parse).collect()
+}
+
+
+/// Apply the full image preprocessing steps to an image, in order.
+pub fn apply_image_steps<'a>(img: &'a DynamicImage, steps: &[Step]) -> Cow<'a, DynamicImage> {
+	let mut img = Cow::Borrowed(img);
+	for step in steps {
+		let (x, y, width, height) = match *step {
+			Step::Trim => content_bounds(&img),
+			Step::Crop(fraction) => {
+				let (width, height) = (((img.width() as f32 * fraction) as u32).max(1), ((img.height() as f32 * fraction) as u32).max(1));
+				((img.width() - width) / 2, (img.height() - height) / 2, width, height)
+			},
+			_ => continue,
+		};
+
+		if (width, height) != (img.width(), img.height()) {
+			img = Cow::Owned(img.crop_imm(x, y, width, height));
+		}
+	}
+
+	img
+}
+
+
+/// How far a pixel's luma can be from the color of the corner for it to count as part of a uniform border.
+const TRIM_TOLERANCE: u8 = 16;
+
+
+/// The region of an image inside its uniform borders, those of the color of its top left corner, as `(x, y, width,
+/// height)`.  The whole image if it is uniform.
+fn content_bounds(img: &DynamicImage) -> (u32, u32, u32, u32) {
+	let luma = img.to_luma8();
+	let (width, height) = luma.dimensions();
+	let Some(border) = luma.get_pixel_checked(0, 0).map(|pixel| pixel.0[0]) else {
+		return (0, 0, width, height);
+	};
+
+	let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+	for (x, y, pixel) in luma.enumerate_pixels() {
+		if pixel.0[0].abs_diff(border) > TRIM_TOLERANCE {
+			(left, top, right, bottom) = (left.min(x), top.min(y), right.max(x), bottom.max(y));
+		}
+	}
+
+	if left > right {
+		return (0, 0, width, height);
+	}
+	(left, top, right - left + 1, bottom - top + 1)
+}
+
+
+/// Apply the downscaled image preprocessing steps to a downscaled grayscale image, in order.
+pub fn apply_steps(mut img: GrayImage, steps: &[Step]) -> GrayImage {
+	for step in steps {
+		img = match *step {
+			Step::Trim | Step::Crop(_) => img,
+			Step::Normalize => normalize(img),
+			Step::Equalize => equalize(img),
+			Step::Blur(sigma) => imageops::blur(&img, sigma),
+		};
+	}
+
+	img
+}
+
+
+fn normalize(mut img: GrayImage) -> GrayImage {
+	let min = img.as_raw().iter().copied().min().unwrap_or(0) as u32;
+	let max = img.as_raw().iter().copied().max().unwrap_or(0) as u32;
+
+	if max > min {
+		for v in img.iter_mut() {
+			*v = ((*v as u32 - min) * 255 / (max - min)) as u8;
+		}
+	}
+
+	img
+}
+
+
+fn equalize(mut img: GrayImage) -> GrayImage {
+	let mut histogram = [0u32; 256];
+	for v in img.iter() {
+		histogram[*v as usize] += 1;
+	}
+
+	let mut cdf = [0u32; 256];
+	let mut total = 0;
+	for (i, count) in histogram.iter().enumerate() {
+		total += count;
+		cdf[i] = total;
+	}
+
+	// Map the lowest value present to 0 and the highest to 255
+	let cdf_min = cdf.iter().copied().find(|c| *c > 0).unwrap_or(0);
+	if total > cdf_min {
+		for v in img.iter_mut() {
+			*v = ((cdf[*v as usize] - cdf_min) as f32 * 255.0 / (total - cdf_min) as f32).round() as u8;
+		}
+	}
+
+	img
+}
@@ -0,0 +1,34 @@
+//! Lowers the CPU and I/O priority of the process (`--nice`), so that long runs can go on in the background.
+
+
+/// Niceness the process runs at, as with `nice` by default.
+#[cfg(unix)]
+const NICENESS: libc::c_int = 10;
+
+
+/// Run at a lower CPU priority and, on Linux, in the idle I/O class, so that disks are only read when nothing else
+/// needs them.  Only threads started afterwards inherit the priority on Linux, so this is called before any are.
+pub fn lower() {
+	#[cfg(unix)]
+	// SAFETY: plain system calls on the calling process
+	unsafe {
+		if libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS) != 0 {
+			tracing::warn!("Warning: couldn't lower CPU priority: {}", std::io::Error::last_os_error());
+		}
+	}
+
+	#[cfg(target_os = "linux")]
+	{
+		const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+		const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+		const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+		// SAFETY: ioprio_set has no memory arguments
+		if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } != 0 {
+			tracing::warn!("Warning: couldn't lower I/O priority: {}", std::io::Error::last_os_error());
+		}
+	}
+
+	#[cfg(not(unix))]
+	tracing::warn!("Warning: --nice isn't supported on this platform");
+}
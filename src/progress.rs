@@ -0,0 +1,96 @@
+//! Shows the progress of hashing runs: as a progress bar, as lines of text on stderr (`--progress plain`) for screen
+//! readers and logs, or as JSON lines (`--progress json`) for programs driving the tool.
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use serde::Serialize;
+use std::{
+	fmt,
+	sync::mpsc::{Receiver, RecvTimeoutError},
+	time::{Duration, Instant},
+};
+
+
+/// How the progress of a hashing run is shown.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+	/// An interactive progress bar.
+	Bar,
+	/// A JSON object per line on stderr every few seconds, with `processed`, `failed` and `total` inputs, the `rate` in
+	/// inputs per second and `eta_secs`.  Other messages on stderr aren't JSON.
+	Json,
+	/// A line of text on stderr every ten seconds, e.g. `Hashed 1200 of 5000 inputs (3 failed), 40.0 per second, about
+	/// 95 seconds left`.  Suppressed with `-q`.
+	Plain,
+}
+
+impl ProgressFormat {
+	/// How often progress records are written.
+	fn interval(self) -> Duration {
+		match self {
+			ProgressFormat::Plain => Duration::from_secs(10),
+			_ => Duration::from_secs(2),
+		}
+	}
+}
+
+
+/// One progress record.
+#[derive(Serialize, Debug)]
+struct Record {
+	/// Inputs hashed or failed so far, out of `total`.
+	processed: u64,
+	failed: u64,
+	total: u64,
+	rate: f64,
+	/// Estimated seconds until all inputs are processed, or None until the rate is known.
+	eta_secs: Option<f64>,
+}
+
+
+impl fmt::Display for Record {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Hashed {} of {} inputs ({} failed), {:.1} per second", self.processed, self.total, self.failed, self.rate)?;
+		match self.eta_secs {
+			Some(eta) => write!(f, ", about {:.0} seconds left", eta),
+			None => Ok(()),
+		}
+	}
+}
+
+
+/// Write a progress record in `format`, JSON or plain, every so often until `done` is disconnected, then a last one.
+/// `counts` gives the number of inputs processed and failed so far.
+pub fn report(format: ProgressFormat, total: u64, counts: impl Fn() -> (u64, u64), done: Receiver<()>) {
+	let start = Instant::now();
+
+	let record = || {
+		let (processed, failed) = counts();
+		let rate = processed as f64 / start.elapsed().as_secs_f64();
+		let record = Record {
+			processed,
+			failed,
+			total,
+			rate,
+			eta_secs: (rate > 0.0).then(|| total.saturating_sub(processed) as f64 / rate),
+		};
+		match format {
+			ProgressFormat::Json => eprintln!("{}", serde_json::to_string(&record).unwrap()),
+			_ => tracing::info!("{}", record),
+		}
+	};
+
+	while let Err(RecvTimeoutError::Timeout) = done.recv_timeout(format.interval()) {
+		record();
+	}
+	record();
+}
+
+
+/// A progress bar of `len` steps, hidden with `-q`.
+pub fn bar(len: u64) -> ProgressBar {
+	if tracing::enabled!(tracing::Level::INFO) {
+		ProgressBar::new(len)
+	} else {
+		ProgressBar::hidden()
+	}
+}
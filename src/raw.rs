@@ -0,0 +1,89 @@
+//! Camera RAW support through the JPEG previews that cameras embed in their RAW files.
+//! CR2, CR3, NEF, ARW, DNG and most other RAW formats carry a full or near-full size preview, which is plenty for
+//! a 32x32 hash and avoids demosaicing the sensor data.
+use std::path::Path;
+
+const RAW_EXTENSIONS: &[&str] = &["3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw", "x3f"];
+
+
+/// Whether the input looks like a camera RAW file, judging by its extension.
+pub fn is_raw(path: &Path) -> bool {
+	path.extension()
+		.and_then(|ext| ext.to_str())
+		.is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+
+/// Find the largest embedded JPEG in a RAW file.
+/// Rather than parsing each vendor's container, this scans for JPEG streams and measures them from their markers.
+pub fn extract_preview(data: &[u8]) -> Option<&[u8]> {
+	let mut best: Option<(u64, &[u8])> = None;
+	let mut offset = 0;
+
+	while let Some(start) = find(&data[offset..], &[0xff, 0xd8, 0xff]).map(|pos| offset + pos) {
+		match parse_jpeg(&data[start..]) {
+			Some((pixels, len)) => {
+				if best.is_none_or(|(best_pixels, _)| pixels > best_pixels) {
+					best = Some((pixels, &data[start..start + len]));
+				}
+				// Skip past this stream, so thumbnails nested inside it aren't considered separately
+				offset = start + len;
+			},
+			None => offset = start + 1,
+		}
+	}
+
+	best.map(|(_, jpeg)| jpeg)
+}
+
+
+/// Walk the markers of a JPEG stream at the start of `data`, returning its pixel count and length in bytes.
+fn parse_jpeg(data: &[u8]) -> Option<(u64, usize)> {
+	let mut pos = 2;
+	let mut pixels = None;
+
+	loop {
+		if *data.get(pos)? != 0xff {
+			return None;
+		}
+
+		let marker = *data.get(pos + 1)?;
+		match marker {
+			// Fill bytes
+			0xff => pos += 1,
+			// End of image
+			0xd9 => return Some((pixels?, pos + 2)),
+			// Markers without a length
+			0x01 | 0xd0..=0xd7 => pos += 2,
+			_ => {
+				let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+				let segment = data.get(pos + 4..pos + 2 + len)?;
+
+				// Start of frame (baseline, progressive, lossless, ...) but not DHT, JPG and DAC, which share the range
+				if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) && segment.len() >= 5 {
+					let height = u16::from_be_bytes([segment[1], segment[2]]) as u64;
+					let width = u16::from_be_bytes([segment[3], segment[4]]) as u64;
+					pixels = Some(width * height);
+				}
+
+				pos += 2 + len;
+
+				// Start of scan: skip the entropy-coded data up to the next marker
+				if marker == 0xda {
+					loop {
+						pos += data.get(pos..)?.iter().position(|&byte| byte == 0xff)?;
+						if !matches!(data.get(pos + 1)?, 0x00 | 0xd0..=0xd7) {
+							break;
+						}
+						pos += 1;
+					}
+				}
+			},
+		}
+	}
+}
+
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
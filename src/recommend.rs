@@ -0,0 +1,233 @@
+//! Recommends an algorithm and threshold for a collection, from how well each algorithm matches altered copies of a
+//! sample of its images without matching different images.
+use image::{imageops::FilterType, DynamicImage};
+use rand::seq::IteratorRandom;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+use crate::{
+	codecs::decode,
+	compare::hashes,
+	hasher::Hasher,
+	settings::{Algorithm, Settings},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct RecommendArgs {
+	/// Directory of images typical of the collection, searched recursively.
+	#[arg(long, value_name = "DIR")]
+	sample: PathBuf,
+
+	/// Number of images to draw from the directory at random.
+	#[arg(long, default_value_t = 200)]
+	count: usize,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+/// Algorithms compared.
+const ALGORITHMS: &[Algorithm] = &[Algorithm::Phash, Algorithm::Dhash, Algorithm::Ahash];
+
+/// Largest fraction of pairs of different sample images a recommended threshold may match.
+const MAX_FALSE_MATCHES: f64 = 0.001;
+
+/// Largest threshold considered.
+const MAX_THRESHOLD: u32 = 24;
+
+
+/// An alteration that copies of an image commonly go through.
+type Perturbation = fn(&DynamicImage) -> DynamicImage;
+
+/// Perturbations applied to each sample image, by name.
+const PERTURBATIONS: &[(&str, Perturbation)] = &[
+	("resize", |img| img.resize_exact((img.width() / 2).max(1), (img.height() / 2).max(1), FilterType::Triangle)),
+	("jpeg", |img| reencode(img, 70)),
+	("crop", |img| {
+		let (x, y) = (img.width() / 20, img.height() / 20);
+		img.crop_imm(x, y, img.width() - 2 * x, img.height() - 2 * y)
+	}),
+	("brightness", |img| img.brighten(20)),
+	("blur", |img| img.blur(1.0)),
+];
+
+
+/// Re-encode an image as a JPEG of the given quality, e.g. 70 for middling quality.
+#[cfg(feature = "jpeg")]
+pub fn reencode(img: &DynamicImage, quality: u8) -> DynamicImage {
+	let mut data = Vec::new();
+	let encoded = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality).encode_image(&DynamicImage::ImageRgb8(img.to_rgb8()));
+
+	match encoded.map_err(anyhow::Error::from).and_then(|_| decode(&data)) {
+		Ok(decoded) => decoded.image,
+		Err(_) => img.clone(),
+	}
+}
+
+
+/// Without JPEG support images are left as they are.
+#[cfg(not(feature = "jpeg"))]
+pub fn reencode(img: &DynamicImage, _quality: u8) -> DynamicImage {
+	img.clone()
+}
+
+
+/// The hashes of a sample image for each algorithm, and those of each of its altered copies that could be hashed.
+struct Sample {
+	hashes: Vec<u64>,
+	copies: Vec<Option<Vec<u64>>>,
+}
+
+
+/// How well an algorithm does at its best threshold.
+struct Evaluation {
+	algorithm: Algorithm,
+	threshold: u32,
+	/// Fraction of altered copies matched, overall and for each perturbation.
+	recall: f64,
+	recall_by_perturbation: Vec<f64>,
+	/// Fraction of pairs of different images matched.
+	false_matches: f64,
+}
+
+
+/// Print, for each algorithm, the largest threshold at which few pairs of different sample images match and how many
+/// altered copies it matches, as `algorithm\tthreshold=N\trecall=0.xx\tfalse-matches=0.xxxx` followed by the recall
+/// of each kind of alteration, then recommend the algorithm that matches the most.
+pub fn run(args: RecommendArgs) {
+	let mut files = Vec::new();
+	collect_files(&args.sample, &mut files);
+	let files = files.into_iter().choose_multiple(&mut rand::thread_rng(), args.count);
+
+	let source = Source::new(&args.source);
+	let hasher = Hasher::new(
+		Settings {
+			algorithms: ALGORITHMS.to_vec(),
+			..Settings::default()
+		},
+		Source::new(&args.source),
+	);
+
+	let hashed = files
+		.par_iter()
+		.filter_map(|path| {
+			let img = decode(&source.read(path).ok()?.data).ok()?.image;
+			let hash = |img: &DynamicImage| hashes(hasher.hash_image(img).first()?, ALGORITHMS, ALGORITHMS);
+
+			Some(Sample {
+				hashes: hash(&img)?,
+				copies: PERTURBATIONS.iter().map(|(_, perturb)| hash(&perturb(&img))).collect(),
+			})
+		})
+		.collect::<Vec<_>>();
+
+	if hashed.len() < 2 {
+		error!("Error: fewer than two images could be read from {}", args.sample.display());
+		std::process::exit(1);
+	}
+
+	let evaluations = ALGORITHMS.iter().enumerate().map(|(i, algorithm)| evaluate(*algorithm, i, &hashed)).collect::<Vec<_>>();
+
+	for evaluation in &evaluations {
+		let mut line = format!(
+			"{}\tthreshold={}\trecall={:.2}\tfalse-matches={:.4}",
+			evaluation.algorithm, evaluation.threshold, evaluation.recall, evaluation.false_matches
+		);
+		for ((name, _), recall) in PERTURBATIONS.iter().zip(&evaluation.recall_by_perturbation) {
+			line.push_str(&format!("\t{}={:.2}", name, recall));
+		}
+		println!("{}", line);
+	}
+
+	// Of the algorithms that tell the sample's images apart, the one that matches the most copies; the first breaks
+	// ties, as the default.  If none do, the sample holds near-duplicates of its own and the one with the fewest
+	// false matches is safest.
+	let distinct = evaluations.iter().filter(|evaluation| evaluation.false_matches <= MAX_FALSE_MATCHES).collect::<Vec<_>>();
+	let best = if let Some(best) = distinct.iter().rev().max_by(|a, b| a.recall.total_cmp(&b.recall)) {
+		*best
+	} else {
+		warn!("Warning: many of the sampled images are similar to each other, so no threshold keeps them apart");
+		evaluations.iter().rev().min_by(|a, b| a.false_matches.total_cmp(&b.false_matches)).unwrap()
+	};
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"recommend",
+		serde_json::json!({
+			"sample": args.sample,
+			"images": hashed.len(),
+			"algorithm": best.algorithm.to_string(),
+			"threshold": best.threshold,
+		}),
+	);
+
+	info!(
+		"Recommended for these {} images: --algorithm {}, with a threshold of {} (e.g. dedupe -t {}), which matches {:.0}% of altered copies",
+		hashed.len(),
+		best.algorithm,
+		best.threshold,
+		best.threshold,
+		best.recall * 100.0
+	);
+}
+
+
+/// Find the largest threshold at which at most `MAX_FALSE_MATCHES` of the pairs of different images match using the
+/// `i`th algorithm's hashes, and how many altered copies match at it.
+fn evaluate(algorithm: Algorithm, i: usize, hashed: &[Sample]) -> Evaluation {
+	// Number of pairs of different images at each distance.  Samples may hold duplicates of their own, so even a
+	// threshold of 0 can match more than wanted; it is then recommended regardless.
+	let mut pairs = vec![0u64; 65];
+	for (a, x) in hashed.iter().enumerate() {
+		for y in &hashed[a + 1..] {
+			pairs[(x.hashes[i] ^ y.hashes[i]).count_ones() as usize] += 1;
+		}
+	}
+	let total = pairs.iter().sum::<u64>() as f64;
+	let false_matches = |threshold: u32| pairs[..=threshold as usize].iter().sum::<u64>() as f64 / total;
+	let threshold = (1..=MAX_THRESHOLD).take_while(|threshold| false_matches(*threshold) <= MAX_FALSE_MATCHES).last().unwrap_or(0);
+
+	// Fraction of the altered copies, of all perturbations or just one, that match their originals
+	let matched = |perturbation: Option<usize>| {
+		let copies = hashed.iter().flat_map(|sample| {
+			sample
+				.copies
+				.iter()
+				.enumerate()
+				.filter(move |(p, _)| perturbation.is_none_or(|perturbation| *p == perturbation))
+				.filter_map(move |(_, copy)| Some((sample.hashes[i] ^ copy.as_ref()?[i]).count_ones() <= threshold))
+		});
+		let (count, matched) = copies.fold((0, 0), |(count, matched), is_match| (count + 1, matched + is_match as usize));
+		if count == 0 { 0.0 } else { matched as f64 / count as f64 }
+	};
+
+	Evaluation {
+		algorithm,
+		threshold,
+		recall: matched(None),
+		recall_by_perturbation: (0..PERTURBATIONS.len()).map(|p| matched(Some(p))).collect(),
+		false_matches: false_matches(threshold),
+	}
+}
+
+
+/// Every file under a directory, including those symlinked to.  Unreadable directories and symlinks to directories,
+/// which could form cycles, are skipped.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+	let Ok(entries) = std::fs::read_dir(dir) else {
+		return;
+	};
+
+	for entry in entries.flatten() {
+		let path = entry.path();
+		if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+			collect_files(&path, files);
+		} else if path.is_file() {
+			files.push(path);
+		}
+	}
+}
@@ -0,0 +1,88 @@
+//! Writes HTML pages showing clusters of duplicates side by side, for a person to review before deleting any.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rayon::prelude::*;
+use std::{
+	fmt::Write as _,
+	fs::File,
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+};
+use tracing::error;
+
+use crate::{dedupe::largest_file, settings::Settings, source::Source, thumbnail::render};
+
+
+/// Longest side of the thumbnails in a report.
+const THUMBNAIL_SIZE: u32 = 160;
+
+
+const STYLE: &str = "body{font-family:sans-serif;margin:1em}section{border-top:1px solid #ccc;padding:.5em 0}\
+	figure{display:inline-block;vertical-align:top;width:180px;margin:.5em;font-size:small;word-break:break-all}\
+	figure.keep{outline:3px solid #4a4}img{display:block;max-width:160px;max-height:160px}";
+
+
+/// Write a self-contained page showing each cluster with a thumbnail, the size and the dimensions of each member, the
+/// member `largest_file` would keep outlined.  Members that can't be read are listed without a thumbnail.
+pub fn write(path: &Path, clusters: &[Vec<PathBuf>], source: &Source, settings: &Settings) -> anyhow::Result<()> {
+	let mut html = String::new();
+	writeln!(html, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Duplicates</title><style>{}</style></head><body>", STYLE)?;
+	writeln!(html, "<h1>{} clusters of duplicates</h1>", clusters.len())?;
+
+	for (id, members) in clusters.iter().enumerate() {
+		let keep = largest_file(members);
+		let figures = members.par_iter().map(|member| figure(member, member == keep, source, settings)).collect::<Vec<_>>();
+
+		writeln!(html, "<section><h2>Cluster {}</h2>", id)?;
+		for figure in figures {
+			html.push_str(&figure);
+		}
+		writeln!(html, "</section>")?;
+	}
+	writeln!(html, "</body></html>")?;
+
+	let mut writer = BufWriter::new(File::create(path)?);
+	writer.write_all(html.as_bytes())?;
+	writer.flush()?;
+
+	Ok(())
+}
+
+
+/// A member of a cluster, as a `<figure>`.
+fn figure(path: &Path, keep: bool, source: &Source, settings: &Settings) -> String {
+	let size = std::fs::metadata(path).map(|metadata| format_size(metadata.len())).unwrap_or_else(|_| "unknown size".to_string());
+
+	let (image, dimensions) = match render(source, settings, path, THUMBNAIL_SIZE) {
+		Ok((data, (width, height))) => (format!("<img src=\"data:image/jpeg;base64,{}\" alt=\"\">", STANDARD.encode(data)), format!("{}x{}", width, height)),
+		Err(err) => {
+			error!("Error creating thumbnail for {}: {}", path.display(), err);
+			(String::new(), "unreadable".to_string())
+		},
+	};
+
+	format!(
+		"<figure{}>{}<figcaption>{}<br>{}, {}{}</figcaption></figure>\n",
+		if keep { " class=\"keep\"" } else { "" },
+		image,
+		escape(&path.display().to_string()),
+		size,
+		dimensions,
+		if keep { ", largest" } else { "" }
+	)
+}
+
+
+/// A file size in bytes, KiB or MiB.
+fn format_size(bytes: u64) -> String {
+	match bytes {
+		0..1024 => format!("{} B", bytes),
+		1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+		_ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+	}
+}
+
+
+/// Text with the characters HTML treats specially escaped.
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
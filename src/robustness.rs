@@ -0,0 +1,96 @@
+//! Measures how much the hashes of an output file's configuration change under perturbations too small to see, such
+//! as those hiding a steganographic payload in the lowest bits of each pixel (`stats --robustness`), to judge whether
+//! it can match images an adversary has tweaked.
+use image::{DynamicImage, Rgba};
+use rand::{seq::IteratorRandom, Rng};
+use rayon::prelude::*;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+use crate::{
+	compare::hashes,
+	hasher::Hasher,
+	settings::{Algorithm, Settings},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct RobustnessArgs {
+	/// Re-hash this many inputs drawn at random, each perturbed by randomizing the lowest bit (`lsb-1`) or two
+	/// (`lsb-2`) of every channel of every pixel, as steganography does, and by adding uniform noise of up to 2
+	/// (`noise-2`) or 8 (`noise-8`) levels, and report how far their hashes move.  The inputs must still be readable.
+	#[arg(long, value_name = "N")]
+	robustness: Option<usize>,
+}
+
+
+/// A perturbation of each channel value, given a random number.
+type Perturbation = fn(u8, u32) -> u8;
+
+const PERTURBATIONS: &[(&str, Perturbation)] = &[
+	("lsb-1", |value, random| value & !1 | (random & 1) as u8),
+	("lsb-2", |value, random| value & !3 | (random & 3) as u8),
+	("noise-2", |value, random| (value as i32 + (random % 5) as i32 - 2).clamp(0, 255) as u8),
+	("noise-8", |value, random| (value as i32 + (random % 17) as i32 - 8).clamp(0, 255) as u8),
+];
+
+
+/// Print a `robustness\tperturbation\timages\tunchanged\tmean\tmax` line for each perturbation: how many sampled
+/// images were perturbed, the fraction whose hash didn't change, and the mean and largest distance it moved.  Images
+/// are hashed with the output file's settings, and their hashes compared with those of the unperturbed images.
+pub fn report(args: &RobustnessArgs, paths: impl Iterator<Item = PathBuf>, settings: &Settings, algorithm: Algorithm) {
+	let Some(count) = args.robustness else {
+		return;
+	};
+
+	let paths = paths.choose_multiple(&mut rand::thread_rng(), count);
+	let source = Source::new(&SourceArgs::default());
+	let hasher = Hasher::new(settings.clone(), Source::new(&SourceArgs::default()));
+	let hash = |img: &DynamicImage| hashes(hasher.hash_image(img).first()?, &settings.algorithms, &[algorithm]).map(|hash| hash[0]);
+
+	let distances = paths
+		.par_iter()
+		.filter_map(|path| {
+			let img = match source.read(path).and_then(|input| hasher.decode_image(&input.data)) {
+				Ok(img) => DynamicImage::ImageRgba8(img.to_rgba8()),
+				Err(err) => {
+					warn!("Warning: couldn't re-hash {} for --robustness: {}", path.display(), err);
+					return None;
+				},
+			};
+			let original = hash(&img)?;
+
+			let mut rng = rand::thread_rng();
+			Some(
+				PERTURBATIONS
+					.iter()
+					.map(|(_, perturb)| {
+						let mut perturbed = img.to_rgba8();
+						for Rgba(pixel) in perturbed.pixels_mut() {
+							// Alpha is left alone, as it is by steganography tools
+							for value in &mut pixel[..3] {
+								*value = perturb(*value, rng.gen());
+							}
+						}
+						hash(&DynamicImage::ImageRgba8(perturbed)).map(|hash| (hash ^ original).count_ones())
+					})
+					.collect::<Vec<_>>(),
+			)
+		})
+		.collect::<Vec<_>>();
+
+	let mut worst = 0;
+	for (p, (name, _)) in PERTURBATIONS.iter().enumerate() {
+		let distances = distances.iter().filter_map(|distances| distances[p]).collect::<Vec<_>>();
+		let images = distances.len().max(1) as f64;
+		let unchanged = distances.iter().filter(|distance| **distance == 0).count() as f64 / images;
+		let mean = distances.iter().sum::<u32>() as f64 / images;
+		let max = distances.iter().copied().max().unwrap_or(0);
+		worst = worst.max(max);
+
+		println!("robustness\t{}\t{}\t{:.4}\t{:.2}\t{}", name, distances.len(), unchanged, mean, max);
+	}
+
+	info!("Perturbed {} sampled images: their {} hashes moved by at most {} bits", distances.len(), algorithm, worst);
+}
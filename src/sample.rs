@@ -0,0 +1,118 @@
+//! Hashing a sample of the inputs and extrapolating duplicate statistics to the whole corpus.
+use clap::ValueEnum;
+use std::{
+	collections::{BTreeMap, HashMap},
+	path::{Path, PathBuf},
+};
+
+use crate::shards::path_hash;
+
+
+/// How the sample is drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SampleBy {
+	/// Uniformly from all inputs.
+	#[default]
+	Random,
+	/// At least one from every directory, and the rest in proportion to the number of inputs each holds, so that no
+	/// directory is missed by chance.  Samples smaller than the number of directories are drawn in proportion only.
+	Directory,
+}
+
+
+/// Choose `size` of the inputs.  Inputs are drawn by a hash of their paths rather than at random, so that a run resumed
+/// with the same input list samples the same inputs, and one resumed with a changed list only samples differently where
+/// paths were added or removed, whatever order they are listed in.
+pub fn choose<T>(inputs: HashMap<PathBuf, T>, size: usize, by: SampleBy) -> HashMap<PathBuf, T> {
+	if by == SampleBy::Random || inputs.len() <= size {
+		return lowest(inputs.into_iter().collect(), size).collect();
+	}
+
+	let total = inputs.len();
+	let mut directories: BTreeMap<PathBuf, Vec<(PathBuf, T)>> = BTreeMap::new();
+	for (path, value) in inputs {
+		directories.entry(path.parent().map(Path::to_path_buf).unwrap_or_default()).or_default().push((path, value));
+	}
+
+	// One place for each directory, if there are enough, and the rest allocated in proportion to the inputs left
+	let (base, size, total) = match size.checked_sub(directories.len()) {
+		Some(rest) => (1, rest, total - directories.len()),
+		None => (0, size, total),
+	};
+
+	// Proportional allocation, handing the places lost to rounding down to the directories with the largest remainders
+	let mut allocations = directories.values().map(|members| base + (members.len() - base) * size / total).collect::<Vec<_>>();
+	let mut remainders = directories.values().enumerate().map(|(i, members)| ((members.len() - base) * size % total, i)).collect::<Vec<_>>();
+	remainders.sort_unstable_by(|a, b| b.cmp(a));
+	for (_, i) in remainders.into_iter().take(base * directories.len() + size - allocations.iter().sum::<usize>()) {
+		allocations[i] += 1;
+	}
+
+	directories
+		.into_values()
+		.zip(allocations)
+		.flat_map(|(members, allocation)| lowest(members, allocation))
+		.collect()
+}
+
+
+/// The `count` inputs with the lowest hashes of their paths, which are as good as a random sample of them.
+fn lowest<T>(mut inputs: Vec<(PathBuf, T)>, count: usize) -> impl Iterator<Item = (PathBuf, T)> {
+	inputs.sort_by_cached_key(|(path, _)| path_hash(path));
+	inputs.truncate(count);
+	inputs.into_iter()
+}
+
+
+/// Print duplicate statistics for a sample of `sampled` inputs drawn from `population`, of which `phashes` were hashed.
+/// Two inputs are duplicates if any of their hashes are within `threshold` bits of each other.  They are the result of
+/// the run, so they go on stdout, which `--quiet` leaves alone.
+pub fn report(population: usize, sampled: usize, phashes: &[Vec<u64>], threshold: u32) {
+	let hashed = phashes.len();
+
+	let mut pairs = 0u64;
+	let mut has_duplicate = vec![false; hashed];
+	for i in 0..hashed {
+		for j in i + 1..hashed {
+			if phashes[i].iter().any(|a| phashes[j].iter().any(|b| (a ^ b).count_ones() <= threshold)) {
+				pairs += 1;
+				has_duplicate[i] = true;
+				has_duplicate[j] = true;
+			}
+		}
+	}
+	let with_duplicate = has_duplicate.iter().filter(|d| **d).count();
+
+	println!("Sampled {} of {} inputs, {} hashed", sampled, population, hashed);
+	println!("Duplicate pairs in the sample (distance <= {}): {}", threshold, pairs);
+	if hashed > 0 {
+		println!("Sampled inputs with a duplicate in the sample: {:.2}%", 100.0 * with_duplicate as f64 / hashed as f64);
+	}
+	if hashed < 2 {
+		return;
+	}
+
+	// Inputs that failed to hash are assumed to be as common in the rest of the corpus as in the sample
+	let n = hashed as f64;
+	let hashable = population as f64 * n / sampled as f64;
+
+	// Each pair in the corpus is in the sample with probability n(n-1) / N(N-1)
+	let scale = hashable * (hashable - 1.0) / (n * (n - 1.0));
+
+	// Approximate 95% interval for the count of sample pairs, treating it as Poisson; with no pairs, the rule of three
+	let observed = pairs as f64;
+	let (low, high) = if pairs == 0 {
+		(0.0, 3.0)
+	} else {
+		((observed - 1.96 * observed.sqrt()).max(0.0), observed + 1.96 * observed.sqrt())
+	};
+
+	println!(
+		"Estimated duplicate pairs in all {} inputs: {:.0} (95% CI {:.0} - {:.0})",
+		population,
+		observed * scale,
+		low * scale,
+		high * scale
+	);
+	println!("Every input that deduplication could remove is part of a pair, so this also bounds how many can be removed");
+}
@@ -0,0 +1,190 @@
+//! Checks that this build reproduces the known hashes of generated test patterns (`self-test`), so that a dependency
+//! upgrade changing how images are decoded or scaled can't silently change the hashes of existing output files.
+use image::{DynamicImage, Rgb, RgbImage};
+use tracing::{error, info};
+
+use crate::{
+	compat::Compat,
+	hasher::Hasher,
+	settings::{Algorithm, Filter, Settings},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct SelfTestArgs {
+	/// Print the hashes this build computes as the table of expected hashes, to update it after an intended change.
+	#[arg(long, hide = true)]
+	print: bool,
+}
+
+
+const PATTERNS: &[&str] = &["gradient", "checker", "rings", "shapes", "noise"];
+
+/// Settings each pattern is hashed with, covering every algorithm, a second resize filter and each `--compat` mode.
+const VARIANTS: &[&str] = &["phash", "dhash", "ahash", "triangle", "phash-org", "imagehash"];
+
+/// The hash of each pattern with each variant's settings.
+const EXPECTED: &[(&str, &str, u64)] = &[
+	("gradient", "phash", 0x693a6c713cb37164),
+	("checker", "phash", 0x47153d15bf10bf14),
+	("rings", "phash", 0x29d629da295b29fd),
+	("shapes", "phash", 0x09a53e3e23b63e49),
+	("noise", "phash", 0xd3d2cca713a3b01d),
+	("gradient", "dhash", 0x0000000000000000),
+	("checker", "dhash", 0x24db24dbdb24db24),
+	("rings", "dhash", 0x90b00000090d0000),
+	("shapes", "dhash", 0xc0c0e0f0f0707030),
+	("noise", "dhash", 0xa1b5935cde97d552),
+	("gradient", "ahash", 0xfffffffce0000000),
+	("checker", "ahash", 0xcc33cc3333cc33cc),
+	("rings", "ahash", 0xdb3c5aa5e7817e5a),
+	("shapes", "ahash", 0x0060607e1e1e1e00),
+	("noise", "ahash", 0x78d7618603c03d3e),
+	("gradient", "triangle", 0x381b35b335e3b164),
+	("checker", "triangle", 0x7b154d55ef10ce14),
+	("rings", "triangle", 0x29d629c629f929fe),
+	("shapes", "triangle", 0x09a53e3e23b63e49),
+	("noise", "triangle", 0xd3d2cca713a3b01d),
+	("gradient", "phash-org", 0x61412b95623da9f7),
+	("checker", "phash-org", 0x00ff00af80bfa2bf),
+	("rings", "phash-org", 0x8808f7f7f70040bf),
+	("shapes", "phash-org", 0x44817e36b3363ec9),
+	("noise", "phash-org", 0xf6e056c72131dc8d),
+	("gradient", "imagehash", 0x807fa303ef6da740),
+	("checker", "imagehash", 0x8aef2ad50afaa8d0),
+	("rings", "imagehash", 0xf9bb4404bb44fb04),
+	("shapes", "imagehash", 0xc1c93e3764363fc0),
+	("noise", "imagehash", 0xdcd89dcec2e83581),
+];
+
+
+/// Hash the patterns and print a `MISMATCH\tpattern\tvariant\tinput\texpected\tactual\tdistance` line for each that
+/// doesn't match, exiting with status 1 if any don't.  Each pattern is hashed from its `pixels` and from a `png`
+/// encoding of it, to also check decoding.
+pub fn run(args: SelfTestArgs) {
+	let mut checked = 0;
+	let mut mismatched = 0;
+
+	for variant in VARIANTS {
+		let hasher = Hasher::new(settings(variant), Source::new(&SourceArgs::default()));
+
+		for name in PATTERNS {
+			let img = DynamicImage::ImageRgb8(pattern(name));
+			let hash = hasher.hash_image(&img).first().and_then(|entry| entry.phash).expect("patterns aren't trivial");
+
+			if args.print {
+				println!("\t(\"{}\", \"{}\", {:#018x}),", name, variant, hash);
+				continue;
+			}
+
+			let expected = EXPECTED.iter().find(|(pattern, expected_variant, _)| pattern == name && expected_variant == variant).map(|(_, _, hash)| *hash);
+			let mut actual = vec![("pixels", hash)];
+			#[cfg(feature = "png")]
+			actual.push(("png", hash_png(&hasher, &img)));
+
+			for (input, actual) in actual {
+				checked += 1;
+				if Some(actual) != expected {
+					mismatched += 1;
+					let distance = expected.map(|expected| (expected ^ actual).count_ones().to_string()).unwrap_or_default();
+					println!("MISMATCH\t{}\t{}\t{}\t{:#018x}\t{:#018x}\t{}", name, variant, input, expected.unwrap_or(0), actual, distance);
+				}
+			}
+		}
+	}
+
+	if args.print {
+		return;
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record("self-test", serde_json::json!({ "checked": checked, "mismatched": mismatched }));
+
+	if mismatched > 0 {
+		error!("{} of {} hashes don't match; this build would compute different hashes from those in existing output files", mismatched, checked);
+		std::process::exit(1);
+	}
+	info!("All {} hashes match", checked);
+}
+
+
+fn settings(variant: &str) -> Settings {
+	let settings = Settings::default();
+
+	match variant {
+		"phash" => settings,
+		"dhash" => Settings {
+			algorithms: vec![Algorithm::Dhash],
+			..settings
+		},
+		"ahash" => Settings {
+			algorithms: vec![Algorithm::Ahash],
+			..settings
+		},
+		"triangle" => Settings {
+			filter: Filter::Triangle,
+			..settings
+		},
+		"phash-org" => Settings {
+			compat: Some(Compat::PhashOrg),
+			..settings
+		},
+		"imagehash" => Settings {
+			compat: Some(Compat::Imagehash),
+			..settings
+		},
+		_ => unreachable!("unknown variant {}", variant),
+	}
+}
+
+
+/// Hash an image decoded from its PNG encoding.
+#[cfg(feature = "png")]
+fn hash_png(hasher: &Hasher, img: &DynamicImage) -> u64 {
+	let mut png = Vec::new();
+	img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).expect("encoding to memory can't fail");
+
+	hasher.hash_bytes(&png).ok().and_then(|entries| entries.first().and_then(|entry| entry.phash)).unwrap_or(0)
+}
+
+
+/// A test pattern, computed with integer arithmetic only so that it is the same on every platform.
+fn pattern(name: &str) -> RgbImage {
+	match name {
+		"gradient" => RgbImage::from_fn(256, 160, |x, y| Rgb([x as u8, (y * 255 / 159) as u8, ((x + y) / 2) as u8])),
+		"checker" => RgbImage::from_fn(240, 180, |x, y| if (x / 20 + y / 20) % 2 == 0 { Rgb([30, 40, 60]) } else { Rgb([220, 210, 190]) }),
+		"rings" => RgbImage::from_fn(201, 201, |x, y| {
+			let (dx, dy) = (x as i32 - 100, y as i32 - 70);
+			let v = ((dx * dx + dy * dy) / 97 % 2 * 200 + 25) as u8;
+			Rgb([v, v / 2, 255 - v])
+		}),
+		"shapes" => RgbImage::from_fn(320, 240, |x, y| {
+			let (dx, dy) = (x as i32 - 230, y as i32 - 160);
+			if (40..200).contains(&x) && (30..150).contains(&y) {
+				Rgb([240, 200, 40])
+			} else if dx * dx + dy * dy < 60 * 60 {
+				Rgb([40, 90, 230])
+			} else {
+				Rgb([20, 20, 20 + (y / 4) as u8])
+			}
+		}),
+		"noise" => {
+			// xorshift32, constant over 8x8 blocks so that the hash isn't decided by aliasing
+			let mut state = 0x9E37_79B9u32;
+			let blocks = (0..16 * 12)
+				.map(|_| {
+					state ^= state << 13;
+					state ^= state >> 17;
+					state ^= state << 5;
+					state.to_le_bytes()
+				})
+				.collect::<Vec<_>>();
+			RgbImage::from_fn(128, 96, |x, y| {
+				let block = blocks[(y / 8 * 16 + x / 8) as usize];
+				Rgb([block[0], block[1], block[2]])
+			})
+		},
+		_ => unreachable!("unknown pattern {}", name),
+	}
+}
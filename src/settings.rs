@@ -0,0 +1,580 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use std::{fmt, str::FromStr};
+
+use crate::{
+	cache::Metadata,
+	compat::Compat,
+	preprocess::{format_steps, parse_steps, Background, Step},
+};
+
+
+/// Everything that affects the hashes computed for an input.
+/// Recorded in the output file's metadata, so that later runs and `verify` use the same settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+	/// Hashes computed for each image.  The first is written in the hash column, the rest in `<algorithm>=` columns.
+	pub algorithms: Vec<Algorithm>,
+
+	/// Rotate and flip images according to their EXIF orientation before hashing.
+	pub exif_orientation: bool,
+
+	/// Scale JPEGs down while decoding them.
+	pub scaled_jpeg: bool,
+
+	/// Hash the thumbnail embedded in the EXIF metadata of images that have one, instead of the image itself.
+	pub thumbnails: bool,
+
+	/// What images with an alpha channel are composited onto.
+	pub background: Background,
+
+	/// Which frames of animated images to hash.
+	pub frames: Frames,
+
+	/// How images are converted to grayscale.
+	pub grayscale: Grayscale,
+
+	/// How images are scaled down to the 32x32 that is hashed.
+	pub filter: Filter,
+
+	/// Record essentially uniform images as trivial instead of hashing them.
+	pub skip_trivial: bool,
+
+	/// Adjustments made to the downscaled image before hashing, in order.
+	pub preprocess: Vec<Step>,
+
+	/// Also hash rotated and mirrored copies of images.
+	pub invariant: Option<Invariant>,
+
+	/// Also hash each tile of an NxN grid over images.
+	pub tiles: Option<u32>,
+
+	/// Hash this many jittered copies of images, setting each bit of their hashes as in most copies.
+	pub ensemble: Option<u32>,
+
+	/// Reproduce another library's hash instead of computing this tool's own.
+	pub compat: Option<Compat>,
+
+	/// Hash frames of video files instead of treating them as images.
+	pub video: Option<VideoFrames>,
+
+	/// Hash PDF documents by rendering this page, counting from 1.
+	pub pdf_page: Option<u32>,
+}
+
+impl Settings {
+	pub fn metadata(&self) -> Metadata {
+		let mut metadata = Metadata::default();
+
+		metadata.set("algorithm", self.algorithms.iter().map(Algorithm::to_string).collect::<Vec<_>>().join(","));
+		metadata.set("background", self.background.to_string());
+		metadata.set("filter", value_name(self.filter));
+		metadata.set("frames", value_name(self.frames));
+		metadata.set("grayscale", value_name(self.grayscale));
+		metadata.set("jpeg", if self.scaled_jpeg { "scaled" } else { "full" });
+		metadata.set("thumbnails", if self.thumbnails { "exif" } else { "none" });
+		metadata.set("orientation", if self.exif_orientation { "exif" } else { "ignore" });
+		metadata.set("trivial", if self.skip_trivial { "skip" } else { "hash" });
+		metadata.set("preprocess", format_steps(&self.preprocess));
+
+		if let Some(invariant) = &self.invariant {
+			metadata.set("invariant", invariant.to_string());
+		}
+
+		if let Some(tiles) = self.tiles {
+			metadata.set("tiles", tiles.to_string());
+		}
+
+		if let Some(ensemble) = self.ensemble {
+			metadata.set("ensemble", ensemble.to_string());
+		}
+
+		if let Some(compat) = self.compat {
+			metadata.set("compat", value_name(compat));
+		}
+
+		if let Some(video) = &self.video {
+			metadata.set("video", video.to_string());
+		}
+
+		if let Some(page) = self.pdf_page {
+			metadata.set("pdf", page.to_string());
+		}
+
+		metadata
+	}
+
+	pub fn from_metadata(metadata: &Metadata) -> anyhow::Result<Self> {
+		let algorithms = metadata
+			.get("algorithm")
+			.unwrap_or_default()
+			.split(',')
+			.map(|algorithm| Algorithm::from_str(algorithm, false).map_err(|_| anyhow::anyhow!("Unsupported algorithm: {}", algorithm)))
+			.collect::<anyhow::Result<Vec<_>>>()?;
+
+		let video = match metadata.get("video") {
+			None | Some("none") => None,
+			Some(video) => Some(video.parse().context("Invalid video setting")?),
+		};
+
+		let invariant = match metadata.get("invariant") {
+			None | Some("none") => None,
+			Some(invariant) => Some(invariant.parse().context("Invalid invariant setting")?),
+		};
+
+		let tiles = match metadata.get("tiles") {
+			None | Some("none") => None,
+			Some(tiles) => Some(tiles.parse().context("Invalid tiles setting")?),
+		};
+
+		let ensemble = match metadata.get("ensemble") {
+			None | Some("none") => None,
+			Some(ensemble) => Some(ensemble.parse().context("Invalid ensemble setting")?),
+		};
+
+		let compat = match metadata.get("compat") {
+			None | Some("none") => None,
+			Some(_) => Some(parse_value(metadata, "compat")?),
+		};
+
+		let pdf_page = match metadata.get("pdf") {
+			None | Some("none") => None,
+			Some(page) => Some(page.parse().context("Invalid pdf setting")?),
+		};
+
+		let exif_orientation = match metadata.get("orientation") {
+			Some("exif") => true,
+			Some("ignore") => false,
+			other => anyhow::bail!("Invalid orientation setting: {}", other.unwrap_or_default()),
+		};
+
+		let scaled_jpeg = match metadata.get("jpeg") {
+			Some("scaled") => true,
+			Some("full") => false,
+			other => anyhow::bail!("Invalid jpeg setting: {}", other.unwrap_or_default()),
+		};
+
+		let thumbnails = match metadata.get("thumbnails") {
+			Some("exif") => true,
+			Some("none") => false,
+			other => anyhow::bail!("Invalid thumbnails setting: {}", other.unwrap_or_default()),
+		};
+
+		let skip_trivial = match metadata.get("trivial") {
+			Some("skip") => true,
+			Some("hash") => false,
+			other => anyhow::bail!("Invalid trivial setting: {}", other.unwrap_or_default()),
+		};
+
+		let background = metadata.get("background").unwrap_or_default().parse().context("Invalid background setting")?;
+
+		Ok(Settings {
+			algorithms,
+			exif_orientation,
+			scaled_jpeg,
+			thumbnails,
+			background,
+			frames: parse_value(metadata, "frames")?,
+			grayscale: parse_value(metadata, "grayscale")?,
+			filter: parse_value(metadata, "filter")?,
+			skip_trivial,
+			preprocess: parse_steps(metadata.get("preprocess").unwrap_or_default()).context("Invalid preprocess setting")?,
+			invariant,
+			tiles,
+			ensemble,
+			compat,
+			video,
+			pdf_page,
+		})
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Settings {
+			algorithms: vec![Algorithm::Phash],
+			exif_orientation: true,
+			scaled_jpeg: true,
+			thumbnails: false,
+			background: Background::None,
+			frames: Frames::First,
+			grayscale: Grayscale::Rec709,
+			filter: Filter::Lanczos3,
+			skip_trivial: false,
+			preprocess: Vec::new(),
+			invariant: None,
+			tiles: None,
+			ensemble: None,
+			compat: None,
+			video: None,
+			pdf_page: None,
+		}
+	}
+}
+
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SettingsArgs {
+	/// Hashes to compute for each image, from a single decode.  The first is written in the hash column and used by
+	/// every other command, the rest are written in `<algorithm>=` columns, e.g. `--algorithm phash,dhash,ahash`.
+	#[arg(long, value_enum, value_delimiter = ',', default_value = "phash")]
+	algorithm: Vec<Algorithm>,
+
+	/// Hash images as stored, ignoring their EXIF orientation.  Needed to resume output files written before
+	/// orientation was applied.
+	#[arg(long)]
+	no_exif_orientation: bool,
+
+	/// Decode JPEGs at full size, instead of scaling large ones down to 1/2, 1/4 or 1/8 as they are decoded.  Needed to
+	/// resume output files written before scaling was added.
+	#[arg(long)]
+	full_decode: bool,
+
+	/// Hash the thumbnail embedded in the EXIF metadata of images that have one, reading only the start of the file,
+	/// and fall back to the image itself for those that don't.  Much faster, especially over network storage, but
+	/// thumbnails are small and sometimes stale, so hashes are less accurate.  Entries hashed from a thumbnail get a
+	/// `thumbnail=exif` column.
+	#[arg(long, conflicts_with = "compat")]
+	use_thumbnails: bool,
+
+	/// Composite transparent images onto this background before hashing: `none` (use the stored color of transparent
+	/// pixels, as before this option existed), `checker`, `white`, `black` or a `#rrggbb` color.
+	#[arg(long, default_value_t = Background::None)]
+	background: Background,
+
+	/// Which frames of animated GIF, WebP and APNG images to hash.
+	/// Unless hashing only the first, each frame's index is recorded in a `frame=` column.
+	#[arg(long, value_enum, default_value_t = Frames::First)]
+	frames: Frames,
+
+	/// How images are converted to grayscale before hashing.
+	#[arg(long, value_enum, default_value_t = Grayscale::Rec709)]
+	grayscale: Grayscale,
+
+	/// Resampling filter used to scale images down before hashing.
+	#[arg(long, value_enum, default_value_t = Filter::Lanczos3)]
+	filter: Filter,
+
+	/// Record essentially uniform images, such as blank pages, as `trivial` instead of with a hash that would match
+	/// every other uniform image.
+	#[arg(long)]
+	skip_trivial: bool,
+
+	/// Stretch the contrast of the downscaled image to the full range before hashing.
+	#[arg(long)]
+	normalize: bool,
+
+	/// Equalize the histogram of the downscaled image before hashing.
+	#[arg(long)]
+	equalize: bool,
+
+	/// Blur the downscaled image before hashing, with a Gaussian of this standard deviation (in pixels of the 32x32
+	/// image).
+	#[arg(long, value_name = "SIGMA", num_args = 0..=1, default_missing_value = "1", value_parser = parse_sigma)]
+	blur: Option<f32>,
+
+	/// Preprocessing steps to apply, in order, instead of those of `--normalize`, `--equalize` and `--blur`, e.g.
+	/// `trim,crop:0.9,equalize,blur:1.0`.  `trim` cuts off uniform borders and `crop:FRACTION` keeps the center of the
+	/// image, before it is downscaled; `normalize`, `equalize` and `blur:SIGMA` adjust the downscaled image, as their
+	/// options do.  Full image steps always come first.  Orientation, grayscale conversion and downscaling are set by
+	/// their own options.
+	#[arg(long, value_name = "STEPS", value_delimiter = ',', conflicts_with_all = ["normalize", "equalize", "blur"])]
+	preprocess: Vec<Step>,
+
+	/// Also hash images rotated by 90, 180 and 270 degrees (`rot90`), mirrored (`flip`), or both (`rot90,flip`, all 8
+	/// combinations), so that rotated and mirrored copies are found as duplicates.
+	#[arg(long, value_enum, value_delimiter = ',')]
+	invariant: Vec<Invariance>,
+
+	/// With `--invariant`, store the smallest of the hashes of an image (`min`), or all of them (`all`), each with the
+	/// transform it was computed for in a `transform=` column.  `min` keeps one entry per image and matches exact
+	/// rotated copies, `all` is more robust for copies that were also edited.
+	#[arg(long, value_enum, default_value_t = InvariantStore::Min, requires = "invariant")]
+	invariant_store: InvariantStore,
+
+	/// Also hash each tile of an NxN grid over images, so that crops, collages and images embedded in larger ones can
+	/// be matched.  Each entry's region is recorded in a `tile=` column: `full` for the whole image, `r<row>c<col>`
+	/// for a tile.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..=16))]
+	tiles: Option<u32>,
+
+	/// Hash N copies of each image, shifted by half a hashed pixel or rotated by a degree, and store hashes whose bits
+	/// are each set as in most of the copies, so that edits too small to move an image by more than that, such as
+	/// changed pixels or slight crops made to evade matching, are unlikely to change its hash.  Hashing takes about N
+	/// times as long.  Hashes are matched as usual, but only against others computed with the same N; an odd N avoids
+	/// ties, which clear the bit.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(3..=9), conflicts_with = "invariant")]
+	ensemble: Option<u32>,
+
+	/// Reproduce the hashes of another library, to compare against databases it built: `phash-org` for the pHash C
+	/// library's `ph_dct_imagehash`, or `imagehash` for the Python package's `phash()`, also written as hex in a `hex=`
+	/// column.  Replaces the whole hashing pipeline, so it implies `--no-exif-orientation` and `--full-decode` and can't
+	/// be combined with the options that adjust it.
+	#[arg(
+		long,
+		value_enum,
+		conflicts_with_all = [
+			"algorithm",
+			"background",
+			"grayscale",
+			"filter",
+			"skip_trivial",
+			"normalize",
+			"equalize",
+			"blur",
+			"preprocess",
+			"invariant",
+			"ensemble"
+		]
+	)]
+	compat: Option<Compat>,
+
+	/// Hash a frame of each video file every this many seconds.  Each frame's timestamp is recorded in a `t=` column.
+	#[cfg(feature = "video")]
+	#[arg(long, conflicts_with = "video_scene")]
+	video_interval: Option<f64>,
+
+	/// Hash each scene change of video files, detected when ffmpeg's scene score exceeds this threshold (0-1).
+	/// Each frame's timestamp is recorded in a `t=` column.
+	#[cfg(feature = "video")]
+	#[arg(long)]
+	video_scene: Option<f64>,
+
+	/// Hash PDF documents by rendering this page (counting from 1) with pdftoppm, instead of skipping them.
+	#[cfg(feature = "pdf")]
+	#[arg(long, value_name = "PAGE", value_parser = clap::value_parser!(u32).range(1..))]
+	pdf_page: Option<u32>,
+}
+
+impl SettingsArgs {
+	pub fn settings(&self) -> Settings {
+		#[allow(unused_mut)]
+		let mut settings = Settings {
+			algorithms: self.algorithm.clone(),
+			exif_orientation: !self.no_exif_orientation && self.compat.is_none(),
+			scaled_jpeg: !self.full_decode && self.compat.is_none(),
+			thumbnails: self.use_thumbnails,
+			background: self.background,
+			frames: self.frames,
+			grayscale: self.grayscale,
+			filter: self.filter,
+			skip_trivial: self.skip_trivial,
+			preprocess: preprocess_steps(&self.preprocess, [
+				self.normalize.then_some(Step::Normalize),
+				self.equalize.then_some(Step::Equalize),
+				self.blur.map(Step::Blur),
+			]),
+			invariant: (!self.invariant.is_empty()).then_some(Invariant {
+				rotate: self.invariant.contains(&Invariance::Rot90),
+				flip: self.invariant.contains(&Invariance::Flip),
+				store: self.invariant_store,
+			}),
+			tiles: self.tiles,
+			ensemble: self.ensemble,
+			compat: self.compat,
+			..Settings::default()
+		};
+
+		#[cfg(feature = "video")]
+		{
+			settings.video = self.video_interval.map(VideoFrames::Interval).or(self.video_scene.map(VideoFrames::Scene));
+		}
+
+		#[cfg(feature = "pdf")]
+		{
+			settings.pdf_page = self.pdf_page;
+		}
+
+		settings
+	}
+}
+
+
+/// The preprocessing steps given with `--preprocess`, or else those of the options that add one, with the full image
+/// steps first.
+fn preprocess_steps(steps: &[Step], options: [Option<Step>; 3]) -> Vec<Step> {
+	let mut steps = if steps.is_empty() { options.into_iter().flatten().collect() } else { steps.to_vec() };
+	steps.sort_by_key(|step| !step.is_full_image());
+	steps
+}
+
+
+/// A perceptual hash.  All are 64 bits, computed from the same 32x32 downscaled image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+	/// The signs of the low frequencies of the image's DCT, relative to their median.
+	Phash,
+	/// The signs of the horizontal gradients of a 9x8 version.
+	Dhash,
+	/// Each cell of an 8x8 grid compared to the mean.
+	Ahash,
+}
+
+impl fmt::Display for Algorithm {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", value_name(*self))
+	}
+}
+
+
+/// Which frames of an animated image are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Frames {
+	/// Only the first frame, like a still image.
+	#[default]
+	First,
+	/// Every frame.
+	All,
+	/// A handful of frames spread evenly across the animation.
+	Representative,
+}
+
+
+/// How images are converted to grayscale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Grayscale {
+	/// ITU-R BT.601 luma (0.299 R + 0.587 G + 0.114 B), as used by many older pipelines.
+	Rec601,
+	/// ITU-R BT.709 luma (0.2126 R + 0.7152 G + 0.0722 B).
+	#[default]
+	Rec709,
+	/// The mean of the red, green and blue channels.
+	Average,
+	/// Only the red channel.
+	Red,
+	/// Only the green channel.
+	Green,
+	/// Only the blue channel.
+	Blue,
+}
+
+
+/// Resampling filter used to scale images down to the size that is hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+	/// Lanczos with a window of 3.
+	#[default]
+	Lanczos3,
+	/// Linear (bilinear).
+	Triangle,
+	/// Nearest neighbor.
+	Nearest,
+	/// Catmull-Rom cubic.
+	#[value(name = "catmullrom")]
+	CatmullRom,
+	/// Gaussian.
+	Gaussian,
+	/// Area averaging down to 128x128 followed by Lanczos3, which is faster for large images.
+	#[value(name = "box-lanczos3")]
+	BoxLanczos3,
+}
+
+
+/// The name of a setting's value, as used on the command line and in metadata.
+fn value_name<T: ValueEnum>(value: T) -> String {
+	value.to_possible_value().unwrap().get_name().to_string()
+}
+
+
+/// A standard deviation to blur with, which must be finite and positive.
+pub fn parse_sigma(s: &str) -> Result<f32, String> {
+	s.parse::<f32>().ok().filter(|sigma| sigma.is_finite() && *sigma > 0.0).ok_or(format!("expected a positive standard deviation: {}", s))
+}
+
+
+/// Parse a setting from metadata using its command line name.
+fn parse_value<T: ValueEnum>(metadata: &Metadata, key: &str) -> anyhow::Result<T> {
+	let value = metadata.get(key).unwrap_or_default();
+	T::from_str(value, false).map_err(|_| anyhow::anyhow!("Invalid {} setting: {}", key, value))
+}
+
+
+/// A kind of transform that `--invariant` hashes images under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Invariance {
+	/// Rotation by multiples of 90 degrees.
+	Rot90,
+	/// Mirroring.
+	Flip,
+}
+
+
+/// Which of the hashes computed by `--invariant` are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InvariantStore {
+	/// The smallest, which is the same for every rotation and mirror image of an image.
+	#[default]
+	Min,
+	/// All of them, one entry per transform.
+	All,
+}
+
+
+/// The transforms images are hashed under, and which of the hashes are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Invariant {
+	pub rotate: bool,
+	pub flip: bool,
+	pub store: InvariantStore,
+}
+
+impl fmt::Display for Invariant {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let kinds = [self.rotate.then_some(Invariance::Rot90), self.flip.then_some(Invariance::Flip)]
+			.into_iter()
+			.flatten()
+			.map(value_name)
+			.collect::<Vec<_>>();
+
+		write!(f, "{}:{}", kinds.join(","), value_name(self.store))
+	}
+}
+
+impl FromStr for Invariant {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (kinds, store) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("Unknown invariant: {}", s))?;
+		let kinds = kinds
+			.split(',')
+			.map(|kind| Invariance::from_str(kind, false).map_err(|_| anyhow::anyhow!("Unknown invariant: {}", kind)))
+			.collect::<anyhow::Result<Vec<_>>>()?;
+
+		Ok(Invariant {
+			rotate: kinds.contains(&Invariance::Rot90),
+			flip: kinds.contains(&Invariance::Flip),
+			store: InvariantStore::from_str(store, false).map_err(|_| anyhow::anyhow!("Unknown invariant store: {}", store))?,
+		})
+	}
+}
+
+
+/// How frames are picked from a video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFrames {
+	/// One frame every this many seconds.
+	Interval(f64),
+	/// Frames whose scene change score exceeds this threshold.
+	Scene(f64),
+}
+
+impl fmt::Display for VideoFrames {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			VideoFrames::Interval(secs) => write!(f, "interval:{}", secs),
+			VideoFrames::Scene(threshold) => write!(f, "scene:{}", threshold),
+		}
+	}
+}
+
+impl FromStr for VideoFrames {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some(("interval", secs)) => Ok(VideoFrames::Interval(secs.parse()?)),
+			Some(("scene", threshold)) => Ok(VideoFrames::Scene(threshold.parse()?)),
+			_ => anyhow::bail!("Unknown video frame selection: {}", s),
+		}
+	}
+}
@@ -0,0 +1,82 @@
+//! Re-hashes a random sample of the inputs a run finds in its output file (`--shadow-sample`), to keep checking that
+//! cached entries still reflect the files on storage where they may change without their paths changing.
+use rand::Rng;
+use rayon::prelude::*;
+use std::{collections::HashMap, path::PathBuf};
+use tracing::{info, warn};
+
+use crate::{cache::Entry, compare::hashes, hasher::Hasher};
+
+
+#[derive(clap::Args, Debug)]
+pub struct ShadowArgs {
+	/// Re-hash this fraction of the inputs found in the output file, e.g. `0.1%` or `0.001`, and warn about any whose
+	/// hashes no longer match their cached entries.  Counts are added to the run summary as `shadow_checked` and
+	/// `shadow_mismatched`.  Cached entries are left as they are; `verify` lists every mismatch.
+	#[arg(long, value_name = "RATE", value_parser = parse_rate)]
+	shadow_sample: Option<f64>,
+}
+
+
+/// A fraction from 0 to 1, or a percentage.
+fn parse_rate(s: &str) -> Result<f64, String> {
+	let rate = match s.strip_suffix('%') {
+		Some(percent) => percent.parse::<f64>().map(|percent| percent / 100.0),
+		None => s.parse::<f64>(),
+	};
+
+	rate.ok().filter(|rate| (0.0..=1.0).contains(rate)).ok_or(format!("expected a fraction from 0 to 1 or a percentage: {}", s))
+}
+
+
+/// What re-hashing the sample found.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checked {
+	pub checked: u64,
+	pub mismatched: u64,
+}
+
+
+/// The cached inputs to re-hash, chosen independently at the sample rate.
+pub fn choose<'a>(args: &ShadowArgs, cached: impl Iterator<Item = &'a PathBuf>) -> Option<Vec<PathBuf>> {
+	let rate = args.shadow_sample?;
+	let mut rng = rand::thread_rng();
+
+	Some(cached.filter(|_| rng.gen_bool(rate)).cloned().collect())
+}
+
+
+/// Re-hash the sampled inputs and compare them with their cached entries, by the hashes of every algorithm.  Inputs that can no longer be hashed, e.g.
+/// because they were deleted, aren't counted.
+pub fn check(hasher: &Hasher, paths: &[PathBuf], cache: &HashMap<PathBuf, Vec<Entry>>) -> Checked {
+	let algorithms = &hasher.settings.algorithms;
+	let columns = |entries: &[Entry]| entries.iter().map(|entry| hashes(entry, algorithms, algorithms)).collect::<Vec<_>>();
+
+	let outcomes = paths
+		.par_iter()
+		.filter_map(|path| {
+			let cached = columns(&cache[path]);
+			match hasher.hash(path) {
+				Ok(entries) => {
+					let matched = columns(&entries) == cached;
+					if !matched {
+						warn!("Warning: {} no longer matches its cached entries; re-hash it by removing it from the output file", path.display());
+					}
+					Some(matched)
+				},
+				Err(err) => {
+					warn!("Warning: couldn't re-hash {} for --shadow-sample: {}", path.display(), err);
+					None
+				},
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let checked = Checked {
+		checked: outcomes.len() as u64,
+		mismatched: outcomes.iter().filter(|matched| !**matched).count() as u64,
+	};
+	info!("Re-hashed {} cached inputs: {} no longer match", checked.checked, checked.mismatched);
+
+	checked
+}
@@ -0,0 +1,198 @@
+//! Output files split across several shard files (`--shards`), so that the hashes of very large collections can be
+//! resumed from and processed in parallel a shard at a time.  The output file itself becomes a manifest listing its
+//! shards, which commands reading output files read in its place.
+use anyhow::Context;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::{
+	collections::{BTreeMap, HashMap},
+	io::Cursor,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	cache::{read_result, Metadata, Results},
+	storage::{self, Storage},
+};
+
+
+/// Header line a manifest starts with, followed by its number of shards.
+const MANIFEST_KEY: &str = "#shards\t";
+
+
+#[derive(clap::Args, Debug)]
+pub struct ShardArgs {
+	/// Split the output file across this many shard files beside it, e.g. `hashes.00-of-16.tsv` to `hashes.15-of-16.tsv`,
+	/// and make the output file a manifest listing them.  Every command reading output files reads the shards of a
+	/// manifest, and runs resuming one only need `--output`.  Each shard is an output file of its own, so they can also
+	/// be processed separately.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..=4096))]
+	pub shards: Option<u32>,
+
+	/// Which shard each input's hashes go in: by a hash of its path, or of its directory's path, which keeps the inputs
+	/// of a directory together.
+	#[arg(long, value_enum, default_value_t = ShardBy::PathHash, requires = "shards")]
+	pub shard_by: ShardBy,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ShardBy {
+	/// A hash of the input's path.
+	PathHash,
+	/// A hash of the path of the input's directory.
+	Directory,
+}
+
+
+/// The shards of an output file, as listed in its manifest.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+	pub by: ShardBy,
+	pub paths: Vec<PathBuf>,
+}
+
+impl Manifest {
+	/// A manifest splitting an output file into `count` shards named after it.
+	pub fn new(output: &Path, count: u32, by: ShardBy) -> Self {
+		let name = output.file_name().unwrap_or_default().to_string_lossy();
+		let (stem, extension) = match name.rsplit_once('.') {
+			Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+			_ => (&*name, String::new()),
+		};
+		let width = count.to_string().len();
+
+		Manifest {
+			by,
+			paths: (0..count).map(|i| output.with_file_name(format!("{}.{:0width$}-of-{}{}", stem, i, count, extension, width = width))).collect(),
+		}
+	}
+
+	/// Parse the contents of an output file as a manifest, returning None if it isn't one.  Shards are listed by name,
+	/// relative to the manifest.
+	pub fn parse(output: &Path, contents: &[u8]) -> anyhow::Result<Option<Self>> {
+		if !contents.starts_with(MANIFEST_KEY.as_bytes()) {
+			return Ok(None);
+		}
+
+		let contents = std::str::from_utf8(contents).context("Invalid shard manifest")?;
+		let mut lines = contents.lines();
+		let count = lines.next().and_then(|line| line.strip_prefix(MANIFEST_KEY)).and_then(|count| count.parse::<usize>().ok());
+		let by = lines.next().and_then(|line| line.strip_prefix("#shard-by\t")).and_then(|by| ShardBy::from_str(by, false).ok());
+		let paths = lines.map(|name| output.with_file_name(name)).collect::<Vec<_>>();
+
+		match (count, by) {
+			(Some(count), Some(by)) if count == paths.len() => Ok(Some(Manifest { by, paths })),
+			_ => anyhow::bail!("Invalid shard manifest"),
+		}
+	}
+
+	/// The manifest as written in the output file.
+	pub fn contents(&self) -> String {
+		let by = self.by.to_possible_value().unwrap();
+		let mut manifest = format!("{}{}\n#shard-by\t{}\n", MANIFEST_KEY, self.paths.len(), by.get_name());
+		for path in &self.paths {
+			manifest += &format!("{}\n", path.file_name().unwrap_or_default().to_string_lossy());
+		}
+
+		manifest
+	}
+
+	/// The index of the shard holding an input's hashes.
+	pub fn shard_of(&self, path: &Path) -> usize {
+		let key = match self.by {
+			ShardBy::PathHash => path,
+			ShardBy::Directory => path.parent().unwrap_or(path),
+		};
+
+		(path_hash(key) % self.paths.len() as u64) as usize
+	}
+
+	/// Read every shard, as the output file they make up.  Shards that don't exist yet are empty, and the others must
+	/// have been written with the same parameters.
+	pub fn read(&self) -> anyhow::Result<Results> {
+		let shards = self
+			.paths
+			.par_iter()
+			.map(|path| {
+				let data = storage::open(path, Default::default())?.read().with_context(|| format!("Error reading shard {}", path.display()))?;
+				Ok(data.filter(|data| !data.is_empty()).map(|data| (path, read_result(&mut Cursor::new(data)))))
+			})
+			.collect::<anyhow::Result<Vec<_>>>()?;
+
+		let mut metadata: Option<(&PathBuf, Metadata)> = None;
+		let mut environment = BTreeMap::new();
+		let mut resumed = Vec::new();
+		let mut hashes = HashMap::new();
+		for (path, results) in shards.into_iter().flatten() {
+			match &metadata {
+				None => metadata = Some((path, results.metadata)),
+				Some((first, metadata)) if *metadata != results.metadata => {
+					anyhow::bail!(
+						"shard {} was written with different parameters ({}) than {} ({})",
+						path.display(),
+						results.metadata,
+						first.display(),
+						metadata
+					)
+				},
+				Some(_) => (),
+			}
+			// Shards are created by the same run
+			if environment.is_empty() {
+				environment = results.environment;
+			}
+			if resumed.len() < results.resumed.len() {
+				resumed = results.resumed;
+			}
+			hashes.extend(results.hashes);
+		}
+
+		Ok(Results {
+			metadata: metadata.map(|(_, metadata)| metadata).unwrap_or_default(),
+			environment,
+			resumed,
+			hashes,
+		})
+	}
+}
+
+
+/// A hash of a path that is the same across runs, builds and platforms: FNV-1a, with the bits of every byte mixed into
+/// all of its bits by splitmix64's finalizer, as FNV-1a's low bits only depend on the bytes' low bits.
+pub fn path_hash(path: &Path) -> u64 {
+	let hash = path.to_string_lossy().bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+	let hash = (hash ^ hash >> 30).wrapping_mul(0xbf58476d1ce4e5b9);
+	let hash = (hash ^ hash >> 27).wrapping_mul(0x94d049bb133111eb);
+	hash ^ hash >> 31
+}
+
+
+/// Find how a run's output file is sharded, given its contents, creating its manifest if it is new and `--shards` is
+/// given.  Returns None for output files that aren't sharded.  An existing output file keeps the sharding it has.
+pub fn resolve(args: &ShardArgs, output_path: &Path, output: &mut dyn Storage, contents: &[u8]) -> anyhow::Result<Option<Manifest>> {
+	if let Some(manifest) = Manifest::parse(output_path, contents)? {
+		if args.shards.is_some_and(|count| count as usize != manifest.paths.len() || args.shard_by != manifest.by) {
+			anyhow::bail!(
+				"the output file is already split into {} shards by {}; merge it into a new one to reshard it",
+				manifest.paths.len(),
+				manifest.by.to_possible_value().unwrap().get_name()
+			);
+		}
+		return Ok(Some(manifest));
+	}
+
+	let Some(count) = args.shards else {
+		return Ok(None);
+	};
+	if !contents.is_empty() {
+		anyhow::bail!("the output file isn't sharded; merge it into a new one with --shards to shard it");
+	}
+
+	let manifest = Manifest::new(output_path, count, args.shard_by);
+	output.append(manifest.contents().as_bytes())?;
+	output.flush()?;
+
+	Ok(Some(manifest))
+}
+
@@ -0,0 +1,200 @@
+//! Read-only filesystem snapshots, so that a long run hashes a consistent view of an archive that is being modified.
+//! Snapshots are created and released by running the filesystem's own tools (`zfs`, `btrfs`, or `lvcreate` and `mount`),
+//! which usually requires root.
+use anyhow::Context;
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	process::Command,
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Mutex, PoisonError,
+	},
+};
+use tracing::error;
+
+
+/// The commands releasing each live snapshot, by id.  They are kept apart from the snapshots so that `release_all` can
+/// run them before the process exits, which skips destructors.
+static RELEASE: Mutex<BTreeMap<u32, Vec<Vec<String>>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+
+/// A filesystem to snapshot, as given to `--snapshot`.
+#[derive(Clone, Debug)]
+pub enum SnapshotSpec {
+	/// `zfs:<dataset>`
+	Zfs(String),
+	/// `btrfs:<subvolume path>`
+	Btrfs(PathBuf),
+	/// `lvm:<vg>/<lv>`
+	Lvm(String),
+}
+
+impl std::str::FromStr for SnapshotSpec {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some(("zfs", dataset)) if !dataset.is_empty() => Ok(SnapshotSpec::Zfs(dataset.to_string())),
+			Some(("btrfs", subvolume)) if !subvolume.is_empty() => Ok(SnapshotSpec::Btrfs(PathBuf::from(subvolume))),
+			Some(("lvm", volume)) if volume.contains('/') => Ok(SnapshotSpec::Lvm(volume.to_string())),
+			_ => Err("expected zfs:<dataset>, btrfs:<subvolume> or lvm:<vg>/<lv>".to_string()),
+		}
+	}
+}
+
+
+/// A live snapshot.  Inputs under `mountpoint` are read from the same location under `root` instead.
+/// The snapshot is released when this is dropped.
+pub struct Snapshot {
+	pub mountpoint: PathBuf,
+	pub root: PathBuf,
+	id: u32,
+}
+
+impl Snapshot {
+	pub fn create(spec: &SnapshotSpec) -> anyhow::Result<Self> {
+		let name = format!("phash-hasher-{}", std::process::id());
+
+		match spec {
+			SnapshotSpec::Zfs(dataset) => {
+				let mountpoint = PathBuf::from(run(&["zfs", "get", "-H", "-o", "value", "mountpoint", dataset])?);
+				if !mountpoint.is_absolute() {
+					anyhow::bail!("ZFS dataset {} is not mounted (mountpoint={})", dataset, mountpoint.display());
+				}
+				let snapshot = format!("{}@{}", dataset, name);
+				run(&["zfs", "snapshot", &snapshot])?;
+
+				Ok(Snapshot::new(mountpoint.clone(), mountpoint.join(".zfs/snapshot").join(&name), args(&["zfs", "destroy", &snapshot])))
+			},
+			SnapshotSpec::Btrfs(subvolume) => {
+				let mountpoint = subvolume.canonicalize().with_context(|| format!("Error resolving {}", subvolume.display()))?;
+				let root = mountpoint.join(format!(".{}", name));
+				let root_str = root.to_string_lossy();
+				run(&["btrfs", "subvolume", "snapshot", "-r", &mountpoint.to_string_lossy(), &root_str])?;
+
+				Ok(Snapshot::new(mountpoint, root.clone(), args(&["btrfs", "subvolume", "delete", &root_str])))
+			},
+			SnapshotSpec::Lvm(volume) => {
+				let (vg, _) = volume.split_once('/').unwrap();
+				let mountpoint = PathBuf::from(run(&["findmnt", "-n", "-o", "TARGET", "--first-only", &format!("/dev/{}", volume)])?);
+				let snapshot = format!("{}/{}", vg, name);
+				run(&["lvcreate", "--snapshot", "--name", &name, "--extents", "10%ORIGIN", volume])?;
+
+				let root = std::env::temp_dir().join(&name);
+				let snapshot = Snapshot::new(mountpoint, root.clone(), args(&["lvremove", "--force", &snapshot]));
+
+				// From here on, dropping `snapshot` on error undoes whatever has been done so far
+				std::fs::create_dir(&root).with_context(|| format!("Error creating {}", root.display()))?;
+				snapshot.release_first(args(&["rmdir", &root.to_string_lossy()]));
+				// XFS refuses to mount a snapshot alongside its origin without nouuid, which other filesystems reject
+				let device = format!("/dev/{}/{}", vg, name);
+				if run(&["mount", "-o", "ro,nouuid", &device, &root.to_string_lossy()]).is_err() {
+					run(&["mount", "-o", "ro", &device, &root.to_string_lossy()])?;
+				}
+				snapshot.release_first(args(&["umount", &root.to_string_lossy()]));
+
+				Ok(snapshot)
+			},
+		}
+	}
+
+	/// A snapshot released by a command.
+	fn new(mountpoint: PathBuf, root: PathBuf, release: Vec<String>) -> Self {
+		let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+		RELEASE.lock().unwrap_or_else(PoisonError::into_inner).insert(id, vec![release]);
+
+		Snapshot { mountpoint, root, id }
+	}
+
+	/// Run a command before the others releasing the snapshot, to undo a later step of creating it.
+	fn release_first(&self, command: Vec<String>) {
+		if let Some(commands) = RELEASE.lock().unwrap_or_else(PoisonError::into_inner).get_mut(&self.id) {
+			commands.insert(0, command);
+		}
+	}
+
+	/// Where to read an input from: its location inside the snapshot, if it lies under the snapshotted filesystem.
+	pub fn resolve(&self, path: &Path) -> Option<PathBuf> {
+		path.strip_prefix(&self.mountpoint).ok().map(|relative| self.root.join(relative))
+	}
+}
+
+impl Drop for Snapshot {
+	fn drop(&mut self) {
+		let commands = RELEASE.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.id);
+		release(commands.unwrap_or_default());
+	}
+}
+
+
+/// Release every live snapshot, before exiting.
+pub fn release_all() {
+	let live = std::mem::take(&mut *RELEASE.lock().unwrap_or_else(PoisonError::into_inner));
+	for commands in live.into_values() {
+		release(commands);
+	}
+}
+
+
+fn release(commands: Vec<Vec<String>>) {
+	for command in commands {
+		if let Err(err) = run(&command.iter().map(String::as_str).collect::<Vec<_>>()) {
+			error!("Error releasing snapshot: {:#}", err);
+		}
+	}
+}
+
+
+/// Release snapshots when the process is interrupted or terminated, instead of leaving them behind, by waiting for the
+/// signals on a thread of their own.  Must be called before any other thread is started, so that every thread blocks
+/// the signals and they are only received by that one.
+#[cfg(unix)]
+pub fn release_on_signals() {
+	// SAFETY: the signal set is initialized by sigemptyset before use, and only this process's mask is changed
+	unsafe {
+		let mut signals = std::mem::zeroed::<libc::sigset_t>();
+		libc::sigemptyset(&mut signals);
+		for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+			libc::sigaddset(&mut signals, signal);
+		}
+		if libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) != 0 {
+			return;
+		}
+
+		std::thread::spawn(move || {
+			let mut signal = 0;
+			if libc::sigwait(&signals, &mut signal) == 0 {
+				error!("Interrupted, releasing snapshot");
+				release_all();
+				std::process::exit(128 + signal);
+			}
+		});
+	}
+}
+
+
+#[cfg(not(unix))]
+pub fn release_on_signals() {}
+
+
+fn args(args: &[&str]) -> Vec<String> {
+	args.iter().map(|arg| arg.to_string()).collect()
+}
+
+
+/// Run a command, returning its trimmed stdout.
+fn run(command: &[&str]) -> anyhow::Result<String> {
+	let output = Command::new(command[0])
+		.args(&command[1..])
+		.output()
+		.with_context(|| format!("Error running {}", command[0]))?;
+
+	if !output.status.success() {
+		anyhow::bail!("{} failed: {}", command.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+	}
+
+	Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
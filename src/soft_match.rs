@@ -0,0 +1,117 @@
+//! Second opinions on borderline duplicates, from their file names and EXIF capture times, so that pairs whose hashes
+//! only barely match aren't acted on without other evidence that they are the same photo.
+use rayon::prelude::*;
+use std::{collections::HashMap, path::Path};
+use tracing::{error, info};
+
+use crate::{capture::read_capture, dedupe::Pair, source::Source};
+
+
+#[derive(clap::Args, Debug)]
+pub struct SoftMatchArgs {
+	/// Treat pairs within this distance of the threshold as borderline (e.g. 1 with `--threshold 4` makes pairs at
+	/// distance 4 borderline), and keep them only if their file names are similar, such as `IMG_1234.jpg` and
+	/// `IMG_1234 (1).jpg`, or their EXIF capture times agree.
+	#[arg(long, value_name = "MARGIN")]
+	soft_match: Option<u32>,
+}
+
+
+/// The pairs to keep: those that aren't borderline, and borderline ones corroborated by their names or capture times.
+/// Capture times are only read for borderline pairs whose names aren't similar.
+pub fn filter(args: &SoftMatchArgs, pairs: Vec<Pair>, threshold: u32, source: &Source) -> Vec<Pair> {
+	let Some(margin) = args.soft_match else {
+		return pairs;
+	};
+	let borderline = |pair: &Pair| pair.distance.saturating_add(margin) > threshold;
+
+	let mut paths = pairs.iter().filter(|pair| borderline(pair) && !similar_names(&pair.a, &pair.b)).flat_map(|pair| [&pair.a, &pair.b]).collect::<Vec<_>>();
+	paths.sort_unstable();
+	paths.dedup();
+
+	let times = paths
+		.into_par_iter()
+		.map(|path| {
+			let time = match source.read(path) {
+				Ok(input) => read_capture(&input.data).time,
+				Err(err) => {
+					error!("Error reading EXIF of {}: {}", path.display(), err);
+					None
+				},
+			};
+			(path.clone(), time)
+		})
+		.collect::<HashMap<_, _>>();
+
+	let before = pairs.len();
+	let kept = pairs
+		.into_iter()
+		.filter(|pair| {
+			if !borderline(pair) || similar_names(&pair.a, &pair.b) {
+				return true;
+			}
+			matches!((&times[&pair.a], &times[&pair.b]), (Some(a), Some(b)) if a == b)
+		})
+		.collect::<Vec<_>>();
+
+	info!("Dropped {} borderline pairs whose names and capture times don't corroborate them", before - kept.len());
+	kept
+}
+
+
+/// Suffixes that file managers and cameras add to copies of a file.
+const COPY_SUFFIXES: &[&str] = &[" - copy", " copy", "_copy", "-copy", "-edited", "_edited"];
+
+
+/// Whether two file names look like the same photo's: equal once copy suffixes such as ` (1)` and ` - Copy` are
+/// removed, or nearly equal with the same digits, e.g. `IMG_1234.jpg` and `img-1234.jpeg`.  Names that differ in
+/// their digits, such as consecutive shots, aren't similar.
+fn similar_names(a: &Path, b: &Path) -> bool {
+	let (a, b) = (base_name(a), base_name(b));
+	if a == b {
+		return true;
+	}
+
+	let digits = |name: &str| name.chars().filter(char::is_ascii_digit).collect::<String>();
+	let longest = a.chars().count().max(b.chars().count());
+	digits(&a) == digits(&b) && !digits(&a).is_empty() && levenshtein(&a, &b) * 4 <= longest
+}
+
+
+/// A file's stem in lowercase, without copy suffixes.
+fn base_name(path: &Path) -> String {
+	let mut name = path.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default();
+
+	loop {
+		let trimmed = name.trim_end();
+		let stripped = COPY_SUFFIXES.iter().find_map(|suffix| trimmed.strip_suffix(suffix)).or_else(|| {
+			// A counter in parentheses, e.g. ` (1)`
+			let counter = trimmed.strip_suffix(')')?.rsplit_once('(')?;
+			counter.1.chars().all(|c| c.is_ascii_digit()).then_some(counter.0)
+		});
+
+		match stripped {
+			Some(stripped) if !stripped.trim().is_empty() => name = stripped.to_string(),
+			_ => return trimmed.to_string(),
+		}
+	}
+}
+
+
+/// The number of single character insertions, deletions and substitutions that turn one string into the other.
+fn levenshtein(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let substituted = diagonal + (ca != *cb) as usize;
+			diagonal = row[j + 1];
+			row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+		}
+	}
+
+	row[b.len()]
+}
@@ -0,0 +1,323 @@
+use anyhow::Context;
+use std::{
+	borrow::Cow,
+	fs::File,
+	io::{Read, Seek, SeekFrom},
+	path::Path,
+	sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::warn;
+
+use crate::snapshot::Snapshot;
+
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SourceArgs {
+	/// Timeout in seconds for downloading an image from a URL.
+	#[cfg(feature = "http")]
+	#[arg(long, default_value_t = 30)]
+	http_timeout: u64,
+
+	/// Maximum size in bytes of an image downloaded from a URL.  Larger downloads are aborted.
+	#[cfg(feature = "http")]
+	#[arg(long, default_value_t = 64 * 1024 * 1024)]
+	http_max_size: u64,
+
+	/// Maximum number of images downloaded at once, and so of HTTP connections open at once.  Idle connections are
+	/// kept open for reuse, up to the same number.
+	#[cfg(feature = "http")]
+	#[arg(long, default_value_t = 32)]
+	http_connections: usize,
+
+	/// Handle inputs as evidence: open them read-only without updating their access times where the OS allows it,
+	/// fail any input that changes while it is read, report errors with their full cause, and record each file's
+	/// device and inode in `dev=` and `ino=` columns.  Video files are read by ffmpeg, which may update access times.
+	#[arg(long)]
+	pub forensic: bool,
+}
+
+impl Default for SourceArgs {
+	/// The defaults of the command line options, for commands that only read local files.
+	fn default() -> Self {
+		SourceArgs {
+			#[cfg(feature = "http")]
+			http_timeout: 30,
+			#[cfg(feature = "http")]
+			http_max_size: 64 * 1024 * 1024,
+			#[cfg(feature = "http")]
+			http_connections: 32,
+			forensic: false,
+		}
+	}
+}
+
+
+/// Reads the raw bytes of input images, which are either local paths, byte ranges of local files given as
+/// `path@offset:len`, or, with the `http` feature, HTTP(S) URLs.
+pub struct Source {
+	#[cfg(feature = "http")]
+	agent: ureq::Agent,
+	#[cfg(feature = "http")]
+	max_size: u64,
+	/// Downloads that may still start, and a condition variable signalled when one finishes.
+	#[cfg(feature = "http")]
+	downloads: (std::sync::Mutex<usize>, std::sync::Condvar),
+	snapshot: Option<Snapshot>,
+	forensic: bool,
+	/// Whether the OS refused to open an input without updating its access time, so that it is only reported once.
+	atime_warned: AtomicBool,
+}
+
+
+/// The contents of an input, along with extra columns describing where they came from.
+pub struct Input {
+	pub data: Vec<u8>,
+	pub extra: Vec<String>,
+}
+
+impl Source {
+	pub fn new(args: &SourceArgs) -> Self {
+		#[cfg(not(feature = "http"))]
+		let _ = args;
+
+		Source {
+			#[cfg(feature = "http")]
+			agent: ureq::AgentBuilder::new()
+				.timeout(std::time::Duration::from_secs(args.http_timeout))
+				.max_idle_connections(args.http_connections)
+				.max_idle_connections_per_host(args.http_connections)
+				.build(),
+			#[cfg(feature = "http")]
+			max_size: args.http_max_size,
+			#[cfg(feature = "http")]
+			downloads: (std::sync::Mutex::new(args.http_connections.max(1)), std::sync::Condvar::new()),
+			snapshot: None,
+			forensic: args.forensic,
+			atime_warned: AtomicBool::new(false),
+		}
+	}
+
+	/// Read local inputs from a snapshot instead of the live filesystem, holding it until the source is dropped.
+	pub fn with_snapshot(mut self, snapshot: Option<Snapshot>) -> Self {
+		self.snapshot = snapshot;
+		self
+	}
+
+	/// The path a local input is actually read from.
+	pub fn resolve<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+		match self.snapshot.as_ref().and_then(|snapshot| snapshot.resolve(path)) {
+			Some(resolved) => Cow::Owned(resolved),
+			None => Cow::Borrowed(path),
+		}
+	}
+
+	/// The file an input is read from as a whole, or None for URLs and byte ranges, which are only parts of one.
+	pub fn whole_file<'a>(&self, path: &'a Path) -> Option<Cow<'a, Path>> {
+		if as_url(path).is_some() || self.as_range(path).is_some() {
+			return None;
+		}
+
+		Some(self.resolve(path))
+	}
+
+	/// Read the entire contents of an input.
+	pub fn read(&self, path: &Path) -> anyhow::Result<Input> {
+		if let Some(url) = as_url(path) {
+			return Ok(Input {
+				data: self.read_url(url)?,
+				extra: Vec::new(),
+			});
+		}
+
+		if let Some((file, offset, len)) = self.as_range(path) {
+			return self.read_range(file, offset, len);
+		}
+
+		let path = self.resolve(path);
+		if !self.forensic {
+			return Ok(Input {
+				data: std::fs::read(path).context("Error reading image")?,
+				extra: Vec::new(),
+			});
+		}
+
+		let mut file = self.open_forensic(&path).context("Error reading image")?;
+		let before = file.metadata().context("Error reading image")?;
+		let mut data = Vec::with_capacity(before.len() as usize);
+		file.read_to_end(&mut data).context("Error reading image")?;
+		let after = file.metadata().context("Error reading image")?;
+
+		if data.len() as u64 != before.len() || after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+			anyhow::bail!("File changed while being read ({} bytes before, {} bytes read, {} bytes after)", before.len(), data.len(), after.len());
+		}
+
+		Ok(Input {
+			data,
+			extra: provenance(&before),
+		})
+	}
+
+	/// Read at most the first `len` bytes of an input, e.g. to look at its metadata without reading all of it.
+	/// URLs and inputs read with `--forensic` are read in full.
+	pub fn read_head(&self, path: &Path, len: u64) -> anyhow::Result<Input> {
+		if self.forensic || as_url(path).is_some() {
+			return self.read(path);
+		}
+
+		if let Some((file, offset, range_len)) = self.as_range(path) {
+			return self.read_range(file, offset, range_len.min(len));
+		}
+
+		let mut data = Vec::new();
+		File::open(self.resolve(path)).and_then(|file| file.take(len).read_to_end(&mut data)).context("Error reading image")?;
+
+		Ok(Input { data, extra: Vec::new() })
+	}
+
+	/// The file, offset and length of an input that is a byte range, unless a file is actually named like one.
+	fn as_range<'a>(&self, path: &'a Path) -> Option<(&'a Path, u64, u64)> {
+		as_range(path).filter(|_| !self.resolve(path).exists())
+	}
+
+	/// Read `len` bytes of a file from `offset`, e.g. an image embedded in a container.
+	fn read_range(&self, path: &Path, offset: u64, len: u64) -> anyhow::Result<Input> {
+		let path = self.resolve(path);
+		let mut file = if self.forensic { self.open_forensic(&path) } else { File::open(&path) }.context("Error reading image")?;
+		let before = file.metadata().context("Error reading image")?;
+
+		if offset.checked_add(len).is_none_or(|end| end > before.len()) {
+			anyhow::bail!("Byte range {}:{} extends past the end of the file ({} bytes)", offset, len, before.len());
+		}
+
+		let mut data = Vec::with_capacity(len as usize);
+		file.seek(SeekFrom::Start(offset)).context("Error reading image")?;
+		file.by_ref().take(len).read_to_end(&mut data).context("Error reading image")?;
+
+		if !self.forensic {
+			return Ok(Input { data, extra: Vec::new() });
+		}
+
+		let after = file.metadata().context("Error reading image")?;
+		if data.len() as u64 != len || after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+			anyhow::bail!("File changed while being read ({} bytes before, {} bytes read, {} bytes after)", before.len(), data.len(), after.len());
+		}
+
+		Ok(Input {
+			data,
+			extra: provenance(&before),
+		})
+	}
+
+	/// Extra columns identifying a local input for `--forensic`, for inputs that are read by other programs.
+	pub fn provenance(&self, path: &Path) -> Vec<String> {
+		if !self.forensic || as_url(path).is_some() {
+			return Vec::new();
+		}
+
+		std::fs::metadata(self.resolve(path)).map(|metadata| provenance(&metadata)).unwrap_or_default()
+	}
+
+	/// Open a file read-only, without updating its access time if the OS allows it.
+	fn open_forensic(&self, path: &Path) -> std::io::Result<File> {
+		#[cfg(target_os = "linux")]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+
+			// O_NOATIME is only permitted for the file's owner (or with CAP_FOWNER)
+			match File::options().read(true).custom_flags(libc::O_NOATIME).open(path) {
+				Err(err) if err.raw_os_error() == Some(libc::EPERM) => {
+					if !self.atime_warned.swap(true, Ordering::Relaxed) {
+						warn!("Warning: not permitted to open inputs without updating their access times; they may be updated");
+					}
+				},
+				result => return result,
+			}
+		}
+
+		#[cfg(not(target_os = "linux"))]
+		if !self.atime_warned.swap(true, Ordering::Relaxed) {
+			warn!("Warning: inputs can't be opened without updating their access times on this OS; they may be updated");
+		}
+
+		File::open(path)
+	}
+
+	/// Download an input, waiting for one of the `--http-connections` downloads that may run at once to finish first.
+	#[cfg(feature = "http")]
+	fn read_url(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+		let (available, finished) = &self.downloads;
+		let mut slots = finished.wait_while(available.lock().unwrap(), |slots| *slots == 0).unwrap();
+		*slots -= 1;
+		drop(slots);
+
+		let data = self.download(url);
+
+		*available.lock().unwrap() += 1;
+		finished.notify_one();
+
+		data
+	}
+
+	#[cfg(feature = "http")]
+	fn download(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+		let response = self.agent.get(url).call().context("Error downloading image")?;
+
+		let mut data = Vec::new();
+		response
+			.into_reader()
+			.take(self.max_size + 1)
+			.read_to_end(&mut data)
+			.context("Error downloading image")?;
+
+		if data.len() as u64 > self.max_size {
+			anyhow::bail!("Download exceeds the maximum size of {} bytes", self.max_size);
+		}
+
+		Ok(data)
+	}
+
+	#[cfg(not(feature = "http"))]
+	fn read_url(&self, _url: &str) -> anyhow::Result<Vec<u8>> {
+		anyhow::bail!("URL inputs require building with the `http` feature")
+	}
+}
+
+
+/// `dev=` and `ino=` columns identifying the device and file an input was read from.
+#[cfg(unix)]
+fn provenance(metadata: &std::fs::Metadata) -> Vec<String> {
+	use std::os::unix::fs::MetadataExt;
+
+	let dev = metadata.dev();
+	vec![format!("dev={}:{}", libc::major(dev), libc::minor(dev)), format!("ino={}", metadata.ino())]
+}
+
+
+#[cfg(not(unix))]
+fn provenance(_metadata: &std::fs::Metadata) -> Vec<String> {
+	Vec::new()
+}
+
+
+/// Returns the input as a URL if it is one.
+fn as_url(path: &Path) -> Option<&str> {
+	path.to_str().filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+
+/// Splits an input of the form `path@offset:len` into its parts.  Offsets and lengths are decimal, or hexadecimal
+/// with a `0x` prefix, as carving tools print them.
+fn as_range(path: &Path) -> Option<(&Path, u64, u64)> {
+	let (file, range) = path.to_str()?.rsplit_once('@')?;
+	let (offset, len) = range.split_once(':')?;
+
+	Some((Path::new(file), parse_number(offset)?, parse_number(len)?))
+}
+
+
+fn parse_number(s: &str) -> Option<u64> {
+	match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+		Some(hex) => u64::from_str_radix(hex, 16).ok(),
+		None => s.parse().ok(),
+	}
+}
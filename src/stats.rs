@@ -0,0 +1,125 @@
+//! Reports how well the hashes of an output file spread out: how often each bit is set, how many bits of entropy they
+//! hold, how many inputs share a hash exactly, and how far apart the hashes of random pairs of inputs are.
+use rand::Rng;
+use std::{collections::HashMap, path::PathBuf};
+use tracing::{error, info};
+
+use crate::{
+	cache::read_result_file,
+	compare::hashes,
+	robustness::{self, RobustnessArgs},
+	settings::{Algorithm, Settings},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+	/// Output file to analyze.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Hashes to analyze, of those the output file holds.  Defaults to its first algorithm.
+	#[arg(long, value_enum)]
+	algorithm: Option<Algorithm>,
+
+	/// Number of random pairs of inputs to measure the distance between.
+	#[arg(long, value_name = "N", default_value_t = 100_000)]
+	pairs: usize,
+
+	#[command(flatten)]
+	robustness: RobustnessArgs,
+}
+
+
+/// Print the statistics as `name\tvalue` lines: `inputs`, `trivial` inputs, `distinct` hashes, `collisions` (hashes
+/// shared by several inputs) and `colliding-pairs` (pairs of inputs with the same hash), `entropy` in bits, then a
+/// `bit\tN\tfrequency` line for each bit and a `distance\tN\tpairs` line for each Hamming distance between random pairs,
+/// and `robustness` lines with `--robustness`.  Each input counts once, by its first entry that isn't a tile.
+pub fn run(args: StatsArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	let settings = Settings::from_metadata(&results.metadata).unwrap_or_else(|err| {
+		error!("Error: can't analyze {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+	let stored = &settings.algorithms;
+	let algorithm = args.algorithm.unwrap_or(stored[0]);
+	if !stored.contains(&algorithm) {
+		error!("Error: {} has no {} hashes", args.output.display(), algorithm);
+		std::process::exit(1);
+	}
+
+	let mut trivial = 0;
+	let mut phashes = Vec::new();
+	for entries in results.hashes.values() {
+		let entry = entries.iter().find(|entry| entry.extra.iter().all(|extra| extra.strip_prefix("tile=").is_none_or(|tile| tile == "full")));
+		match entry.and_then(|entry| hashes(entry, stored, &[algorithm])) {
+			Some(hash) => phashes.push(hash[0]),
+			None => trivial += 1,
+		}
+	}
+
+	if phashes.is_empty() {
+		error!("Error: {} has no {} hashes to analyze", args.output.display(), algorithm);
+		std::process::exit(1);
+	}
+
+	let mut counts = HashMap::<u64, u64>::new();
+	for phash in &phashes {
+		*counts.entry(*phash).or_default() += 1;
+	}
+	let collisions = counts.values().filter(|count| **count > 1).count();
+	let colliding_pairs = counts.values().map(|count| count * (count - 1) / 2).sum::<u64>();
+
+	// Each bit contributes its binary entropy; correlations between bits only lower the total, so this is an upper bound
+	let frequencies = (0..64).map(|bit| phashes.iter().filter(|phash| *phash >> bit & 1 == 1).count() as f64 / phashes.len() as f64).collect::<Vec<_>>();
+	let entropy = frequencies.iter().filter(|p| **p > 0.0 && **p < 1.0).map(|p| -p * p.log2() - (1.0 - p) * (1.0 - p).log2()).sum::<f64>();
+
+	println!("inputs\t{}", phashes.len() + trivial);
+	println!("trivial\t{}", trivial);
+	println!("distinct\t{}", counts.len());
+	println!("collisions\t{}", collisions);
+	println!("colliding-pairs\t{}", colliding_pairs);
+	println!("entropy\t{:.2}", entropy);
+	for (bit, frequency) in frequencies.iter().enumerate() {
+		println!("bit\t{}\t{:.4}", bit, frequency);
+	}
+
+	// Random pairs of different inputs, which may have the same hash
+	let mut histogram = [0u64; 65];
+	let pairs = if phashes.len() < 2 { 0 } else { args.pairs };
+	let mut rng = rand::thread_rng();
+	for _ in 0..pairs {
+		let a = rng.gen_range(0..phashes.len());
+		let b = (a + rng.gen_range(1..phashes.len())) % phashes.len();
+		histogram[(phashes[a] ^ phashes[b]).count_ones() as usize] += 1;
+	}
+	for (distance, count) in histogram.iter().enumerate() {
+		println!("distance\t{}\t{}", distance, count);
+	}
+
+	robustness::report(&args.robustness, results.hashes.keys().cloned(), &settings, algorithm);
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"stats",
+		serde_json::json!({
+			"output": args.output,
+			"algorithm": algorithm.to_string(),
+			"distinct": counts.len(),
+			"colliding_pairs": colliding_pairs,
+		}),
+	);
+
+	info!(
+		"Analyzed {} {} hashes: {} distinct, {:.1} bits of entropy, {} pairs of inputs with the same hash",
+		phashes.len(),
+		algorithm,
+		counts.len(),
+		entropy,
+		colliding_pairs
+	);
+}
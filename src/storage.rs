@@ -0,0 +1,348 @@
+//! Where output files are kept: on the local filesystem, or with the `object-store` feature in S3, Google Cloud Storage
+//! or Azure Blob Storage, so that workers without any local state can share one output file.
+use std::{
+	fs::File,
+	io::{self, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+
+/// URL schemes of output files kept in object storage.
+const OBJECT_SCHEMES: &[&str] = &["s3", "gs", "az"];
+
+
+/// An output file, which is read once and then appended to.
+pub trait Storage: Send {
+	/// The whole contents of the output file, or None if it doesn't exist yet.
+	fn read(&mut self) -> anyhow::Result<Option<Vec<u8>>>;
+
+	/// Discard everything after the first `len` bytes, such as a line left incomplete by an interrupted run.
+	fn truncate(&mut self, len: u64) -> anyhow::Result<()>;
+
+	/// Append the lines of one input.  They may be buffered until `flush`.
+	fn append(&mut self, lines: &[u8]) -> anyhow::Result<()>;
+
+	/// Write buffered lines that are due to be written, while no more are being appended.
+	fn poll(&mut self) -> anyhow::Result<()> {
+		Ok(())
+	}
+
+	/// Write any appended lines that are still buffered.
+	fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+
+/// When appended lines are written to the output file: once this many inputs' lines are buffered, or once this long
+/// has passed since the last write, whichever comes first.  With neither, a default for where the file is kept.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlushPolicy {
+	pub every: Option<usize>,
+	pub interval: Option<Duration>,
+}
+
+
+/// How often output files in object storage are written by default.  Objects can't be appended to, so every write
+/// uploads the whole object again.
+#[cfg(feature = "object-store")]
+const OBJECT_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+
+/// Open the output file at a local path or object storage URL.
+pub fn open(path: &Path, policy: FlushPolicy) -> anyhow::Result<Box<dyn Storage>> {
+	let (inner, default): (Box<dyn Storage>, _) = match path.to_str().filter(|path| is_object_url(path)) {
+		#[cfg(feature = "object-store")]
+		Some(url) => (
+			Box::new(object::ObjectStorage::open(url)?),
+			FlushPolicy {
+				every: None,
+				interval: Some(OBJECT_FLUSH_INTERVAL),
+			},
+		),
+		#[cfg(not(feature = "object-store"))]
+		Some(url) => anyhow::bail!("Output files in object storage ({}) require building with the `object-store` feature", url),
+		// Local files get each input's lines as soon as it is hashed
+		None => (
+			Box::new(LocalStorage {
+				path: path.to_path_buf(),
+				file: None,
+			}),
+			FlushPolicy { every: Some(1), interval: None },
+		),
+	};
+
+	Ok(Box::new(Buffered {
+		inner,
+		policy: if policy.every.is_none() && policy.interval.is_none() { default } else { policy },
+		pending: Vec::new(),
+		inputs: 0,
+		written_at: Instant::now(),
+	}))
+}
+
+
+/// Whether an output path is the URL of an object rather than a local path.
+fn is_object_url(path: &str) -> bool {
+	OBJECT_SCHEMES.iter().any(|scheme| path.strip_prefix(scheme).is_some_and(|rest| rest.starts_with("://")))
+}
+
+
+/// Buffers appended lines according to a `FlushPolicy`.  Only whole inputs' lines are written at once, and a write cut
+/// short by a crash leaves at most one incomplete line at the end, which the next run discards.
+struct Buffered {
+	inner: Box<dyn Storage>,
+	policy: FlushPolicy,
+	pending: Vec<u8>,
+	/// Number of inputs whose lines are pending.
+	inputs: usize,
+	written_at: Instant,
+}
+
+impl Buffered {
+	fn due(&self) -> bool {
+		let full = self.policy.every.is_some_and(|every| self.inputs >= every);
+		let stale = self.policy.interval.is_some_and(|interval| self.written_at.elapsed() >= interval);
+
+		self.inputs > 0 && (full || stale)
+	}
+
+	fn write(&mut self) -> anyhow::Result<()> {
+		if !self.pending.is_empty() {
+			#[cfg(feature = "failpoints")]
+			crate::failpoint::delay(crate::failpoint::Stage::WriterStall);
+
+			self.inner.append(&self.pending)?;
+			self.pending.clear();
+		}
+		self.inputs = 0;
+		self.written_at = Instant::now();
+
+		Ok(())
+	}
+}
+
+impl Storage for Buffered {
+	fn read(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+		self.inner.read()
+	}
+
+	fn truncate(&mut self, len: u64) -> anyhow::Result<()> {
+		self.inner.truncate(len)
+	}
+
+	fn append(&mut self, lines: &[u8]) -> anyhow::Result<()> {
+		self.pending.extend_from_slice(lines);
+		self.inputs += 1;
+
+		if self.due() {
+			self.write()?;
+		}
+
+		Ok(())
+	}
+
+	fn poll(&mut self) -> anyhow::Result<()> {
+		if self.due() {
+			self.write()?;
+		}
+
+		Ok(())
+	}
+
+	fn flush(&mut self) -> anyhow::Result<()> {
+		self.write()?;
+		self.inner.flush()
+	}
+}
+
+
+/// An output file on the local filesystem.  Lines are written as soon as they are appended.
+struct LocalStorage {
+	path: PathBuf,
+	/// Opened for writing on the first write, so that reading doesn't create the file.
+	file: Option<File>,
+}
+
+impl LocalStorage {
+	fn file(&mut self) -> io::Result<&mut File> {
+		if self.file.is_none() {
+			self.file = Some(File::options().write(true).create(true).truncate(false).open(&self.path)?);
+		}
+
+		Ok(self.file.as_mut().unwrap())
+	}
+}
+
+impl Storage for LocalStorage {
+	fn read(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+		let mut data = Vec::new();
+		match File::open(&self.path) {
+			Ok(mut file) => file.read_to_end(&mut data)?,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(err.into()),
+		};
+
+		Ok(Some(data))
+	}
+
+	fn truncate(&mut self, len: u64) -> anyhow::Result<()> {
+		Ok(self.file()?.set_len(len)?)
+	}
+
+	fn append(&mut self, lines: &[u8]) -> anyhow::Result<()> {
+		let file = self.file()?;
+		file.seek(SeekFrom::End(0))?;
+		file.write_all(lines)?;
+		Ok(file.flush()?)
+	}
+
+	fn flush(&mut self) -> anyhow::Result<()> {
+		Ok(())
+	}
+}
+
+
+#[cfg(feature = "object-store")]
+mod object {
+	use anyhow::Context;
+	use object_store::{
+		aws::AmazonS3Builder, azure::MicrosoftAzureBuilder, gcp::GoogleCloudStorageBuilder, path::Path as ObjectPath, ObjectStore, ObjectStoreExt, PutMode,
+		UpdateVersion,
+	};
+	use std::{collections::HashMap, io::Cursor, path::PathBuf};
+
+	use super::Storage;
+	use crate::cache::read_result;
+
+	/// An output file kept as an object.  Appended lines are uploaded with conditional puts, so that several workers can
+	/// append to the same object: when another worker has written it first, the object is read again and the lines
+	/// uploaded on top of it, leaving out any inputs the other worker already hashed.
+	pub struct ObjectStorage {
+		store: Box<dyn ObjectStore>,
+		path: ObjectPath,
+		runtime: tokio::runtime::Runtime,
+		/// The contents of the object as last read or written, and their version; None if it didn't exist.
+		contents: Vec<u8>,
+		version: Option<UpdateVersion>,
+		/// Appended lines that are being uploaded.
+		pending: Vec<u8>,
+	}
+
+	impl ObjectStorage {
+		/// Open the object at an `s3://bucket/key`, `gs://bucket/key` or `az://container/key` URL, with credentials
+		/// and other settings taken from the environment (`AWS_*`, `GOOGLE_*` or `AZURE_*` variables).
+		pub fn open(url: &str) -> anyhow::Result<Self> {
+			let (scheme, rest) = url.split_once("://").unwrap();
+			let key = rest.split_once('/').map(|(_, key)| key).filter(|key| !key.is_empty()).context("URL has no object key")?;
+
+			let store: Box<dyn ObjectStore> = match scheme {
+				"s3" => Box::new(AmazonS3Builder::from_env().with_url(url).build()?),
+				"gs" => Box::new(GoogleCloudStorageBuilder::from_env().with_url(url).build()?),
+				"az" => Box::new(MicrosoftAzureBuilder::from_env().with_url(url).build()?),
+				_ => unreachable!(),
+			};
+
+			Ok(ObjectStorage {
+				store,
+				path: ObjectPath::parse(key)?,
+				runtime: tokio::runtime::Builder::new_current_thread().enable_all().build()?,
+				contents: Vec::new(),
+				version: None,
+				pending: Vec::new(),
+			})
+		}
+
+		/// Upload the contents with the pending lines appended, unless the object has changed since it was read.
+		fn upload(&mut self) -> anyhow::Result<()> {
+			loop {
+				let contents = [&self.contents[..], &self.pending[..]].concat();
+				let mode = match &self.version {
+					Some(version) => PutMode::Update(version.clone()),
+					None => PutMode::Create,
+				};
+
+				match self.runtime.block_on(self.store.put_opts(&self.path, contents.clone().into(), mode.into())) {
+					Ok(result) => {
+						self.contents = contents;
+						self.version = Some(UpdateVersion {
+							e_tag: result.e_tag,
+							version: result.version,
+						});
+						self.pending.clear();
+						return Ok(());
+					},
+					Err(object_store::Error::Precondition { .. } | object_store::Error::AlreadyExists { .. }) => self.reconcile()?,
+					Err(err) => return Err(err.into()),
+				}
+			}
+		}
+
+		/// Read the object again after another worker has written it, and drop the pending lines it already has: the
+		/// header, if the object now has one, and the entries of inputs it holds.
+		fn reconcile(&mut self) -> anyhow::Result<()> {
+			let ours = read_result(&mut Cursor::new([&self.contents[..], &self.pending[..]].concat())).metadata;
+			self.read()?;
+
+			if self.contents.is_empty() {
+				return Ok(());
+			}
+
+			let theirs = read_result(&mut Cursor::new(&self.contents));
+			if theirs.metadata != ours {
+				anyhow::bail!("another worker wrote the output file with different parameters ({}) than this run ({})", theirs.metadata, ours);
+			}
+
+			self.pending = without_entries(&self.pending, &theirs.hashes);
+			Ok(())
+		}
+	}
+
+	impl Storage for ObjectStorage {
+		fn read(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+			let object = match self.runtime.block_on(self.store.get(&self.path)) {
+				Ok(object) => object,
+				Err(object_store::Error::NotFound { .. }) => {
+					self.contents.clear();
+					self.version = None;
+					return Ok(None);
+				},
+				Err(err) => return Err(err.into()),
+			};
+
+			self.version = Some(UpdateVersion {
+				e_tag: object.meta.e_tag.clone(),
+				version: object.meta.version.clone(),
+			});
+			self.contents = self.runtime.block_on(object.bytes())?.to_vec();
+
+			Ok(Some(self.contents.clone()))
+		}
+
+		fn truncate(&mut self, len: u64) -> anyhow::Result<()> {
+			self.contents.truncate(len as usize);
+			self.upload()
+		}
+
+		fn append(&mut self, lines: &[u8]) -> anyhow::Result<()> {
+			self.pending.extend_from_slice(lines);
+			self.upload()
+		}
+
+		fn flush(&mut self) -> anyhow::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Lines that are neither header lines nor entries of inputs in `hashes`.
+	fn without_entries<T>(lines: &[u8], hashes: &HashMap<PathBuf, T>) -> Vec<u8> {
+		lines
+			.split_inclusive(|byte| *byte == b'\n')
+			.filter(|line| {
+				let path = line.split(|byte| *byte == b'\t').next().unwrap();
+				!line.starts_with(b"#") && !std::str::from_utf8(path).is_ok_and(|path| hashes.contains_key(&PathBuf::from(path)))
+			})
+			.flatten()
+			.copied()
+			.collect()
+	}
+}
@@ -0,0 +1,95 @@
+//! Enumerates the media in a Google Takeout export, pairing each file with its JSON sidecar.
+use anyhow::Context;
+use serde_json::Value;
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+};
+
+
+/// Return the path of every media file in the export, along with extra columns carrying its metadata:
+/// `taken=` (the capture time as a unix timestamp, from the sidecar) and `album=` (from the folder's `metadata.json`).
+pub fn read_export(root: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+	let mut media = Vec::new();
+	let mut dirs = vec![root.to_path_buf()];
+
+	while let Some(dir) = dirs.pop() {
+		let mut files = Vec::new();
+		let mut sidecars = HashMap::new();
+
+		for entry in fs::read_dir(&dir).with_context(|| format!("Error reading directory {}", dir.display()))? {
+			let path = entry?.path();
+			let Some(name) = path.file_name().and_then(|name| name.to_str()).map(str::to_string) else {
+				continue;
+			};
+
+			if path.is_dir() {
+				dirs.push(path);
+			} else if name.ends_with(".json") {
+				if let Some(json) = fs::read(&path).ok().and_then(|data| serde_json::from_slice::<Value>(&data).ok()) {
+					sidecars.insert(name, json);
+				}
+			} else if !name.ends_with(".html") {
+				files.push((path, name));
+			}
+		}
+
+		let album = sidecars.get("metadata.json").and_then(|json| json["title"].as_str()).map(clean);
+		let titles = sidecars
+			.iter()
+			.filter_map(|(name, json)| Some((json.get("title")?.as_str()?.to_string(), name.clone())))
+			.collect::<HashMap<_, _>>();
+
+		for (path, name) in files {
+			let mut extra = Vec::new();
+
+			if let Some(taken) = find_sidecar(&name, &sidecars, &titles).and_then(|json| json["photoTakenTime"]["timestamp"].as_str()) {
+				extra.push(format!("taken={}", taken));
+			}
+
+			if let Some(album) = &album {
+				extra.push(format!("album={}", album));
+			}
+
+			media.push((path, extra));
+		}
+	}
+
+	Ok(media)
+}
+
+
+/// Find the sidecar for a media file.
+/// Takeout names sidecars `name.json` or `name.supplemental-metadata.json`, moves the duplicate counter of
+/// `name(1).jpg` to `name.jpg(1).json`, shares the original's sidecar with `-edited` copies, and truncates long names.
+/// Anything not matched by name is looked up by the original filename stored in the sidecar's `title`.
+fn find_sidecar<'a>(name: &str, sidecars: &'a HashMap<String, Value>, titles: &HashMap<String, String>) -> Option<&'a Value> {
+	let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+	let mut candidates = vec![format!("{}.supplemental-metadata.json", name), format!("{}.json", name)];
+
+	if let Some((base, counter)) = stem.strip_suffix(')').and_then(|s| s.rsplit_once('(')) {
+		if counter.chars().all(|c| c.is_ascii_digit()) {
+			candidates.push(format!("{}.{}.supplemental-metadata({}).json", base, ext, counter));
+			candidates.push(format!("{}.{}({}).json", base, ext, counter));
+		}
+	}
+
+	let original = match stem.strip_suffix("-edited") {
+		Some(base) => format!("{}.{}", base, ext),
+		None => name.to_string(),
+	};
+	candidates.push(format!("{}.supplemental-metadata.json", original));
+	candidates.push(format!("{}.json", original));
+
+	candidates
+		.iter()
+		.find_map(|candidate| sidecars.get(candidate))
+		.or_else(|| titles.get(&original).and_then(|sidecar| sidecars.get(sidecar)))
+}
+
+
+/// Album titles are free text; keep them on one column.
+fn clean(title: &str) -> String {
+	title.replace(['\t', '\n', '\r'], " ")
+}
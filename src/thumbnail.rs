@@ -0,0 +1,55 @@
+//! Extracts the thumbnails embedded in EXIF metadata, for hashing photos without decoding them in full, and renders
+//! thumbnails of inputs for people to look at.
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use std::{io::Cursor, path::Path};
+
+use crate::{
+	codecs::decode,
+	orientation::{apply_orientation, read_orientation},
+	settings::Settings,
+	source::Source,
+};
+
+
+/// How much of an input is read to look for a thumbnail.  JPEG limits the EXIF segment to 64 KiB, and it comes first.
+pub const THUMBNAIL_HEAD: u64 = 128 * 1024;
+
+
+/// The embedded JPEG thumbnail of an image, and its EXIF orientation, if the start of the image's data holds both.
+pub fn read_thumbnail(data: &[u8]) -> Option<(Vec<u8>, u32)> {
+	let exif = exif::Reader::new().read_from_container(&mut Cursor::new(data)).ok()?;
+	let field = |tag| exif.get_field(tag, exif::In::THUMBNAIL).and_then(|field| field.value.get_uint(0));
+
+	let offset = field(exif::Tag::JPEGInterchangeFormat)? as usize;
+	let len = field(exif::Tag::JPEGInterchangeFormatLength)? as usize;
+	let thumbnail = exif.buf().get(offset..offset.checked_add(len)?)?;
+
+	// Thumbnails are stored the same way up as the image itself
+	let orientation = exif
+		.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+		.and_then(|field| field.value.get_uint(0))
+		.unwrap_or(1);
+
+	Some((thumbnail.to_vec(), orientation))
+}
+
+
+/// A JPEG thumbnail of an input that fits in `size` pixels, oriented the way it was hashed, and the width and height
+/// of the input that way up.
+pub fn render(source: &Source, settings: &Settings, path: &Path, size: u32) -> anyhow::Result<(Vec<u8>, (u32, u32))> {
+	let input = source.read(path)?;
+	let decoded = decode(&input.data)?;
+	let mut img = decoded.image;
+
+	if settings.exif_orientation && !decoded.oriented {
+		img = apply_orientation(img, read_orientation(&input.data));
+	}
+	let dimensions = (img.width(), img.height());
+
+	// JPEG has no alpha channel
+	let img = DynamicImage::ImageRgb8(img.resize(size, size, FilterType::Triangle).to_rgb8());
+	let mut data = Vec::new();
+	img.write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)?;
+
+	Ok((data, dimensions))
+}
@@ -0,0 +1,138 @@
+use indicatif::ParallelProgressIterator;
+use rand::seq::IteratorRandom;
+use rayon::prelude::*;
+use std::path::PathBuf;
+use tracing::{error, info};
+
+use crate::{
+	cache::{format_phash, read_result_file, Entry},
+	codecs::UnsupportedFormat,
+	compare::hashes,
+	hasher::Hasher,
+	progress,
+	settings::{Algorithm, Settings},
+	source::{Source, SourceArgs},
+};
+
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+	/// Output file from a previous run whose entries should be verified.
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Only verify a random sample of this many entries.  Verifies every entry if not given.
+	#[arg(short, long)]
+	sample: Option<usize>,
+
+	#[command(flatten)]
+	source: SourceArgs,
+}
+
+
+enum Outcome {
+	Match,
+	Mismatch(Vec<Option<Vec<u64>>>),
+	Error(anyhow::Error),
+}
+
+
+/// Re-compute the hashes of cached entries, with every algorithm the file was hashed with, and report any mismatches.
+/// Exits with a non-zero status if any entry failed to verify.
+pub fn run(args: VerifyArgs) {
+	let results = read_result_file(&args.output).unwrap_or_else(|err| {
+		error!("Error reading {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+
+	// Re-compute with the settings the file was written with
+	let settings = Settings::from_metadata(&results.metadata).unwrap_or_else(|err| {
+		error!("Error: can't verify {}: {}", args.output.display(), err);
+		std::process::exit(1);
+	});
+	let algorithms = settings.algorithms.clone();
+	let hasher = Hasher::new(settings, Source::new(&args.source));
+	let cache = results.hashes;
+
+	let entries = match args.sample {
+		Some(n) => cache.into_iter().choose_multiple(&mut rand::thread_rng(), n),
+		None => cache.into_iter().collect::<Vec<_>>(),
+	};
+
+	let outcomes = entries
+		.par_iter()
+		.progress_with(progress::bar(entries.len() as u64))
+		.map(|(path, entries)| {
+			let cached = columns(entries, &algorithms);
+			let outcome = match hasher.hash(path) {
+				Ok(entries) => {
+					let hashes = columns(&entries, &algorithms);
+					if hashes == cached {
+						Outcome::Match
+					} else {
+						Outcome::Mismatch(hashes)
+					}
+				},
+				Err(err) => Outcome::Error(err),
+			};
+
+			(path, cached, outcome)
+		})
+		.collect::<Vec<_>>();
+
+	let mut mismatches = 0;
+	let mut errors = 0;
+
+	for (path, cached, outcome) in &outcomes {
+		match outcome {
+			Outcome::Match => (),
+			Outcome::Mismatch(hashes) if hashes.len() != cached.len() => {
+				println!("MISMATCH\t{}\t{} entries\t{} entries", path.display(), cached.len(), hashes.len());
+				mismatches += 1;
+			},
+			Outcome::Mismatch(hashes) => {
+				for (cached, hashes) in cached.iter().zip(hashes).filter(|(cached, hashes)| cached != hashes) {
+					for (index, algorithm) in algorithms.iter().enumerate() {
+						let cached = cached.as_ref().map(|cached| cached[index]);
+						let hash = hashes.as_ref().map(|hashes| hashes[index]);
+						if cached == hash {
+							continue;
+						}
+
+						let distance = match (cached, hash) {
+							(Some(cached), Some(hash)) => (cached ^ hash).count_ones().to_string(),
+							_ => "-".to_string(),
+						};
+						// Files hashed with several algorithms name the one whose hashes differ
+						let name = if algorithms.len() > 1 { format!("{}=", algorithm) } else { String::new() };
+						println!("MISMATCH\t{}\t{}{}\t{}{}\t{}", path.display(), name, format_phash(cached), name, format_phash(hash), distance);
+					}
+				}
+				mismatches += 1;
+			},
+			Outcome::Error(err) => {
+				let category = if err.is::<UnsupportedFormat>() { "UNSUPPORTED" } else { "ERROR" };
+				println!("{}\t{}\t{:#}", category, path.display(), err);
+				errors += 1;
+			},
+		}
+	}
+
+	#[cfg(feature = "audit")]
+	crate::audit::record(
+		"verify",
+		serde_json::json!({ "output": args.output, "verified": outcomes.len(), "mismatched": mismatches, "errors": errors }),
+	);
+
+	info!("Verified {} entries: {} ok, {} mismatched, {} errors", outcomes.len(), outcomes.len() - mismatches - errors, mismatches, errors);
+
+	if mismatches > 0 || errors > 0 {
+		std::process::exit(1);
+	}
+}
+
+
+/// The hashes of each entry for every algorithm, or None for trivial entries.
+fn columns(entries: &[Entry], algorithms: &[Algorithm]) -> Vec<Option<Vec<u64>>> {
+	entries.iter().map(|entry| hashes(entry, algorithms, algorithms)).collect()
+}
@@ -0,0 +1,164 @@
+//! Describes the build, for recording alongside results.
+
+
+/// Every optional cargo feature, and whether this build has it.
+const FEATURES: &[(&str, bool)] = &[
+	("jpeg", cfg!(feature = "jpeg")),
+	("png", cfg!(feature = "png")),
+	("gif", cfg!(feature = "gif")),
+	("webp", cfg!(feature = "webp")),
+	("tiff", cfg!(feature = "tiff")),
+	("bmp", cfg!(feature = "bmp")),
+	("ico", cfg!(feature = "ico")),
+	("pnm", cfg!(feature = "pnm")),
+	("tga", cfg!(feature = "tga")),
+	("qoi", cfg!(feature = "qoi")),
+	("hdr", cfg!(feature = "hdr")),
+	("exr", cfg!(feature = "exr")),
+	("dds", cfg!(feature = "dds")),
+	("ff", cfg!(feature = "ff")),
+	("avif", cfg!(feature = "avif")),
+	("heic", cfg!(feature = "heic")),
+	("heic-vendored", cfg!(feature = "heic-vendored")),
+	("jxl", cfg!(feature = "jxl")),
+	("svg", cfg!(feature = "svg")),
+	("raw", cfg!(feature = "raw")),
+	("http", cfg!(feature = "http")),
+	("photos", cfg!(feature = "photos")),
+	("takeout", cfg!(feature = "takeout")),
+	("video", cfg!(feature = "video")),
+	("pdf", cfg!(feature = "pdf")),
+	("attest", cfg!(feature = "attest")),
+	("audit", cfg!(feature = "audit")),
+	("bundle", cfg!(feature = "bundle")),
+	("object-store", cfg!(feature = "object-store")),
+	("failpoints", cfg!(feature = "failpoints")),
+	("conformance", cfg!(feature = "conformance")),
+	("lib", cfg!(feature = "lib")),
+	("ffi", cfg!(feature = "ffi")),
+];
+
+
+/// SIMD instruction sets that code may be compiled to use, which can change the results of floating point arithmetic.
+const SIMD_FEATURES: &[(&str, bool)] = &[
+	("sse2", cfg!(target_feature = "sse2")),
+	("sse4.1", cfg!(target_feature = "sse4.1")),
+	("sse4.2", cfg!(target_feature = "sse4.2")),
+	("avx", cfg!(target_feature = "avx")),
+	("avx2", cfg!(target_feature = "avx2")),
+	("fma", cfg!(target_feature = "fma")),
+	("avx512f", cfg!(target_feature = "avx512f")),
+	("neon", cfg!(target_feature = "neon")),
+	("sve", cfg!(target_feature = "sve")),
+	("simd128", cfg!(target_feature = "simd128")),
+];
+
+
+/// The cargo features this build was compiled with.
+pub fn enabled_features() -> Vec<&'static str> {
+	FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect()
+}
+
+
+/// A human readable description of this build: version, target and features.
+#[cfg(feature = "bundle")]
+pub fn describe() -> String {
+	format!(
+		"hasher {}\ntarget: {}-{}\nfeatures: {}\n",
+		env!("CARGO_PKG_VERSION"),
+		std::env::consts::ARCH,
+		std::env::consts::OS,
+		enabled_features().join(" ")
+	)
+}
+
+
+/// The build and machine this run hashes with, recorded in the `#env.` header lines of the output files it writes, so
+/// that hashes differing between machines can be traced to what differs: the version, compiler and decoding libraries
+/// of this build, the SIMD instruction sets it was compiled for and those the CPU has, and the OS.
+pub fn environment() -> Vec<(&'static str, String)> {
+	let simd = SIMD_FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect::<Vec<_>>();
+
+	[
+		("version", env!("CARGO_PKG_VERSION").to_string()),
+		("features", enabled_features().join(",")),
+		("rustc", env!("HASHER_RUSTC_VERSION").to_string()),
+		("libraries", env!("HASHER_LIBRARY_VERSIONS").to_string()),
+		("target", format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)),
+		("simd", simd.join(",")),
+		("cpu", cpu_model().unwrap_or_else(|| "unknown".to_string())),
+		("cpu-features", detected_features().join(",")),
+		("os", os_version().unwrap_or_else(|| std::env::consts::OS.to_string())),
+	]
+	.into_iter()
+	// Header values are a single line
+	.map(|(key, value)| (key, value.split_whitespace().collect::<Vec<_>>().join(" ")))
+	.collect()
+}
+
+
+/// SIMD instruction sets the CPU has, whether or not this build was compiled to use them.  Libraries that pick their
+/// code at run time, as some decoders do, use these.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detected_features() -> Vec<&'static str> {
+	[
+		("sse4.1", std::arch::is_x86_feature_detected!("sse4.1")),
+		("sse4.2", std::arch::is_x86_feature_detected!("sse4.2")),
+		("avx", std::arch::is_x86_feature_detected!("avx")),
+		("avx2", std::arch::is_x86_feature_detected!("avx2")),
+		("fma", std::arch::is_x86_feature_detected!("fma")),
+		("avx512f", std::arch::is_x86_feature_detected!("avx512f")),
+	]
+	.into_iter()
+	.filter(|(_, detected)| *detected)
+	.map(|(name, _)| name)
+	.collect()
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detected_features() -> Vec<&'static str> {
+	[("neon", std::arch::is_aarch64_feature_detected!("neon")), ("sve", std::arch::is_aarch64_feature_detected!("sve"))]
+		.into_iter()
+		.filter(|(_, detected)| *detected)
+		.map(|(name, _)| name)
+		.collect()
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn detected_features() -> Vec<&'static str> {
+	Vec::new()
+}
+
+
+fn cpu_model() -> Option<String> {
+	if cfg!(target_os = "macos") {
+		return command_output("sysctl", &["-n", "machdep.cpu.brand_string"]);
+	}
+
+	let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+	let model = cpuinfo.lines().find(|line| line.starts_with("model name") || line.starts_with("Model"))?;
+	model.split_once(':').map(|(_, model)| model.trim().to_string())
+}
+
+
+fn os_version() -> Option<String> {
+	if cfg!(target_os = "macos") {
+		return command_output("sw_vers", &["-productVersion"]).map(|version| format!("macOS {}", version));
+	}
+	if cfg!(windows) {
+		return command_output("cmd", &["/c", "ver"]);
+	}
+
+	let release = std::fs::read_to_string("/etc/os-release").ok()?;
+	let name = release.lines().find_map(|line| line.strip_prefix("PRETTY_NAME="))?.trim_matches('"');
+	match std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+		Ok(kernel) => Some(format!("{} (kernel {})", name, kernel.trim())),
+		Err(_) => Some(name.to_string()),
+	}
+}
+
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+	let output = std::process::Command::new(program).args(args).output().ok().filter(|output| output.status.success())?;
+	String::from_utf8(output.stdout).ok().map(|output| output.trim().to_string()).filter(|output| !output.is_empty())
+}
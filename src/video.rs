@@ -0,0 +1,88 @@
+//! Extracts frames from video files by running `ffmpeg`.
+//! Running the binary rather than linking libav keeps static builds simple; it must be on `PATH`.
+use std::path::Path;
+
+#[cfg(feature = "video")]
+use {
+	crate::{ppm::read_ppm, settings::VideoFrames},
+	anyhow::Context,
+	image::DynamicImage,
+	std::{
+		io::{BufRead, BufReader},
+		process::{Command, Stdio},
+		thread,
+	},
+};
+
+const VIDEO_EXTENSIONS: &[&str] = &["3gp", "avi", "flv", "m2ts", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "mts", "ogv", "ts", "webm", "wmv"];
+
+
+/// Whether the input looks like a video file, judging by its extension.
+pub fn is_video(path: &Path) -> bool {
+	path.extension()
+		.and_then(|ext| ext.to_str())
+		.is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+
+/// Decode the selected frames of a video, returning each with its timestamp in seconds.
+#[cfg(feature = "video")]
+pub fn read_frames(path: &Path, frames: VideoFrames) -> anyhow::Result<Vec<(f64, DynamicImage)>> {
+	let select = match frames {
+		VideoFrames::Interval(secs) => format!("select='isnan(prev_selected_t)+gte(t-prev_selected_t\\,{})'", secs),
+		// Always include the first frame so that videos without any scene change still get a hash
+		VideoFrames::Scene(threshold) => format!("select='eq(n\\,0)+gt(scene\\,{})'", threshold),
+	};
+
+	let mut child = Command::new("ffmpeg")
+		.args(["-nostdin", "-hide_banner", "-loglevel", "info", "-i"])
+		.arg(path)
+		.args(["-an", "-sn", "-vf"])
+		.arg(format!("{},showinfo", select))
+		.args(["-vsync", "vfr", "-pix_fmt", "rgb24", "-c:v", "ppm", "-f", "image2pipe", "-"])
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.context("Error running ffmpeg")?;
+
+	// showinfo logs the timestamp of every frame that makes it through the select filter, in output order
+	let stderr = child.stderr.take().unwrap();
+	let log_thread = thread::spawn(move || {
+		let mut timestamps = Vec::new();
+		let mut last_line = String::new();
+
+		for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+			if line.contains("Parsed_showinfo") {
+				if let Some(pts_time) = line.split_whitespace().find_map(|field| field.strip_prefix("pts_time:")) {
+					timestamps.push(pts_time.parse::<f64>().unwrap_or(f64::NAN));
+				}
+			} else {
+				last_line = line;
+			}
+		}
+
+		(timestamps, last_line)
+	});
+
+	let mut stdout = BufReader::new(child.stdout.take().unwrap());
+	let mut images = Vec::new();
+
+	while let Some(img) = read_ppm(&mut stdout).context("Error reading frames from ffmpeg")? {
+		images.push(DynamicImage::ImageRgb8(img));
+	}
+
+	let status = child.wait().context("Error running ffmpeg")?;
+	let (timestamps, last_line) = log_thread.join().unwrap();
+
+	if !status.success() {
+		anyhow::bail!("ffmpeg failed: {}", last_line);
+	}
+
+	if timestamps.len() != images.len() {
+		anyhow::bail!("ffmpeg reported {} frame timestamps for {} frames", timestamps.len(), images.len());
+	}
+
+	Ok(timestamps.into_iter().zip(images).collect())
+}
+
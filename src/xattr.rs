@@ -0,0 +1,205 @@
+//! Caches the entries of each input in an extended attribute on the file itself (`--xattr-cache`), so that they are
+//! found again by runs that don't have the output file they were first written to.  On Windows they are kept in an
+//! NTFS alternate data stream instead.
+use std::{fmt, path::Path, time::UNIX_EPOCH};
+
+use crate::cache::{format_phash, parse_phash, Entry, Metadata};
+
+
+/// Name of the extended attribute entries are stored in.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+const ATTRIBUTE: &str = "user.phash-hasher";
+
+/// Name of the alternate data stream entries are stored in on Windows.
+#[cfg(windows)]
+const STREAM: &str = "phash-hasher";
+
+
+/// The version of a file that entries were computed from: its size and modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+	len: u64,
+	secs: u64,
+	nanos: u32,
+}
+
+impl Stamp {
+	pub fn of(path: &Path) -> Option<Self> {
+		let metadata = std::fs::metadata(path).ok()?;
+		let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+		Some(Stamp {
+			len: metadata.len(),
+			secs: modified.as_secs(),
+			nanos: modified.subsec_nanos(),
+		})
+	}
+
+	#[cfg(windows)]
+	fn modified(&self) -> std::time::SystemTime {
+		UNIX_EPOCH + std::time::Duration::new(self.secs, self.nanos)
+	}
+}
+
+impl fmt::Display for Stamp {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}.{:09}", self.len, self.secs, self.nanos)
+	}
+}
+
+
+/// Digest of the parameters hashes were computed with, so that entries computed with different ones are ignored.
+/// FNV-1a, which is stable across builds.
+pub fn digest(metadata: &Metadata) -> u64 {
+	metadata.to_string().bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+
+/// The result of looking for an input's entries in its extended attribute.
+pub enum Lookup {
+	/// Entries computed with the same parameters from the file as it is now.
+	Hit(Vec<Entry>),
+	/// No usable entries; the file's current stamp, to store with the entries once computed, if it could be read.
+	Miss(Option<Stamp>),
+}
+
+
+/// Look for the entries of the file at `path` computed with parameters of the given digest.
+pub fn lookup(path: &Path, digest: u64) -> Lookup {
+	let Some(stamp) = Stamp::of(path) else {
+		return Lookup::Miss(None);
+	};
+
+	let entries = get(path).and_then(|value| String::from_utf8(value).ok()).and_then(|value| {
+		let mut lines = value.lines();
+		if lines.next()? != header(digest, stamp) {
+			return None;
+		}
+
+		lines
+			.map(|line| {
+				let mut parts = line.split('\t');
+				Some(Entry {
+					phash: parse_phash(parts.next()?)?,
+					extra: parts.map(str::to_string).collect(),
+				})
+			})
+			.collect::<Option<Vec<_>>>()
+	});
+
+	match entries {
+		Some(entries) if !entries.is_empty() => Lookup::Hit(entries),
+		_ => Lookup::Miss(Some(stamp)),
+	}
+}
+
+
+/// Store the entries of the file at `path`, computed from the version of it identified by `stamp`.
+/// Best effort: files that can't have extended attributes, such as those on read-only or FAT filesystems, or too many
+/// entries for the filesystem's size limit, are silently left without.
+pub fn store(path: &Path, digest: u64, stamp: Stamp, entries: &[Entry]) {
+	if entries.iter().any(|entry| entry.extra.iter().any(|extra| extra.contains('\t') || extra.contains('\n'))) {
+		return;
+	}
+
+	let mut value = header(digest, stamp);
+	for entry in entries {
+		value.push('\n');
+		value.push_str(&format_phash(entry.phash));
+		for extra in &entry.extra {
+			value.push('\t');
+			value.push_str(extra);
+		}
+	}
+
+	set(path, value.as_bytes(), stamp);
+}
+
+
+/// First line of the attribute, identifying what its entries were computed from.
+fn header(digest: u64, stamp: Stamp) -> String {
+	format!("{:016x}\t{}", digest, stamp)
+}
+
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn get(path: &Path) -> Option<Vec<u8>> {
+	use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+	let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+	let name = CString::new(ATTRIBUTE).unwrap();
+
+	// SAFETY: the strings are nul-terminated and the buffer is as long as the size passed with it
+	let getxattr = |value: &mut [u8]| unsafe {
+		#[cfg(target_os = "macos")]
+		return libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len(), 0, 0);
+		#[cfg(not(target_os = "macos"))]
+		return libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len());
+	};
+
+	// An empty buffer asks for the size of the value
+	let len = usize::try_from(getxattr(&mut [])).ok()?;
+	let mut value = vec![0; len];
+	let len = usize::try_from(getxattr(&mut value)).ok()?;
+	value.truncate(len);
+
+	Some(value)
+}
+
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn set(path: &Path, value: &[u8], _stamp: Stamp) {
+	use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+	let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+		return;
+	};
+	let name = CString::new(ATTRIBUTE).unwrap();
+
+	// SAFETY: the strings are nul-terminated and the value is as long as the size passed with it
+	unsafe {
+		#[cfg(target_os = "macos")]
+		libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0, 0);
+		#[cfg(not(target_os = "macos"))]
+		libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0);
+	}
+}
+
+
+/// The alternate data stream of a file.
+#[cfg(windows)]
+fn stream(path: &Path) -> std::ffi::OsString {
+	let mut stream = path.as_os_str().to_owned();
+	stream.push(":");
+	stream.push(STREAM);
+	stream
+}
+
+
+#[cfg(windows)]
+fn get(path: &Path) -> Option<Vec<u8>> {
+	std::fs::read(stream(path)).ok()
+}
+
+
+#[cfg(windows)]
+fn set(path: &Path, value: &[u8], stamp: Stamp) {
+	if std::fs::write(stream(path), value).is_err() {
+		return;
+	}
+
+	// Writing a stream updates the file's modification time, which has to stay the one stored
+	if let Ok(file) = std::fs::File::options().write(true).open(path) {
+		let _ = file.set_modified(stamp.modified());
+	}
+}
+
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", windows)))]
+fn get(_path: &Path) -> Option<Vec<u8>> {
+	None
+}
+
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", windows)))]
+fn set(_path: &Path, _value: &[u8], _stamp: Stamp) {}
@@ -0,0 +1,33 @@
+//! JavaScript bindings to the hashing core, so that a web page can compute the same hash of an image as the hasher CLI,
+//! e.g. to check an upload against an output file before sending it.  Images are hashed with the CLI's default
+//! parameters.
+use wasm_bindgen::prelude::*;
+
+
+fn error(err: anyhow::Error) -> JsError {
+	JsError::new(&format!("{:#}", err))
+}
+
+
+/// The perceptual hash of an image decoded into RGBA pixels, such as the `data` of the `ImageData` from a canvas, as a
+/// BigInt.  Browsers decode images differently from the CLI, so only pixels decoded the same way, e.g. from a lossless
+/// image, hash the same; `hashBytes` hashes the encoded image itself.
+#[wasm_bindgen(js_name = hashRgba)]
+pub fn hash_rgba(pixels: Vec<u8>, width: u32, height: u32) -> Result<u64, JsError> {
+	phash::hash_rgba(pixels, width, height).map_err(error)
+}
+
+
+/// The perceptual hash of an encoded JPEG or PNG image, such as the contents of a `File`, as a BigInt.  Hashes
+/// match the CLI's for the same file.
+#[wasm_bindgen(js_name = hashBytes)]
+pub fn hash_bytes(data: &[u8]) -> Result<u64, JsError> {
+	phash::hash_bytes(data).map_err(error)
+}
+
+
+/// The Hamming distance between two hashes: the number of bits in which they differ, from 0 to 64.
+#[wasm_bindgen]
+pub fn distance(a: u64, b: u64) -> u32 {
+	phash::distance(a, b)
+}